@@ -1,38 +1,62 @@
-use axum::{extract::{State}, http::HeaderMap, Json as AxumJson};
+use axum::{extract::{Path, State}, http::HeaderMap, Json as AxumJson};
 use serde_json::{json, Value};
 use std::sync::Arc;
 use tracing::info;
 
-use crate::ledger::Ledger;
+use crate::ledger::{self, Ledger, Origin};
 use crate::types::*;
-use crate::engine::{Kernel, ExecMeta, KeyMaterial};
+use crate::engine::{Kernel, ExecMeta, ExecOptions, FuelMeter};
+use crate::bundle::ProofBundle;
+use crate::envelope::{self, EnvelopeContent};
+use crate::canon;
+use crate::identity::{self, Action, Caller};
 use crate::interp;
+use crate::policy;
 use crate::trust_barrier;
 use crate::error::UblError;
 use uuid::Uuid;
 
-fn require_auth(headers: &HeaderMap) -> Result<(), UblError> {
-    if let Ok(expected) = std::env::var("UBL_API_KEY") {
-        let got = headers.get("x-ubl-key").and_then(|h| h.to_str().ok()).unwrap_or("");
-        if got != expected { return Err(UblError::Unauthorized); }
-    }
-    Ok(())
-}
-
 pub async fn execute(
     State(ledger): State<Arc<Ledger>>,
     headers: HeaderMap,
     AxumJson(req): AxumJson<ExecReq>,
 ) -> Result<AxumJson<Value>, UblError> {
-    require_auth(&headers)?;
-
-    let keys = KeyMaterial::from_env();
+    let keys = ledger.key_material();
     let meta = ExecMeta { tx_id: Uuid::new_v4().to_string(), execution_time: chrono::Utc::now() };
+    if req.envelope.is_some() && keys.signing.is_none() {
+        return Err(UblError::Validation("envelope_requires_signing_key".into()));
+    }
+
+    // A client-signed request authenticates its signer; otherwise the request token.
+    let caller = match &req.client_signature {
+        Some(auth) => Caller::from(&ledger.verify_client_request(&req, auth, &meta)?),
+        None => identity::authenticate(&headers, &ledger)?,
+    };
+    caller.require(Action::Execute)?;
 
     // Program
     let mut prog = ledger.get_program(&req.program)
         .ok_or_else(|| UblError::ProgramNotFound(req.program.clone()))?;
     prog.hash = Kernel::compute_program_hash(&prog);
+    // programs stored before these names were reserved would shadow the kernel's bindings
+    ledger::check_context_names(&prog)?;
+
+    // Inputs declared as envelopes must come sealed from the barrier
+    let envelopes = trust_barrier::check_program_inputs(&prog, &req.inputs, &keys)?;
+
+    // Canonical (NFC) inputs: what chips see and what input_hash covers
+    let inputs = canon::canonicalize(&req.inputs)?;
+    let input_hash = Kernel::input_hash(&inputs)?;
+
+    // Approvals are bound to (program, inputs, target version)
+    let operation = match (req.approvals.is_empty(), req.target_version) {
+        (true, _) => None,
+        (false, None) => return Err(UblError::Validation("approvals_require_target_version".into())),
+        (false, Some(v)) => Some(Operation { program_hash: prog.hash.clone(), input_hash: input_hash.clone(), target_version: v }),
+    };
+
+    // One fuel budget for computed context, chip and effects
+    let fuel = FuelMeter::new(Kernel::fuel_limit(prog.fuel));
 
     // Ledger snapshot root
     let ledger_root = ledger.snapshot_root();
@@ -40,13 +64,24 @@ pub async fn execute(
     // Context binding
     // NOTE: we always include the full input object under `context.input`.
     // This lets program packs use either {field} or {input.field} templates.
+    // The reserved keys are bound by the kernel alone, so program context cannot
+    // supply e.g. the operation hash approvals are checked against.
     let mut ctx = serde_json::Map::new();
-    ctx.insert("input".into(), req.inputs.clone());
+    ctx.insert("input".into(), inputs.clone());
+    ctx.insert("caller".into(), json!({ "id": caller.id }));
+    let op_val = operation.as_ref().map(|op| {
+        let mut v = json!(op);
+        v["hash"] = json!(Kernel::operation_hash(op));
+        v
+    });
+    ctx.insert("operation".into(), op_val.unwrap_or(Value::Null));
+    ctx.insert("approvals".into(), json!(req.approvals));
+    ctx.insert("signer".into(), req.client_signature.as_ref().map_or(Value::Null, |auth| json!({ "id": auth.signer_id })));
     for c in &prog.context {
         match c.source {
             ContextSource::Input => {
                 let p: Vec<String> = c.path.split('.').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
-                if let Some(v) = Kernel::resolve_path(&req.inputs, &p) { ctx.insert(c.name.clone(), v); }
+                if let Some(v) = Kernel::resolve_path(&inputs, &p) { ctx.insert(c.name.clone(), v); }
             }
             ContextSource::Ledger => {
                 // Interpolate using the already-bound context (ordered binding semantics).
@@ -55,10 +90,21 @@ pub async fn execute(
                 let p: Vec<String> = resolved.split('.').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
                 if let Some(v) = Kernel::resolve_path(&ledger_root, &p) { ctx.insert(c.name.clone(), v); }
             }
+            ContextSource::Trajectory | ContextSource::Fingerprint => {
+                // Principal from the interpolated path (e.g. "{to_id}"); defaults to the caller.
+                let principal = if c.path.is_empty() { caller.id.clone() } else {
+                    interp::interpolate_str(&c.path, &Value::Object(ctx.clone()), None, &meta)
+                };
+                let v = match c.source {
+                    ContextSource::Trajectory => json!(ledger.trajectory(&principal, &meta)),
+                    _ => json!(ledger.fingerprint(&principal)),
+                };
+                ctx.insert(c.name.clone(), v);
+            }
             ContextSource::Computed => {
                 if let Some(expr) = &c.expression {
                     let ctx_val = Value::Object(ctx.clone());
-                    let v = Kernel::eval_expr(expr, &ctx_val, &meta);
+                    let v = Kernel::eval_expr_metered(expr, &ctx_val, &meta, &fuel, ledger.functions());
                     ctx.insert(c.name.clone(), v);
                 }
             }
@@ -67,36 +113,53 @@ pub async fn execute(
     let context = Value::Object(ctx);
 
     // Chip (by hash or by `CHIP:<name>` reference)
-    let mut chip = if prog.evaluate.starts_with("CHIP:") {
-        let name = prog.evaluate.trim_start_matches("CHIP:");
-        ledger.get_chip_by_name(name)
-            .ok_or_else(|| UblError::ChipNotFound(prog.evaluate.clone()))?
-    } else {
-        ledger.get_chip(&prog.evaluate)
-            .ok_or_else(|| UblError::ChipNotFound(prog.evaluate.clone()))?
-    };
+    let mut chip = ledger.resolve_chip(&prog.evaluate)
+        .ok_or_else(|| UblError::ChipNotFound(prog.evaluate.clone()))?;
     chip.hash = Kernel::compute_chip_hash(&chip);
+    caller.require_execute(&req.program, &chip)?;
 
-    // Proof
-    let proof = Kernel::execute_chip_signed(&chip, &context, &meta, &keys);
+    // Proof (the chip gets what is left of the program's fuel, at most its own limit)
+    let fuel_limit = Kernel::fuel_limit(chip.fuel).min(fuel.remaining());
+    let opts = ExecOptions { trace: req.trace, fuel_limit: Some(fuel_limit), chips: &*ledger, functions: ledger.functions(), ..Default::default() };
+    let mut proof = Kernel::execute_chip(&chip, &context, &meta, &opts);
+    fuel.charge(proof.fuel.map_or(0, |f| f.used));
+    proof.envelopes = envelopes;
+    Kernel::seal_proof(&mut proof, &keys);
 
     let allowed = proof.final_result == 1;
     let effects = if allowed { &prog.on_allow } else { &prog.on_deny };
 
-    let input_hash = Kernel::jcs_hash(&req.inputs);
+    let counterparty = prog.counterparty.as_deref()
+        .and_then(|p| Kernel::resolve_path(&inputs, &p.split('.').map(|s| s.to_string()).collect::<Vec<_>>()))
+        .map(|v| v.as_str().map(|s| s.to_string()).unwrap_or_else(|| v.to_string()));
+    let amount = prog.amount.as_deref()
+        .and_then(|p| Kernel::resolve_path(&inputs, &p.split('.').map(|s| s.to_string()).collect::<Vec<_>>()))
+        .and_then(|v| v.as_f64());
+    let origin = Origin {
+        client_signature: req.client_signature.clone(),
+        activity: Some(Activity { principal: caller.id.clone(), program: prog.name.clone(), allowed, counterparty, amount }),
+    };
 
     let record = ledger.apply_transaction(
-        &prog.hash, &input_hash, req.target_version, &proof, effects, &meta, &keys
+        &prog.hash, &input_hash, req.target_version, &proof, effects, &meta, &keys, origin, &fuel
     ).await?;
 
     info!("tx={} allowed={} version={}", meta.tx_id, allowed, record.resulting_version);
 
-    Ok(AxumJson(json!({
+    let mut out = json!({
         "tx_id": meta.tx_id,
         "allowed": allowed,
         "proof": proof,
-        "effect_record": record
-    })))
+        "effect_record": record,
+        "fuel": fuel.usage()
+    });
+    if let Some(format) = req.envelope {
+        out["envelopes"] = json!({
+            "proof": envelope::wrap(&proof, format, &keys),
+            "effect_record": envelope::wrap(&record, format, &keys),
+        });
+    }
+    Ok(AxumJson(out))
 }
 
 pub async fn register(
@@ -104,7 +167,9 @@ pub async fn register(
     headers: HeaderMap,
     AxumJson(req): AxumJson<RegisterReq>,
 ) -> Result<AxumJson<Value>, UblError> {
-    require_auth(&headers)?;
+    // chips and programs need `register`; trust configuration needs `admin`
+    let action = match req { RegisterReq::Chip { .. } | RegisterReq::Program { .. } | RegisterReq::Policy { .. } => Action::Register, _ => Action::Admin };
+    identity::authorize(&headers, &ledger, action)?;
     match req {
         RegisterReq::Chip { data } => {
             let hash = ledger.register_chip(data)?;
@@ -116,6 +181,29 @@ pub async fn register(
             ledger.commit().await?;
             Ok(AxumJson(json!({ "hash": hash, "status": "registered" })))
         }
+        RegisterReq::Source { data } => {
+            let id = ledger.register_source(data)?;
+            ledger.commit().await?;
+            Ok(AxumJson(json!({ "id": id, "status": "registered" })))
+        }
+        RegisterReq::Principal { data } => {
+            let id = ledger.register_principal(data)?;
+            ledger.commit().await?;
+            Ok(AxumJson(json!({ "id": id, "status": "registered" })))
+        }
+        RegisterReq::Policy { source } => {
+            // parse everything first so a syntax error registers nothing; an invalid
+            // chip or program registers nothing either
+            let doc = policy::parse(&source)?;
+            let (chips, programs) = ledger.register_policy(doc.chips, doc.programs)?;
+            ledger.commit().await?;
+            Ok(AxumJson(json!({ "chips": chips, "programs": programs, "status": "registered" })))
+        }
+        RegisterReq::BarrierPolicy { data } => {
+            ledger.set_barrier_policy(data);
+            ledger.commit().await?;
+            Ok(AxumJson(json!({ "status": "registered" })))
+        }
     }
 }
 
@@ -123,7 +211,7 @@ pub async fn list_chips(
     State(ledger): State<Arc<Ledger>>,
     headers: HeaderMap,
 ) -> Result<AxumJson<Value>, UblError> {
-    require_auth(&headers)?;
+    identity::authorize(&headers, &ledger, Action::Verify)?;
     let xs = ledger.list_chips();
     Ok(AxumJson(json!({
         "chips": xs.iter().map(|(h,n,d)| json!({"hash":h,"name":n,"description":d})).collect::<Vec<_>>()
@@ -134,38 +222,137 @@ pub async fn list_programs(
     State(ledger): State<Arc<Ledger>>,
     headers: HeaderMap,
 ) -> Result<AxumJson<Value>, UblError> {
-    require_auth(&headers)?;
+    identity::authorize(&headers, &ledger, Action::Verify)?;
     let xs = ledger.list_programs();
     Ok(AxumJson(json!({
         "programs": xs.iter().map(|(n,h)| json!({"name":n,"hash":h})).collect::<Vec<_>>()
     })))
 }
 
+/// A registered chip rendered in the policy language.
+pub async fn chip_source(
+    State(ledger): State<Arc<Ledger>>,
+    headers: HeaderMap,
+    Path(hash): Path<String>,
+) -> Result<AxumJson<Value>, UblError> {
+    identity::authorize(&headers, &ledger, Action::Verify)?;
+    let chip = ledger.get_chip(&hash).ok_or(UblError::ChipNotFound(hash))?;
+    Ok(AxumJson(json!({ "hash": chip.hash, "source": policy::print_chip(&chip) })))
+}
+
+pub async fn program_source(
+    State(ledger): State<Arc<Ledger>>,
+    headers: HeaderMap,
+    Path(name): Path<String>,
+) -> Result<AxumJson<Value>, UblError> {
+    identity::authorize(&headers, &ledger, Action::Verify)?;
+    let program = ledger.get_program(&name).ok_or(UblError::ProgramNotFound(name))?;
+    Ok(AxumJson(json!({ "hash": Kernel::compute_program_hash(&program), "source": policy::print_program(&program) })))
+}
+
 pub async fn verify(
     State(ledger): State<Arc<Ledger>>,
     headers: HeaderMap,
     AxumJson(req): AxumJson<VerifyReq>,
 ) -> Result<AxumJson<Value>, UblError> {
-    require_auth(&headers)?;
-    let keys = KeyMaterial::from_env();
+    identity::authorize(&headers, &ledger, Action::Verify)?;
+    let keys = ledger.key_material();
+    let server = SignaturePolicy::from_env();
+    let policy = req.signature_policy.map_or(server, |p| p.stricter(server));
+
+    // Either a bare proof or a JWS / COSE_Sign1 envelope (signature checked first).
+    let proof = match (req.proof, &req.envelope) {
+        (Some(p), None) => p,
+        (None, Some(env)) => match Kernel::verify_envelope(env, &keys)? {
+            EnvelopeContent::Proof(p) => p,
+            EnvelopeContent::Record(r) => {
+                let intact = Kernel::verify_record(&r, &keys);
+                let signed_ok = policy != SignaturePolicy::Required || r.record_signature.is_some();
+                return Ok(AxumJson(json!({
+                    "valid": intact && signed_ok,
+                    "envelope": CheckStatus::Passed,
+                    "record_hash": r.record_hash,
+                    "record": CheckStatus::from(intact && signed_ok),
+                })));
+            }
+        },
+        _ => return Err(UblError::Validation("verify_requires_proof_or_envelope".into())),
+    };
 
-    let mut chip = ledger.get_chip(&req.proof.chip_hash)
-        .ok_or_else(|| UblError::ChipNotFound(req.proof.chip_hash.clone()))?;
+    let mut chip = ledger.get_chip(&proof.chip_hash)
+        .ok_or_else(|| UblError::ChipNotFound(proof.chip_hash.clone()))?;
     chip.hash = Kernel::compute_chip_hash(&chip);
 
-    let ok = Kernel::verify_proof(&req.proof, &chip, &keys);
-    Ok(AxumJson(json!({"valid": ok})))
+    let opts = ExecOptions { chips: &*ledger, functions: ledger.functions(), signature_policy: policy, ..Default::default() };
+    let report = Kernel::verify_proof(&proof, &chip, &keys, &opts);
+    let mut out = json!(report);
+    if req.envelope.is_some() { out["envelope"] = json!(CheckStatus::Passed); }
+    Ok(AxumJson(out))
+}
+
+pub async fn export_bundle(
+    State(ledger): State<Arc<Ledger>>,
+    headers: HeaderMap,
+    AxumJson(req): AxumJson<BundleReq>,
+) -> Result<AxumJson<Value>, UblError> {
+    identity::authorize(&headers, &ledger, Action::Verify)?;
+    let bundle = ledger.export_bundle(&req.proof, req.include_chain, req.checkpoint_version)?;
+    Ok(AxumJson(json!(bundle)))
+}
+
+pub async fn verify_bundle(
+    State(ledger): State<Arc<Ledger>>,
+    headers: HeaderMap,
+    AxumJson(bundle): AxumJson<ProofBundle>,
+) -> Result<AxumJson<Value>, UblError> {
+    identity::authorize(&headers, &ledger, Action::Verify)?;
+    Ok(AxumJson(json!(bundle.verify(SignaturePolicy::from_env(), &ledger.key_material(), ledger.functions()))))
 }
 
 pub async fn barrier_process(
+    State(ledger): State<Arc<Ledger>>,
     headers: HeaderMap,
     AxumJson(req): AxumJson<BarrierReq>,
 ) -> Result<AxumJson<Value>, UblError> {
-    require_auth(&headers)?;
-    let vd = trust_barrier::process(&req)?;
+    identity::authorize(&headers, &ledger, Action::Execute)?;
+    let keys = ledger.key_material();
+    let mut vd = trust_barrier::process(&req, &ledger.barrier_registry())?;
+    trust_barrier::seal(&mut vd, &keys)?;
     Ok(AxumJson(json!({"validated": vd})))
 }
 
+pub async fn list_keys(
+    State(ledger): State<Arc<Ledger>>,
+    headers: HeaderMap,
+) -> Result<AxumJson<Value>, UblError> {
+    identity::authorize(&headers, &ledger, Action::Verify)?;
+    let keys = ledger.key_material();
+    Ok(AxumJson(json!({ "active_key_id": keys.key_id, "keyring": ledger.keyring() })))
+}
+
+pub async fn rotate_key(
+    State(ledger): State<Arc<Ledger>>,
+    headers: HeaderMap,
+) -> Result<AxumJson<Value>, UblError> {
+    identity::authorize(&headers, &ledger, Action::Admin)?;
+    let meta = ExecMeta { tx_id: Uuid::new_v4().to_string(), execution_time: chrono::Utc::now() };
+    let record = ledger.rotate_key(&meta).await?;
+    info!("key rotated tx={} version={}", meta.tx_id, record.resulting_version);
+    Ok(AxumJson(json!({ "active_key_id": ledger.key_material().key_id, "effect_record": record })))
+}
+
+pub async fn revoke_key(
+    State(ledger): State<Arc<Ledger>>,
+    headers: HeaderMap,
+    AxumJson(req): AxumJson<RevokeKeyReq>,
+) -> Result<AxumJson<Value>, UblError> {
+    identity::authorize(&headers, &ledger, Action::Admin)?;
+    let meta = ExecMeta { tx_id: Uuid::new_v4().to_string(), execution_time: chrono::Utc::now() };
+    let record = ledger.revoke_key(&req.key_id, &req.reason, &meta).await?;
+    info!("key revoked key_id={} tx={}", req.key_id, meta.tx_id);
+    Ok(AxumJson(json!({ "effect_record": record })))
+}
+
 pub async fn health() -> AxumJson<Value> {
     AxumJson(json!({ "ok": true }))
 }
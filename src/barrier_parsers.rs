@@ -0,0 +1,143 @@
+//! Parsers for non-JSON barrier inputs.
+//!
+//! Each parser turns raw bytes into a flat `Dict[str, primitive]` (strings, or
+//! arrays of strings for list-valued headers). They only split structure; they
+//! never decode, evaluate or otherwise interpret content.
+
+use crate::error::UblError;
+use crate::types::RawFormat;
+use serde_json::{json, Map, Value};
+
+pub const MAX_RAW_BYTES: usize = 1024 * 1024;
+
+pub fn parse(format: RawFormat, bytes: &[u8]) -> Result<Map<String, Value>, UblError> {
+    if bytes.len() > MAX_RAW_BYTES {
+        return Err(UblError::Validation(format!("raw_too_large: {} bytes", bytes.len())));
+    }
+    let text = std::str::from_utf8(bytes).map_err(|_| UblError::Validation("raw_not_utf8".into()))?;
+    match format {
+        RawFormat::Csv => parse_csv(text),
+        RawFormat::Email => parse_email(text),
+        RawFormat::Form => parse_form(bytes),
+    }
+}
+
+// --------------------------
+// CSV (RFC 4180): header row + exactly one record
+// --------------------------
+fn parse_csv(text: &str) -> Result<Map<String, Value>, UblError> {
+    let rows = csv_rows(text)?;
+    let mut rows = rows.into_iter().filter(|r| !(r.len() == 1 && r[0].is_empty()));
+    let header = rows.next().ok_or_else(|| UblError::Validation("csv_missing_header".into()))?;
+    let record = rows.next().ok_or_else(|| UblError::Validation("csv_missing_record".into()))?;
+    if rows.next().is_some() {
+        return Err(UblError::Validation("csv_multiple_records".into()));
+    }
+    if header.len() != record.len() {
+        return Err(UblError::Validation(format!("csv_column_mismatch: header={} record={}", header.len(), record.len())));
+    }
+
+    let mut out = Map::new();
+    for (k, v) in header.into_iter().zip(record) {
+        let k = k.trim().to_string();
+        if k.is_empty() { return Err(UblError::Validation("csv_empty_column_name".into())); }
+        if out.insert(k.clone(), json!(v)).is_some() {
+            return Err(UblError::Validation(format!("csv_duplicate_column: {}", k)));
+        }
+    }
+    Ok(out)
+}
+
+fn csv_rows(text: &str) -> Result<Vec<Vec<String>>, UblError> {
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => { chars.next(); field.push('"'); }
+                '"' => in_quotes = false,
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => { row.push(std::mem::take(&mut field)); rows.push(std::mem::take(&mut row)); }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes { return Err(UblError::Validation("csv_unterminated_quote".into())); }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+// --------------------------
+// Email (RFC 5322): unfolded headers + body, no MIME decoding
+// --------------------------
+/// Headers RFC 5322 §3.6 allows at most once; a second copy is a spoofing vector.
+const SINGLE_HEADERS: &[&str] = &["from", "sender", "reply-to", "to", "cc", "bcc", "date", "message-id", "in-reply-to", "references", "subject"];
+
+fn parse_email(text: &str) -> Result<Map<String, Value>, UblError> {
+    let text = text.replace("\r\n", "\n");
+    let (head, body) = match text.find("\n\n") {
+        Some(i) => (&text[..i], &text[i + 2..]),
+        None => (text.as_str(), ""),
+    };
+
+    // Unfold continuation lines (leading WSP) into the previous header.
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in head.lines() {
+        if line.starts_with(' ') || line.starts_with('\t') {
+            let (_, v) = headers.last_mut().ok_or_else(|| UblError::Validation("email_malformed_header".into()))?;
+            v.push(' ');
+            v.push_str(line.trim());
+            continue;
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| UblError::Validation("email_malformed_header".into()))?;
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(UblError::Validation("email_malformed_header".into()));
+        }
+        let name = name.to_ascii_lowercase();
+        if SINGLE_HEADERS.contains(&name.as_str()) && headers.iter().any(|(k, _)| k == &name) {
+            return Err(UblError::Validation(format!("email_duplicate_header: {}", name)));
+        }
+        headers.push((name, value.trim().to_string()));
+    }
+
+    let header = |name: &str| headers.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone());
+
+    let mut out = Map::new();
+    for (field, name) in [("from", "from"), ("to", "to"), ("subject", "subject"), ("timestamp", "date"), ("message_id", "message-id")] {
+        if let Some(v) = header(name) { out.insert(field.into(), json!(v)); }
+    }
+    if let Some(cc) = header("cc") {
+        let list: Vec<Value> = cc.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).map(|s| json!(s)).collect();
+        out.insert("cc".into(), Value::Array(list));
+    }
+    out.insert("body".into(), json!(body));
+    Ok(out)
+}
+
+// --------------------------
+// application/x-www-form-urlencoded
+// --------------------------
+fn parse_form(bytes: &[u8]) -> Result<Map<String, Value>, UblError> {
+    let pairs: Vec<(String, String)> = serde_urlencoded::from_bytes(bytes)
+        .map_err(|e| UblError::Validation(format!("form_malformed: {}", e)))?;
+    let mut out = Map::new();
+    for (k, v) in pairs {
+        if out.insert(k.clone(), json!(v)).is_some() {
+            return Err(UblError::Validation(format!("form_duplicate_field: {}", k)));
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,213 @@
+use crate::engine::Kernel;
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use crate::types::{RiskSignal, Severity};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Deterministic, rule-based analysis of barrier output.
+///
+/// Rules never alter or interpret field values; they only emit `risk_signals`
+/// that chips can gate on. The rule set is versioned and content-hashed so a
+/// proof can pin exactly which heuristics produced the signals.
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleSet {
+    pub version: String,
+    pub instruction_phrases: Vec<String>,
+    pub identifier_fields: Vec<String>,
+    pub max_string_len: usize,
+    pub max_array_len: usize,
+    pub min_blob_len: usize,
+}
+
+impl RuleSet {
+    /// v1: phrases match as substrings, any non-ASCII letter in an identifier is
+    /// confusable, and any long base64-alphabet run is a blob. Kept so envelopes
+    /// stamped `rules_version: "1"` can be re-derived.
+    pub fn v1() -> Self {
+        Self::with_version("1")
+    }
+
+    /// v2: phrases match whole words, confusables need mixed scripts or
+    /// look-alike letters only, and blobs must be padded or decode to text.
+    pub fn v2() -> Self {
+        Self::with_version("2")
+    }
+
+    /// The rule set recorded as `rules_version`.
+    pub fn by_version(version: &str) -> Option<Self> {
+        match version {
+            "1" => Some(Self::v1()),
+            "2" => Some(Self::v2()),
+            _ => None,
+        }
+    }
+
+    fn with_version(version: &str) -> Self {
+        let phrases = [
+            "ignore previous instructions", "ignore all previous", "ignore the above",
+            "disregard previous", "disregard the above", "forget your instructions",
+            "new instructions", "system prompt", "you are now", "act as",
+            "approve all", "approve this payment", "override policy", "do not tell",
+        ];
+        Self {
+            version: version.into(),
+            instruction_phrases: phrases.iter().map(|s| s.to_string()).collect(),
+            identifier_fields: ["vendor_id", "currency", "reference", "from", "to"].iter().map(|s| s.to_string()).collect(),
+            max_string_len: 4096,
+            max_array_len: 1000,
+            min_blob_len: 64,
+        }
+    }
+
+    fn legacy(&self) -> bool { self.version == "1" }
+
+    pub fn hash(&self) -> String {
+        Kernel::jcs_hash(self)
+    }
+
+    /// Runs every rule over `fields` (recursing into arrays/objects).
+    pub fn analyze(&self, fields: &Value) -> Vec<RiskSignal> {
+        let mut out = vec![];
+        if let Some(obj) = fields.as_object() {
+            for (k, v) in obj { self.walk(k, k, v, &mut out); }
+        }
+        out
+    }
+
+    fn walk(&self, top: &str, path: &str, v: &Value, out: &mut Vec<RiskSignal>) {
+        match v {
+            Value::String(s) => self.check_string(top, path, s, out),
+            Value::Array(arr) => {
+                if arr.len() > self.max_array_len {
+                    out.push(signal("oversize_field", path, Severity::Medium, format!("array_len={}", arr.len())));
+                }
+                for (i, x) in arr.iter().enumerate() { self.walk(top, &format!("{}.{}", path, i), x, out); }
+            }
+            Value::Object(map) => {
+                for (k, x) in map { self.walk(top, &format!("{}.{}", path, k), x, out); }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_string(&self, top: &str, path: &str, s: &str, out: &mut Vec<RiskSignal>) {
+        if s.chars().count() > self.max_string_len {
+            out.push(signal("oversize_field", path, Severity::Medium, format!("len={}", s.chars().count())));
+        }
+
+        if let Some(c) = s.chars().find(|c| is_invisible_or_bidi(*c)) {
+            out.push(signal("invisible_unicode", path, Severity::High, format!("U+{:04X}", c as u32)));
+        }
+
+        let lowered = s.to_lowercase();
+        let phrase = if self.legacy() {
+            let folded = fold_whitespace(&lowered);
+            self.instruction_phrases.iter().find(|p| folded.contains(p.as_str()))
+        } else {
+            let text = words(&lowered);
+            self.instruction_phrases.iter().find(|p| contains_words(&text, &words(p)))
+        };
+        if let Some(p) = phrase {
+            out.push(signal("instruction_like_text", path, Severity::High, p.clone()));
+        }
+
+        if self.identifier_fields.iter().any(|f| f == top) {
+            let confusable = if self.legacy() {
+                s.chars().find(|c| !c.is_ascii() && c.is_alphanumeric())
+            } else {
+                words(s).into_iter().find_map(|w| mixed_script(w).or_else(|| lookalike_word(w)))
+            };
+            if let Some(c) = confusable {
+                out.push(signal("confusable_identifier", path, Severity::High, format!("U+{:04X}", c as u32)));
+            }
+        }
+
+        if let Some(kind) = self.encoding_trick(s) {
+            out.push(signal("encoding_trick", path, Severity::Medium, kind.into()));
+        }
+    }
+
+    fn encoding_trick(&self, s: &str) -> Option<&'static str> {
+        let b = s.as_bytes();
+        let pct = b.windows(3).filter(|w| w[0] == b'%' && w[1].is_ascii_hexdigit() && w[2].is_ascii_hexdigit()).count();
+        if pct >= 3 { return Some("percent_encoding"); }
+        if s.contains("&#") { return Some("html_entity"); }
+        if s.contains("\\u") || s.contains("\\x") { return Some("escape_sequence"); }
+
+        let blob = s.split(|c: char| !(c.is_ascii_alphanumeric() || c == '+' || c == '/' || c == '='))
+            .filter(|run| run.len() >= self.min_blob_len)
+            .any(|run| self.legacy() || is_base64_blob(run));
+        blob.then_some("encoded_blob")
+    }
+}
+
+fn signal(rule: &str, field: &str, severity: Severity, detail: String) -> RiskSignal {
+    RiskSignal { rule: rule.into(), field: field.into(), severity, detail }
+}
+
+fn fold_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Alphanumeric runs; everything else separates words.
+fn words(s: &str) -> Vec<&str> {
+    s.split(|c: char| !c.is_alphanumeric()).filter(|w| !w.is_empty()).collect()
+}
+
+fn contains_words(text: &[&str], phrase: &[&str]) -> bool {
+    !phrase.is_empty() && text.windows(phrase.len()).any(|w| w == phrase)
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum Script { Latin, Greek, Cyrillic, Other }
+
+fn script(c: char) -> Option<Script> {
+    if !c.is_alphabetic() { return None; }
+    Some(match c as u32 {
+        0x0041..=0x024F | 0x1E00..=0x1EFF => Script::Latin,
+        0x0370..=0x03FF | 0x1F00..=0x1FFF => Script::Greek,
+        0x0400..=0x052F => Script::Cyrillic,
+        _ => Script::Other,
+    })
+}
+
+/// The first letter of `word` whose script differs from the word's first letter.
+fn mixed_script(word: &str) -> Option<char> {
+    let mut letters = word.chars().filter_map(|c| script(c).map(|s| (c, s)));
+    let (_, first) = letters.next()?;
+    letters.find(|(_, s)| *s != first).map(|(c, _)| c)
+}
+
+/// Greek and Cyrillic letters drawn like Latin ones.
+const LOOKALIKES: &str = "АВЕЅІЈКМНОРСТУХавекмнорстухѕіјһԁԛԝѵΑΒΕΖΗΙΚΜΝΟΡΤΥΧαικνορτυχ";
+
+/// The first letter of a non-Latin `word` made only of Latin look-alikes
+/// (`асме` in Cyrillic); such a word reads as Latin without mixing scripts.
+fn lookalike_word(word: &str) -> Option<char> {
+    let mut letters = word.chars().filter(|c| c.is_alphabetic());
+    let first = letters.clone().next()?;
+    letters.all(|c| matches!(script(c), Some(Script::Greek | Script::Cyrillic)) && LOOKALIKES.contains(c))
+        .then_some(first)
+}
+
+/// Not hex (digests, ids) and either padded or decoding to UTF-8 text.
+fn is_base64_blob(run: &str) -> bool {
+    if run.bytes().all(|b| b.is_ascii_hexdigit()) { return false; }
+    match B64.decode(run) {
+        Ok(bytes) => run.ends_with('=') || std::str::from_utf8(&bytes).is_ok_and(|t| !t.chars().any(|c| c.is_control() && !c.is_whitespace())),
+        Err(_) => false,
+    }
+}
+
+/// Zero-width, BOM, soft hyphen and bidi control/isolate characters.
+pub fn is_invisible_or_bidi(c: char) -> bool {
+    matches!(c as u32,
+        0x00AD | 0x034F | 0x061C | 0x115F | 0x1160 | 0x17B4 | 0x17B5 | 0x180E |
+        0x200B..=0x200F | 0x202A..=0x202E | 0x2060..=0x2064 | 0x2066..=0x2069 |
+        0x3164 | 0xFEFF | 0xFFA0)
+}
+
+/// Highest severity among the signals, or `None` when clean.
+pub fn risk_level(signals: &[RiskSignal]) -> Option<Severity> {
+    signals.iter().map(|s| s.severity).max()
+}
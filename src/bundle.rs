@@ -0,0 +1,126 @@
+use crate::engine::{ExecOptions, Kernel, KeyMaterial};
+use crate::functions::FunctionRegistry;
+use crate::types::*;
+use serde::{Deserialize, Serialize};
+
+/// Self-contained proof bundle: everything a counterparty needs to verify a
+/// decision without access to the issuing ledger or registry.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProofBundle {
+    pub version: u32,
+    pub proof: Proof,
+    pub chip: Chip,
+    /// Chips the proof references (`Expr::Chip`), for replaying its sub-proofs.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chips: Vec<Chip>,
+    #[serde(default)]
+    pub program: Option<Program>,
+    #[serde(default)]
+    pub record: Option<EffectRecord>,
+    /// Public keys (with ids and validity) that signed the proof, record and chain.
+    /// Hints only: verification uses the verifier's trusted keys, and each of
+    /// these must be one of them.
+    #[serde(default)]
+    pub keys: Vec<KeyEntry>,
+    /// Records following `record`, ending at a signed checkpoint.
+    #[serde(default)]
+    pub chain: Vec<EffectRecord>,
+}
+
+pub const BUNDLE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundleVerification {
+    pub valid: bool,
+    pub proof: ProofVerification,
+    pub program: CheckStatus,
+    pub record: CheckStatus,
+    pub chain: CheckStatus,
+    /// Every embedded key is a trusted one.
+    pub keys: CheckStatus,
+    #[serde(default)]
+    pub checkpoint_version: Option<u64>,
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+impl ProofBundle {
+    /// Verifies the bundle fully offline against `trusted`: the verifier's
+    /// keyring (with revocations) or the issuer's pinned keys
+    /// ([`KeyMaterial::pinned`]), never the keys the bundle brings along:
+    /// - embedded keys are all trusted ones,
+    /// - proof against the embedded chip and the chips it references (hash,
+    ///   replay, signature per `policy`),
+    /// - program hash and its reference to the chip,
+    /// - record hash/signature and its link to the proof and program,
+    /// - chain links from the record up to a signed checkpoint.
+    ///
+    /// The replay calls `functions`: the issuer's registry, or one with the
+    /// same function versions (`VerifyStage::Functions` otherwise).
+    pub fn verify(&self, policy: SignaturePolicy, trusted: &KeyMaterial, functions: &dyn FunctionRegistry) -> BundleVerification {
+        let mut errors = vec![];
+
+        let untrusted: Vec<&str> = self.keys.iter().filter(|k| !trusted.trusts(k)).map(|k| k.key_id.as_str()).collect();
+        for id in &untrusted { errors.push(format!("untrusted_key: {}", id)); }
+        let key_check = if self.keys.is_empty() { CheckStatus::Skipped } else { CheckStatus::from(untrusted.is_empty()) };
+
+        let mut chip = self.chip.clone();
+        chip.hash = Kernel::compute_chip_hash(&chip);
+        let opts = ExecOptions { chips: &self.chips, functions, signature_policy: policy, ..Default::default() };
+        let proof = Kernel::verify_proof(&self.proof, &chip, trusted, &opts);
+
+        let program = match &self.program {
+            None => CheckStatus::Skipped,
+            Some(p) => {
+                let hash = Kernel::compute_program_hash(p);
+                let evaluates_chip = p.evaluate == chip.hash || p.evaluate == format!("CHIP:{}", chip.name);
+                if !evaluates_chip { errors.push("program_does_not_evaluate_chip".into()); }
+                let in_record = self.record.as_ref().map(|r| r.program_hash == hash).unwrap_or(true);
+                if !in_record { errors.push("program_hash_mismatch".into()); }
+                CheckStatus::from(evaluates_chip && in_record)
+            }
+        };
+
+        let record = match &self.record {
+            None => CheckStatus::Skipped,
+            Some(r) => {
+                let intact = Kernel::verify_record(r, trusted);
+                if !intact { errors.push("record_invalid".into()); }
+                let signed_ok = policy != SignaturePolicy::Required || r.record_signature.is_some();
+                if !signed_ok { errors.push("record_signature_required".into()); }
+                let links_proof = r.proof_hash == self.proof.proof_hash;
+                if !links_proof { errors.push("record_proof_hash_mismatch".into()); }
+                CheckStatus::from(intact && signed_ok && links_proof)
+            }
+        };
+
+        let (chain, checkpoint_version) = match (&self.record, self.chain.is_empty()) {
+            (_, true) => (CheckStatus::Skipped, None),
+            (None, false) => { errors.push("chain_without_record".into()); (CheckStatus::Failed, None) }
+            (Some(r), false) => match Self::verify_chain(r, &self.chain, trusted) {
+                Ok(v) => (CheckStatus::Passed, Some(v)),
+                Err(e) => { errors.push(e); (CheckStatus::Failed, None) }
+            },
+        };
+
+        let valid = proof.valid && ![program, record, chain, key_check].contains(&CheckStatus::Failed);
+        BundleVerification { valid, proof, program, record, chain, keys: key_check, checkpoint_version, errors }
+    }
+
+    fn verify_chain(start: &EffectRecord, chain: &[EffectRecord], keys: &KeyMaterial) -> Result<u64, String> {
+        let mut prev = start;
+        for r in chain {
+            if r.previous_record_hash.as_deref() != Some(prev.record_hash.as_str()) || r.version_applied_to != prev.resulting_version {
+                return Err(format!("chain_broken_at_version: {}", r.resulting_version));
+            }
+            if !Kernel::verify_record(r, keys) {
+                return Err(format!("chain_record_invalid: {}", r.resulting_version));
+            }
+            prev = r;
+        }
+        if prev.record_signature.is_none() {
+            return Err("checkpoint_unsigned".into());
+        }
+        Ok(prev.resulting_version)
+    }
+}
@@ -0,0 +1,212 @@
+//! Dates, durations, time zones and business calendars for the time builtins.
+//!
+//! Everything here is a pure function of its arguments: "now" is always
+//! `ExecMeta::execution_time`, passed in by the caller, so replay at
+//! `evaluated_at` gives the same answers. Results are UTC RFC 3339 timestamps
+//! (like `now()`) or plain dates/buckets.
+//!
+//! Zones are IANA names (`"Europe/Lisbon"`, from the compiled tz database), fixed
+//! offsets (`"+05:30"`) or `"UTC"`. A business calendar is ledger state, bound
+//! with a `ledger` context source and passed as an argument:
+//! `{"holidays": ["2025-12-25", ...], "weekend": [6, 7]}` (ISO weekdays, default
+//! Saturday and Sunday), or just the array of holidays.
+
+use chrono::{DateTime, Datelike, Days, FixedOffset, Months, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// Upper bound on the days a business-day computation scans.
+pub const MAX_CALENDAR_SCAN: u64 = 3_660;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Zone { Fixed(FixedOffset), Iana(Tz) }
+
+impl Default for Zone {
+    fn default() -> Self { Zone::Fixed(Utc.fix()) }
+}
+
+pub fn zone(name: &str) -> Option<Zone> {
+    if matches!(name, "UTC" | "Z" | "") { return Some(Zone::default()); }
+    if let Some(sign @ ('+' | '-')) = name.chars().next() {
+        let (h, m) = name[1..].split_once(':')?;
+        let (h, m) = (offset_part(h, 23)?, offset_part(m, 59)?);
+        let secs = h.checked_mul(60)?.checked_add(m)?.checked_mul(60)?;
+        return FixedOffset::east_opt(if sign == '-' { -secs } else { secs }).map(Zone::Fixed);
+    }
+    name.parse::<Tz>().ok().map(Zone::Iana)
+}
+
+/// Hours or minutes of a fixed offset: digits only, at most `max`.
+fn offset_part(s: &str, max: i32) -> Option<i32> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) { return None; }
+    s.parse().ok().filter(|n| *n <= max)
+}
+
+impl Zone {
+    /// Wall-clock time in this zone.
+    pub fn local(self, at: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Zone::Fixed(z) => at.with_timezone(&z).naive_local(),
+            Zone::Iana(z) => at.with_timezone(&z).naive_local(),
+        }
+    }
+
+    /// The instant of a wall-clock time: the earlier one when clocks go back,
+    /// the first valid one after a gap when they go forward.
+    pub fn instant(self, local: NaiveDateTime) -> DateTime<Utc> {
+        let mut t = local;
+        loop {
+            let found = match self {
+                Zone::Fixed(z) => z.from_local_datetime(&t).earliest().map(|d| d.with_timezone(&Utc)),
+                Zone::Iana(z) => z.from_local_datetime(&t).earliest().map(|d| d.with_timezone(&Utc)),
+            };
+            match found {
+                Some(d) => return d,
+                None => t += chrono::Duration::minutes(15), // gaps are whole quarter hours
+            }
+        }
+    }
+}
+
+pub fn rfc3339(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+// --------------------------
+// ISO 8601 durations
+// --------------------------
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IsoDuration {
+    pub negative: bool,
+    pub months: u32, // years are 12 months
+    pub days: u64,   // weeks are 7 days
+    pub seconds: u64,
+}
+
+/// `[-]PnYnMnWnDTnHnMnS` with integer components, e.g. `P30D`, `P1Y2M`, `PT36H`.
+pub fn parse_duration(s: &str) -> Option<IsoDuration> {
+    let (negative, s) = match s.strip_prefix('-') { Some(r) => (true, r), None => (false, s) };
+    let body = s.strip_prefix('P')?;
+    let (date, time) = match body.split_once('T') { Some((d, t)) => (d, Some(t)), None => (body, None) };
+    if body.is_empty() || time == Some("") { return None; }
+
+    let mut d = IsoDuration { negative, ..Default::default() };
+    let parts = |src: &str, units: &str, apply: &mut dyn FnMut(char, u64) -> Option<()>| -> Option<()> {
+        let mut num = String::new();
+        let mut last = 0;
+        for c in src.chars() {
+            if c.is_ascii_digit() { num.push(c); continue; }
+            // units in order, each at most once
+            let pos = units.find(c).filter(|&p| p + 1 > last)? + 1;
+            last = pos;
+            apply(c, std::mem::take(&mut num).parse().ok()?)?;
+        }
+        num.is_empty().then_some(())
+    };
+    parts(date, "YMWD", &mut |unit, n| {
+        match unit {
+            'Y' => d.months = d.months.checked_add(u32::try_from(n).ok()?.checked_mul(12)?)?,
+            'M' => d.months = d.months.checked_add(u32::try_from(n).ok()?)?,
+            'W' => d.days = d.days.checked_add(n.checked_mul(7)?)?,
+            _ => d.days = d.days.checked_add(n)?,
+        }
+        Some(())
+    })?;
+    if let Some(t) = time {
+        parts(t, "HMS", &mut |unit, n| {
+            let scale = match unit { 'H' => 3600, 'M' => 60, _ => 1 };
+            d.seconds = d.seconds.checked_add(n.checked_mul(scale)?)?;
+            Some(())
+        })?;
+    }
+    Some(d)
+}
+
+/// Months first (clamped to the end of the month, so Jan 31 + P1M is Feb 28/29),
+/// then days, then seconds, all in UTC.
+pub fn add_duration(at: DateTime<Utc>, d: &IsoDuration) -> Option<DateTime<Utc>> {
+    let secs = chrono::Duration::try_seconds(i64::try_from(d.seconds).ok()?)?;
+    if d.negative {
+        at.checked_sub_months(Months::new(d.months))?.checked_sub_days(Days::new(d.days))?.checked_sub_signed(secs)
+    } else {
+        at.checked_add_months(Months::new(d.months))?.checked_add_days(Days::new(d.days))?.checked_add_signed(secs)
+    }
+}
+
+/// Calendar days from `a` to `b` (negative if `b` is earlier), by local date.
+pub fn days_between(a: DateTime<Utc>, b: DateTime<Utc>, z: Zone) -> i64 {
+    (z.local(b).date() - z.local(a).date()).num_days()
+}
+
+/// First instant of the local month.
+pub fn start_of_month(at: DateTime<Utc>, z: Zone) -> DateTime<Utc> {
+    let first = z.local(at).date().with_day(1).unwrap_or_default();
+    z.instant(first.and_hms_opt(0, 0, 0).unwrap_or_default())
+}
+
+/// ISO weekday of the local date: 1 = Monday … 7 = Sunday.
+pub fn weekday(at: DateTime<Utc>, z: Zone) -> u32 {
+    z.local(at).weekday().number_from_monday()
+}
+
+/// `minute`, `hour`, `day`, `week` (ISO, `2025-W03`) or `month`, by local time.
+pub fn bucket(at: DateTime<Utc>, unit: &str, z: Zone) -> Option<String> {
+    let t = z.local(at);
+    Some(match unit {
+        "minute" => t.format("%Y-%m-%dT%H:%M").to_string(),
+        "hour" => t.format("%Y-%m-%dT%H").to_string(),
+        "day" => t.format("%Y-%m-%d").to_string(),
+        "week" => { let w = t.iso_week(); format!("{}-W{:02}", w.year(), w.week()) }
+        "month" => t.format("%Y-%m").to_string(),
+        _ => return None,
+    })
+}
+
+// --------------------------
+// Business calendars
+// --------------------------
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calendar {
+    pub holidays: BTreeSet<NaiveDate>,
+    pub weekend: Vec<u32>, // ISO weekdays
+}
+
+/// Reads a calendar from its ledger representation; `None` if malformed.
+pub fn calendar(v: &Value) -> Option<Calendar> {
+    let (holidays, weekend) = match v {
+        Value::Array(h) => (h, None),
+        Value::Object(m) => (m.get("holidays").and_then(Value::as_array)?, m.get("weekend")),
+        _ => return None,
+    };
+    let holidays = holidays.iter()
+        .map(|d| d.as_str().and_then(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d").ok()))
+        .collect::<Option<_>>()?;
+    let weekend = match weekend {
+        None => vec![6, 7],
+        Some(w) => w.as_array()?.iter()
+            .map(|d| d.as_u64().filter(|d| (1..=7).contains(d)).map(|d| d as u32))
+            .collect::<Option<_>>()?,
+    };
+    Some(Calendar { holidays, weekend })
+}
+
+pub fn is_business_day(date: NaiveDate, cal: &Calendar) -> bool {
+    !cal.weekend.contains(&date.weekday().number_from_monday()) && !cal.holidays.contains(&date)
+}
+
+/// The `n`th business day after (`n < 0`: before) the local date, at the same
+/// local time. `n = 0` rolls forward to the next business day. `None` if more
+/// than [`MAX_CALENDAR_SCAN`] days would be scanned.
+pub fn add_business_days(at: DateTime<Utc>, n: i64, cal: &Calendar, z: Zone) -> Option<DateTime<Utc>> {
+    let local = z.local(at);
+    let step = |d: NaiveDate| if n < 0 { d.pred_opt() } else { d.succ_opt() };
+    let (mut date, mut left, mut scanned) = (local.date(), n.unsigned_abs(), 0);
+    while left > 0 || !is_business_day(date, cal) {
+        date = step(date)?;
+        scanned += 1;
+        if scanned > MAX_CALENDAR_SCAN { return None; }
+        if left > 0 && is_business_day(date, cal) { left -= 1; }
+    }
+    Some(z.instant(date.and_time(local.time())))
+}
@@ -0,0 +1,120 @@
+//! Canonical string handling shared by the barrier and the engine.
+//!
+//! Two visually identical strings must hash and compare identically, so every
+//! string that reaches a hash or a comparison is brought to a Unicode normal form.
+
+use crate::error::UblError;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use unicode_normalization::{is_nfc_quick, IsNormalized, UnicodeNormalization};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NormForm {
+    #[default]
+    Nfc,
+    Nfkc,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaseFold {
+    #[default]
+    None,
+    Lower,
+    Upper,
+}
+
+/// Per-field string rule applied by the barrier.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TextRule {
+    pub form: NormForm,
+    pub case: CaseFold,
+    /// Reject Unicode format characters (zero-width, bidi controls, tags).
+    pub reject_format: bool,
+    /// Allow `\t`, `\n`, `\r` (other control characters are always rejected).
+    pub allow_whitespace_controls: bool,
+}
+
+impl TextRule {
+    /// Identifiers: NFKC, no format or control characters at all.
+    pub const fn ident(case: CaseFold) -> Self {
+        Self { form: NormForm::Nfkc, case, reject_format: true, allow_whitespace_controls: false }
+    }
+
+    /// Free text: NFC, line breaks allowed, format characters left to risk analysis.
+    pub const fn text() -> Self {
+        Self { form: NormForm::Nfc, case: CaseFold::None, reject_format: false, allow_whitespace_controls: true }
+    }
+
+    /// Validates then normalizes `s`. Errors name the offending character class.
+    pub fn apply(&self, s: &str) -> Result<String, &'static str> {
+        for c in s.chars() {
+            if c.is_control() && !(self.allow_whitespace_controls && matches!(c, '\t' | '\n' | '\r')) {
+                return Err("control_character");
+            }
+            if self.reject_format && is_format(c) { return Err("format_character"); }
+        }
+        let normalized = match self.form {
+            NormForm::Nfc => nfc(s),
+            NormForm::Nfkc => nfkc(s),
+        };
+        Ok(match self.case {
+            CaseFold::None => normalized,
+            CaseFold::Lower => normalized.to_lowercase(),
+            CaseFold::Upper => normalized.to_uppercase(),
+        })
+    }
+}
+
+pub fn nfc(s: &str) -> String {
+    if is_nfc_quick(s.chars()) == IsNormalized::Yes { return s.to_string(); }
+    s.nfc().collect()
+}
+
+pub fn nfkc(s: &str) -> String {
+    s.nfkc().collect()
+}
+
+/// Caseless matching key: NFKC(lower(NFKC(s))).
+pub fn casefold(s: &str) -> String {
+    nfkc(&nfkc(s).to_lowercase())
+}
+
+/// Unicode format characters (general category Cf) relevant to spoofing.
+pub fn is_format(c: char) -> bool {
+    matches!(c as u32,
+        0x00AD | 0x0600..=0x0605 | 0x061C | 0x06DD | 0x070F | 0x08E2 | 0x180E |
+        0x200B..=0x200F | 0x202A..=0x202E | 0x2060..=0x2064 | 0x2066..=0x206F |
+        0xFEFF | 0xFFF9..=0xFFFB | 0x110BD | 0x110CD | 0x1D173..=0x1D17A |
+        0xE0001 | 0xE0020..=0xE007F)
+}
+
+/// NFC-normalizes every string (and object key) in a JSON value. Distinct keys
+/// that become equal under NFC are rejected rather than merged.
+pub fn canonicalize(v: &Value) -> Result<Value, UblError> {
+    Ok(match v {
+        Value::String(s) => Value::String(nfc(s)),
+        Value::Array(arr) => Value::Array(arr.iter().map(canonicalize).collect::<Result<_, _>>()?),
+        Value::Object(map) => {
+            let mut out = Map::new();
+            for (k, x) in map {
+                let key = nfc(k);
+                if out.contains_key(&key) {
+                    return Err(UblError::Validation(format!("duplicate_key_after_nfc: {}", key)));
+                }
+                out.insert(key, canonicalize(x)?);
+            }
+            Value::Object(out)
+        }
+        _ => v.clone(),
+    })
+}
+
+/// Equality with strings compared in NFC.
+pub fn value_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(x), Value::String(y)) => x == y || nfc(x) == nfc(y),
+        _ => a == b,
+    }
+}
@@ -1,25 +1,309 @@
+use crate::calendar::{self, Zone};
+use crate::canon;
+use crate::envelope::{self, EnvelopeContent, Opened};
+use crate::error::{EvalError, UblError};
+use crate::fingerprint;
+use crate::text;
+use crate::functions::{self, FunctionRegistry};
 use crate::types::*;
 use serde::Serialize;
 use serde_json::{json, Value};
 use sha2::{Digest, Sha256};
 use chrono::{DateTime, Utc};
 use serde_jcs::to_string as jcs_to_string;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 
 use ed25519_dalek::{SigningKey, VerifyingKey, Signature, Signer, Verifier};
 use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
 
 pub struct Kernel;
 
+/// What a function sees besides its arguments: the evaluation context, the
+/// execution metadata (for time) and the error slot of the current gate.
+pub struct CallContext<'a> {
+    pub context: &'a Value,
+    pub meta: &'a ExecMeta,
+    st: &'a EvalState<'a>,
+}
+
+impl CallContext<'_> {
+    /// Records an evaluation error (strict chips fail on it); the function
+    /// still returns its fallback value.
+    pub fn fail(&self, e: EvalError) { self.st.fail(e); }
+}
+
+/// Per-gate evaluation state: the first error (later ones are consequences)
+/// and, in trace mode, the children collected for each open expression.
+struct EvalState<'c> {
+    error: RefCell<Option<EvalError>>,
+    trace: Option<RefCell<Vec<Vec<TraceNode>>>>,
+    functions: &'c dyn FunctionRegistry,
+    fuel: FuelMeter,
+    depth: Cell<usize>,
+    chips: Option<&'c ChipScope<'c>>,
+    /// `threshold_signatures` evidence: the first failing call, else the first call.
+    signers: RefCell<Option<(bool, GateValues, Option<String>)>>,
+}
+
+impl Default for EvalState<'_> {
+    fn default() -> Self { Self::new(functions::builtin_registry(), false, FuelMeter::unlimited()) }
+}
+
+impl<'c> EvalState<'c> {
+    fn new(functions: &'c dyn FunctionRegistry, traced: bool, fuel: FuelMeter) -> Self {
+        Self { error: RefCell::new(None), trace: traced.then(|| RefCell::new(vec![vec![]])), functions, fuel, depth: Cell::new(0), chips: None, signers: RefCell::new(None) }
+    }
+
+    /// Untraced state with its own error slot, sharing functions, fuel, depth
+    /// and referenced chips.
+    fn evidence(&self) -> Self {
+        let mut st = Self::new(self.functions, false, self.fuel.clone());
+        st.depth.set(self.depth.get());
+        st.chips = self.chips;
+        st
+    }
+
+    /// A fatal error replaces an earlier ordinary one: it decides the gate.
+    fn fail(&self, e: EvalError) {
+        let mut slot = self.error.borrow_mut();
+        if slot.as_ref().is_none_or(|old| e.is_fatal() && !old.is_fatal()) { *slot = Some(e); }
+    }
+
+    fn record_signers(&self, passed: bool, values: GateValues, error: Option<String>) {
+        let mut slot = self.signers.borrow_mut();
+        if slot.as_ref().is_none_or(|(ok, _, _)| *ok && !passed) { *slot = Some((passed, values, error)); }
+    }
+
+    fn charge(&self, n: u64) -> bool {
+        self.fuel.charge(n) || { self.fail(EvalError::FuelExhausted); false }
+    }
+
+    /// Enters an expression node: checks the nesting depth and charges its fuel.
+    fn admit(&self, expr: &Expr) -> bool {
+        if self.depth.get() >= MAX_EXPR_DEPTH { self.fail(EvalError::DepthExceeded); return false; }
+        let size = match expr { Expr::Literal { value: Value::Array(a) } => a.len(), Expr::Literal { value: Value::Object(o) } => o.len(), _ => 0 };
+        if !self.charge(FUEL_NODE + size as u64) { return false; }
+        self.depth.set(self.depth.get() + 1);
+        true
+    }
+
+    fn leave(&self) { self.depth.set(self.depth.get() - 1); }
+
+    fn enter(&self) {
+        if let Some(t) = &self.trace { t.borrow_mut().push(vec![]); }
+    }
+
+    /// Closes the innermost open node and attaches it to its parent.
+    fn exit(&self, node: &str, label: String, value: &Value) {
+        let Some(t) = &self.trace else { return };
+        let mut t = t.borrow_mut();
+        let children = t.pop().unwrap_or_default();
+        // and/or: the first operand that fixed the result
+        let decided_by = match (node, label.as_str()) {
+            ("logic", "and") => children.iter().position(|c| c.value != json!(true)),
+            ("logic", "or") => children.iter().position(|c| c.value == json!(true)),
+            _ => None,
+        };
+        let n = TraceNode { node: node.into(), label, value: value.clone(), decided_by, children };
+        match t.last_mut() { Some(parent) => parent.push(n), None => t.push(vec![n]) }
+    }
+
+    fn into_parts(self) -> (Option<EvalError>, Option<TraceNode>) {
+        let trace = self.trace.and_then(|t| t.into_inner().pop()).and_then(|mut root| root.pop());
+        (self.error.into_inner(), trace)
+    }
+}
+
+static NULL: Value = Value::Null;
+
+/// The stable part of a gate error: its code, before the first `:`.
+fn error_code(error: &Option<String>) -> Option<&str> {
+    error.as_deref().map(|e| e.split(':').next().unwrap_or(e).trim())
+}
+
+fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null", Value::Bool(_) => "boolean", Value::Number(_) => "number",
+        Value::String(_) => "string", Value::Array(_) => "array", Value::Object(_) => "object",
+    }
+}
+
+fn compare_symbol(op: &CompareOp) -> String {
+    serde_json::to_value(op).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default()
+}
+
+/// Trace node type and label (path, operator, function or variable).
+fn trace_label(expr: &Expr) -> (&'static str, String) {
+    match expr {
+        Expr::Literal { .. } => ("literal", String::new()),
+        Expr::Path { path, .. } => ("path", path.join(".")),
+        Expr::Compare { op, .. } => ("compare", compare_symbol(op)),
+        Expr::Logic { op, .. } => ("logic", match op { LogicOp::And => "and", LogicOp::Or => "or", LogicOp::Not => "not" }.into()),
+        Expr::Call { function, .. } => ("call", function.clone()),
+        Expr::Collection { op, .. } => ("collection", serde_json::to_value(op).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default()),
+        Expr::Let { name, .. } => ("let", name.clone()),
+        Expr::If { .. } => ("if", String::new()),
+        Expr::Chip { reference } => ("chip", reference.clone()),
+    }
+}
+
+/// Upper bound on the elements a collection expression visits.
+pub const MAX_COLLECTION_LEN: usize = 10_000;
+/// Upper bound on expression nesting (evaluation and the policy parser). Chips
+/// within it stay under serde_json's recursion limit when stored and reloaded.
+pub const MAX_EXPR_DEPTH: usize = 48;
+
+/// Nesting depth of an expression tree (a literal or path is 1).
+pub fn expr_depth(expr: &Expr) -> usize {
+    let max = |es: &mut dyn Iterator<Item = &Expr>| es.map(expr_depth).max().unwrap_or(0);
+    1 + match expr {
+        Expr::Literal { .. } | Expr::Path { .. } | Expr::Chip { .. } => 0,
+        Expr::Compare { left, right, .. } => max(&mut [left, right].into_iter().map(|b| &**b)),
+        Expr::Logic { args, .. } | Expr::Call { args, .. } => max(&mut args.iter()),
+        Expr::Collection { over, body, .. } => max(&mut std::iter::once(&**over).chain(body.as_deref())),
+        Expr::Let { value, body, .. } => max(&mut [value, body].into_iter().map(|b| &**b)),
+        Expr::If { cond, then, otherwise } => max(&mut [cond, then, otherwise].into_iter().map(|b| &**b)),
+    }
+}
+
+// --------------------------
+// Fuel
+// --------------------------
+/// Default and maximum fuel for one evaluation, unless `UBL_FUEL_LIMIT` says otherwise.
+pub const DEFAULT_FUEL_LIMIT: u64 = 1_000_000;
+/// Every expression node, plus one per element of an array or object literal.
+pub const FUEL_NODE: u64 = 1;
+/// Every function call, plus one per 64 bytes of string arguments and per array element.
+pub const FUEL_CALL: u64 = 10;
+/// Every element a collection expression visits.
+pub const FUEL_ELEMENT: u64 = 1;
+/// Every applied effect.
+pub const FUEL_EFFECT: u64 = 100;
+
+/// Fuel for one execution. Clones share the counter; once a charge would pass
+/// the limit, `used` stays at the limit and the meter is exhausted.
+#[derive(Clone, Debug)]
+pub struct FuelMeter {
+    limit: u64,
+    used: Arc<AtomicU64>,
+    exhausted: Arc<AtomicBool>,
+}
+
+impl FuelMeter {
+    pub fn new(limit: u64) -> Self { Self { limit, used: Default::default(), exhausted: Default::default() } }
+
+    pub fn unlimited() -> Self { Self::new(u64::MAX) }
+
+    pub fn limit(&self) -> u64 { self.limit }
+
+    pub fn used(&self) -> u64 { self.used.load(Ordering::Relaxed) }
+
+    pub fn remaining(&self) -> u64 { self.limit - self.used() }
+
+    pub fn exhausted(&self) -> bool { self.exhausted.load(Ordering::Relaxed) }
+
+    pub fn charge(&self, n: u64) -> bool {
+        if self.exhausted() { return false; }
+        match self.used().checked_add(n).filter(|&u| u <= self.limit) {
+            Some(u) => { self.used.store(u, Ordering::Relaxed); true }
+            None => { self.used.store(self.limit, Ordering::Relaxed); self.exhausted.store(true, Ordering::Relaxed); false }
+        }
+    }
+
+    pub fn usage(&self) -> FuelUsage { FuelUsage { limit: self.limit, used: self.used() } }
+}
+
+fn call_fuel(args: &[Value]) -> u64 {
+    let size: usize = args.iter().map(|v| match v { Value::String(s) => s.len() / 64, Value::Array(a) => a.len(), _ => 0 }).sum();
+    FUEL_CALL + size as u64
+}
+
+// --------------------------
+// Chip references
+// --------------------------
+/// Upper bound on chips nested through `Expr::Chip`, the evaluated one included.
+/// With [`MAX_EXPR_DEPTH`] per chip, this bounds the stack an evaluation uses and
+/// keeps traced proofs with their sub-proofs under serde_json's recursion limit.
+pub const MAX_CHIP_NESTING: usize = 4;
+
+/// Looks up the chips an `Expr::Chip` names: by hash, or `CHIP:name`.
+pub trait ChipResolver {
+    fn resolve_chip(&self, reference: &str) -> Option<Chip>;
+}
+
+/// A list of chips (e.g. a bundle's); for `CHIP:name` the last one named so wins.
+impl ChipResolver for Vec<Chip> {
+    fn resolve_chip(&self, reference: &str) -> Option<Chip> {
+        match reference.strip_prefix("CHIP:") {
+            Some(name) => self.iter().rev().find(|c| c.name == name),
+            None => self.iter().find(|c| Kernel::compute_chip_hash(c) == reference),
+        }.cloned()
+    }
+}
+
+/// What one chip evaluation can reach: the resolver, the chips being evaluated
+/// (outermost first) and the proofs of the chips referenced so far.
+struct ChipScope<'c> {
+    resolver: &'c dyn ChipResolver,
+    chain: Vec<Hash>,
+    subproofs: RefCell<Vec<Proof>>,
+}
+
+/// Variables bound by collection expressions and `let`, innermost first.
+struct Scope<'a> {
+    name: &'a str,
+    value: &'a Value,
+    parent: Option<&'a Scope<'a>>,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, name: &str) -> Option<&Value> {
+        if self.name == name { Some(self.value) } else { self.parent.and_then(|p| p.lookup(name)) }
+    }
+}
+
 #[derive(Clone)]
 pub struct ExecMeta {
     pub tx_id: String,
     pub execution_time: DateTime<Utc>,
 }
 
-#[derive(Clone)]
+static NO_CHIPS: Vec<Chip> = Vec::new();
+
+/// How [`Kernel::execute_chip`] runs a chip and [`Kernel::verify_proof`] replays
+/// it. The default: untraced, the chip's own fuel limit, no chip references
+/// (`unknown_chip`), the builtin functions, optional signatures.
+#[derive(Clone, Copy)]
+pub struct ExecOptions<'a> {
+    /// Every gate also records the value of each evaluated sub-expression
+    /// (`GateResult::trace`, covered by `proof_hash`). Replay traces if the proof did.
+    pub trace: bool,
+    /// Recorded in `proof.fuel`; `None` is [`Kernel::fuel_limit`] of the chip.
+    /// Replay uses the limit the proof recorded.
+    pub fuel_limit: Option<u64>,
+    /// Resolves `Expr::Chip` references.
+    pub chips: &'a (dyn ChipResolver + Sync),
+    /// Functions `Expr::Call` resolves to; replay must use the same versions
+    /// (`VerifyStage::Functions`).
+    pub functions: &'a dyn FunctionRegistry,
+    /// Whether [`Kernel::verify_proof`] requires a signature.
+    pub signature_policy: SignaturePolicy,
+}
+
+impl Default for ExecOptions<'_> {
+    fn default() -> Self {
+        Self { trace: false, fuel_limit: None, chips: &NO_CHIPS, functions: functions::builtin_registry(), signature_policy: SignaturePolicy::default() }
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct KeyMaterial {
     pub signing: Option<SigningKey>,
     pub verifying: Option<VerifyingKey>,
+    pub key_id: Option<String>, // id of the active signing key
+    pub keyring: Keyring,       // historical keys for verification
 }
 
 impl KeyMaterial {
@@ -42,23 +326,104 @@ impl KeyMaterial {
             })
             .or_else(|| signing.as_ref().map(|sk| sk.verifying_key()));
 
-        Self { signing, verifying }
+        let key_id = std::env::var("UBL_ED25519_KEY_ID").ok()
+            .or_else(|| verifying.as_ref().map(Self::derive_key_id));
+
+        Self { signing, verifying, key_id, keyring: Keyring::default() }
     }
 
-    pub fn sign_b64(&self, msg: &[u8]) -> Option<String> {
+    /// Key material for a single signing key (id derived from the public key).
+    pub fn from_signing_key(sk: SigningKey) -> Self {
+        let vk = sk.verifying_key();
+        Self { key_id: Some(Self::derive_key_id(&vk)), signing: Some(sk), verifying: Some(vk), keyring: Keyring::default() }
+    }
+
+    /// Verification-only key material trusting exactly `keys` (e.g. an issuer's
+    /// pinned keys); add revocations to `keyring.revoked`.
+    pub fn pinned(keys: Vec<KeyEntry>) -> Self {
+        Self { keyring: Keyring { keys, revoked: vec![] }, ..Default::default() }
+    }
+
+    /// Whether `entry` names a key trusted here: the same id with the same public key.
+    pub fn trusts(&self, entry: &KeyEntry) -> bool {
+        let active = self.key_id.as_deref() == Some(entry.key_id.as_str())
+            && self.verifying.is_some_and(|vk| B64.encode(vk.as_bytes()) == entry.public_key);
+        active || self.keyring.keys.iter().any(|k| k.key_id == entry.key_id && k.public_key == entry.public_key)
+    }
+
+    /// `k-` + first 16 hex chars of sha256(public key bytes).
+    pub fn derive_key_id(vk: &VerifyingKey) -> String {
+        format!("k-{}", &Kernel::sha256_hex(vk.as_bytes())[..16])
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Option<Vec<u8>> {
         self.signing.as_ref().map(|sk| {
             let sig: Signature = sk.sign(msg);
-            B64.encode(sig.to_bytes())
+            sig.to_bytes().to_vec()
         })
     }
 
+    pub fn sign_b64(&self, msg: &[u8]) -> Option<String> {
+        self.sign(msg).map(|sig| B64.encode(sig))
+    }
+
     pub fn verify_sig_b64(&self, msg: &[u8], sig_b64: &str) -> bool {
         let vk = match &self.verifying { Some(v) => v, None => return false };
+        Self::verify_with(vk, msg, sig_b64)
+    }
+
+    fn verify_with(vk: &VerifyingKey, msg: &[u8], sig_b64: &str) -> bool {
         let sig_bytes = match B64.decode(sig_b64) { Ok(b) => b, Err(_) => return false };
-        let arr: [u8; 64] = match sig_bytes.as_slice().try_into() { Ok(a) => a, Err(_) => return false }; 
+        let arr: [u8; 64] = match sig_bytes.as_slice().try_into() { Ok(a) => a, Err(_) => return false };
         let sig = Signature::from_bytes(&arr);
         vk.verify(msg, &sig).is_ok()
     }
+
+    /// Verifies a signature made by `key_id` at time `at`, picking the historical
+    /// key from the keyring. Revoked keys never verify; the signing time must fall
+    /// inside the key's validity period. Without a `key_id` (legacy artifacts) the
+    /// current verifying key is used.
+    pub fn verify_at(&self, msg: &[u8], sig_b64: &str, key_id: Option<&str>, at: &str) -> bool {
+        let id = match key_id { Some(id) => id, None => return self.verify_sig_b64(msg, sig_b64) };
+        if self.keyring.revoked.iter().any(|r| r.key_id == id) { return false; }
+
+        match self.keyring.keys.iter().find(|k| k.key_id == id) {
+            Some(entry) => {
+                let t = match Kernel::parse_ts(at) { Some(t) => t, None => return false };
+                let from_ok = Kernel::parse_ts(&entry.valid_from).map(|f| t >= f).unwrap_or(false);
+                let until_ok = entry.valid_until.as_deref().map(|u| Kernel::parse_ts(u).map(|u| t < u).unwrap_or(false)).unwrap_or(true);
+                if !(from_ok && until_ok) { return false; }
+                match Kernel::parse_verifying_key_b64(&entry.public_key) {
+                    Some(vk) => Self::verify_with(&vk, msg, sig_b64),
+                    None => false,
+                }
+            }
+            // Key not (yet) in the keyring: only the configured key may match.
+            None if self.key_id.as_deref() == Some(id) => self.verify_sig_b64(msg, sig_b64),
+            None => false,
+        }
+    }
+}
+
+impl SignaturePolicy {
+    /// `UBL_SIGNATURE_POLICY` = `required` | `optional` (default) | `ignored`.
+    pub fn from_env() -> Self {
+        match std::env::var("UBL_SIGNATURE_POLICY").as_deref() {
+            Ok("required") => SignaturePolicy::Required,
+            Ok("ignored") => SignaturePolicy::Ignored,
+            _ => SignaturePolicy::Optional,
+        }
+    }
+
+    /// The stricter of two policies: a request may tighten the server's, never loosen it.
+    pub fn stricter(self, other: Self) -> Self {
+        let rank = |p| match p { SignaturePolicy::Ignored => 0, SignaturePolicy::Optional => 1, SignaturePolicy::Required => 2 };
+        if rank(self) >= rank(other) { self } else { other }
+    }
+}
+
+impl From<bool> for CheckStatus {
+    fn from(ok: bool) -> Self { if ok { CheckStatus::Passed } else { CheckStatus::Failed } }
 }
 
 impl Kernel {
@@ -80,24 +445,37 @@ impl Kernel {
         Self::sha256_hex(s.as_bytes())
     }
 
+    /// Hash of execution inputs: JCS over the NFC-canonicalized value, so
+    /// visually identical inputs in different Unicode forms hash identically.
+    pub fn input_hash(inputs: &Value) -> Result<String, UblError> {
+        Ok(Self::jcs_hash(&canon::canonicalize(inputs)?))
+    }
+
+    pub fn parse_verifying_key_b64(pk_b64: &str) -> Option<VerifyingKey> {
+        let pk_bytes = B64.decode(pk_b64).ok()?;
+        let arr: [u8; 32] = pk_bytes.as_slice().try_into().ok()?;
+        VerifyingKey::from_bytes(&arr).ok()
+    }
+
+    /// Verifies a base64 Ed25519 signature against a base64 public key.
+    pub fn verify_ed25519_b64(pk_b64: &str, msg: &[u8], sig_b64: &str) -> bool {
+        let vk = match Self::parse_verifying_key_b64(pk_b64) { Some(v) => v, None => return false };
+
+        let sig_bytes = match B64.decode(sig_b64) { Ok(b) => b, Err(_) => return false };
+        let arr: [u8; 64] = match sig_bytes.as_slice().try_into() { Ok(a) => a, Err(_) => return false };
+        let sig = Signature::from_bytes(&arr);
+
+        vk.verify(msg, &sig).is_ok()
+    }
+
     pub fn now_rfc3339(meta: &ExecMeta) -> String {
         meta.execution_time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
     }
 
-    fn parse_ts(s: &str) -> Option<DateTime<Utc>> {
+    pub fn parse_ts(s: &str) -> Option<DateTime<Utc>> {
         DateTime::parse_from_rfc3339(s).ok().map(|dt| dt.with_timezone(&Utc))
     }
 
-    fn time_bucket(ts: &str, unit: &str) -> String {
-        let dt = match Self::parse_ts(ts) { Some(d) => d, None => return "".into() };
-        match unit {
-            "minute" => dt.format("%Y-%m-%dT%H:%M").to_string(),
-            "hour" => dt.format("%Y-%m-%dT%H").to_string(),
-            "day" => dt.format("%Y-%m-%d").to_string(),
-            _ => "".into()
-        }
-    }
-
     // --------------------------
     // Path
     // --------------------------
@@ -112,20 +490,63 @@ impl Kernel {
     // --------------------------
     // Expression eval (deterministic)
     // --------------------------
+    /// Evaluates against the builtins ([`functions::builtin_registry`]).
     pub fn eval_expr(expr: &Expr, ctx: &Value, meta: &ExecMeta) -> Value {
+        Self::eval(expr, ctx, None, meta, &EvalState::default())
+    }
+
+    /// [`Kernel::eval_expr`] against `functions`, charging `fuel`; `Null` once it runs out.
+    pub fn eval_expr_metered(expr: &Expr, ctx: &Value, meta: &ExecMeta, fuel: &FuelMeter, functions: &dyn FunctionRegistry) -> Value {
+        Self::eval(expr, ctx, None, meta, &EvalState::new(functions, false, fuel.clone()))
+    }
+
+    /// Fuel limit for a chip or program: its own `fuel`, capped by the global
+    /// `UBL_FUEL_LIMIT` (default [`DEFAULT_FUEL_LIMIT`]).
+    pub fn fuel_limit(own: Option<u64>) -> u64 {
+        let global = std::env::var("UBL_FUEL_LIMIT").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_FUEL_LIMIT);
+        own.map_or(global, |l| l.min(global))
+    }
+
+    /// Like [`Kernel::eval_expr`], but returns the first evaluation error instead
+    /// of the fallback value.
+    pub fn try_eval_expr(expr: &Expr, ctx: &Value, meta: &ExecMeta) -> Result<Value, EvalError> {
+        let st = EvalState::default();
+        let v = Self::eval(expr, ctx, None, meta, &st);
+        match st.into_parts().0 { Some(e) => Err(e), None => Ok(v) }
+    }
+
+    // Errors never change the value: each site records its cause in `st` and
+    // continues with the fallback, so non-strict chips evaluate as they always did.
+    fn eval(expr: &Expr, ctx: &Value, scope: Option<&Scope>, meta: &ExecMeta, st: &EvalState) -> Value {
+        if !st.admit(expr) { return Value::Null; }
+        st.enter();
+        let v = Self::eval_node(expr, ctx, scope, meta, st);
+        if st.trace.is_some() { let (node, label) = trace_label(expr); st.exit(node, label, &v); }
+        st.leave();
+        v
+    }
+
+    fn eval_node(expr: &Expr, ctx: &Value, scope: Option<&Scope>, meta: &ExecMeta, st: &EvalState) -> Value {
         match expr {
             Expr::Literal { value } => value.clone(),
             Expr::Path { path, fallback } => {
-                Self::resolve_path(ctx, path).or_else(|| fallback.clone()).unwrap_or(Value::Null)
+                let bound = path.first().and_then(|root| scope.and_then(|s| s.lookup(root)));
+                let v = match bound { Some(v) => Self::resolve_path(v, &path[1..]), None => Self::resolve_path(ctx, path) };
+                v.or_else(|| fallback.clone()).unwrap_or(Value::Null)
             }
             Expr::Compare { op, left, right } => {
-                let l = Self::eval_expr(left, ctx, meta);
-                let r = Self::eval_expr(right, ctx, meta);
+                let l = Self::eval(left, ctx, scope, meta, st);
+                let r = Self::eval(right, ctx, scope, meta, st);
+                if let Some(e) = Self::compare_error(op, &l, &r) { st.fail(e); }
                 json!(Self::compare_strict(op, &l, &r))
             }
             Expr::Logic { op, args } => {
+                let name = match op { LogicOp::And => "and", LogicOp::Or => "or", LogicOp::Not => "not" };
+                if matches!(op, LogicOp::Not) && args.len() != 1 {
+                    st.fail(EvalError::Arity { function: name.into(), expected: 1, found: args.len() });
+                }
                 let vals: Vec<bool> = args.iter()
-                    .map(|a| Self::eval_expr(a, ctx, meta).as_bool().unwrap_or(false))
+                    .map(|a| Self::as_bool(name, &Self::eval(a, ctx, scope, meta, st), st))
                     .collect();
                 let res = match op {
                     LogicOp::And => vals.iter().all(|&x| x),
@@ -135,129 +556,380 @@ impl Kernel {
                 json!(res)
             }
             Expr::Call { function, args } => {
-                let vals: Vec<Value> = args.iter().map(|a| Self::eval_expr(a, ctx, meta)).collect();
-                match function.as_str() {
-                    // time
-                    "now" => json!(Self::now_rfc3339(meta)),
-                    "before" => {
-                        let a = vals.get(0).and_then(|v| v.as_str()).unwrap_or("");
-                        let b = vals.get(1).and_then(|v| v.as_str()).unwrap_or("");
-                        let adt = Self::parse_ts(a);
-                        let bdt = Self::parse_ts(b);
-                        json!(adt.zip(bdt).map(|(x,y)| x < y).unwrap_or(false))
-                    }
-                    "after" => {
-                        let a = vals.get(0).and_then(|v| v.as_str()).unwrap_or("");
-                        let b = vals.get(1).and_then(|v| v.as_str()).unwrap_or("");
-                        let adt = Self::parse_ts(a);
-                        let bdt = Self::parse_ts(b);
-                        json!(adt.zip(bdt).map(|(x,y)| x > y).unwrap_or(false))
-                    }
-                    "age" => {
-                        let a = vals.get(0).and_then(|v| v.as_str()).unwrap_or("");
-                        let adt = Self::parse_ts(a);
-                        json!(adt.map(|x| (meta.execution_time - x).num_seconds()).unwrap_or(0))
-                    }
-                    "time_bucket" => {
-                        let ts = vals.get(0).and_then(|v| v.as_str()).unwrap_or("");
-                        let unit = vals.get(1).and_then(|v| v.as_str()).unwrap_or("");
-                        json!(Self::time_bucket(ts, unit))
-                    }
+                let vals: Vec<Value> = args.iter().map(|a| Self::eval(a, ctx, scope, meta, st)).collect();
+                if !st.charge(call_fuel(&vals)) { return Value::Null; }
+                let Some(f) = st.functions.get(function) else {
+                    st.fail(EvalError::UnknownFunction(function.clone()));
+                    return Value::Null;
+                };
+                // the signature is checked up front; the call still runs, so lenient
+                // chips get the function's own fallback
+                let spec = &f.spec;
+                if vals.len() < spec.min_arity() {
+                    st.fail(EvalError::MissingArgument { function: function.clone(), index: vals.len() });
+                } else if let Some(max) = spec.max_arity().filter(|&m| vals.len() > m) {
+                    st.fail(EvalError::Arity { function: function.clone(), expected: max, found: vals.len() });
+                }
+                let mismatch = vals.iter().enumerate()
+                    .find_map(|(i, v)| spec.param(i).filter(|t| !t.accepts(v)).map(|t| (i, t, v)));
+                if let Some((i, t, v)) = mismatch {
+                    st.fail(EvalError::TypeMismatch { at: format!("{}#{}", function, i), expected: t.name(), found: type_name(v) });
+                }
+                (f.call)(&vals, &CallContext { context: ctx, meta, st })
+            }
+            Expr::Collection { op, over, var, body } => {
+                Self::eval_collection(*op, over, var, body.as_deref(), ctx, scope, meta, st)
+                    .map(|(v, _)| v)
+                    .unwrap_or_else(|e| { st.fail(e); Value::Null })
+            }
+            Expr::Let { name, value, body } => {
+                let v = Self::eval(value, ctx, scope, meta, st);
+                Self::eval(body, ctx, Some(&Scope { name, value: &v, parent: scope }), meta, st)
+            }
+            Expr::If { cond, then, otherwise } => {
+                let branch = if Self::as_bool("if", &Self::eval(cond, ctx, scope, meta, st), st) { then } else { otherwise };
+                Self::eval(branch, ctx, scope, meta, st)
+            }
+            Expr::Chip { reference } => json!(Self::eval_chip_ref(reference, ctx, meta, st).is_some_and(|p| p.final_result == 1)),
+        }
+    }
 
-                    // string
-                    "lower" => json!(vals.get(0).and_then(|v| v.as_str()).unwrap_or("").to_lowercase()),
-                    "upper" => json!(vals.get(0).and_then(|v| v.as_str()).unwrap_or("").to_uppercase()),
-                    "starts_with" => {
-                        let s = vals.get(0).and_then(|v| v.as_str()).unwrap_or("");
-                        let p = vals.get(1).and_then(|v| v.as_str()).unwrap_or("");
-                        json!(s.starts_with(p))
-                    }
-                    "ends_with" => {
-                        let s = vals.get(0).and_then(|v| v.as_str()).unwrap_or("");
-                        let p = vals.get(1).and_then(|v| v.as_str()).unwrap_or("");
-                        json!(s.ends_with(p))
-                    }
+    /// The kernel builtins, as registered by [`functions::Functions::builtins`].
+    pub(crate) fn call_builtin(function: &str, vals: &[Value], cx: &CallContext) -> Value {
+        let (ctx, meta, st) = (cx.context, cx.meta, cx.st);
+        let s = |i| Self::arg_str(function, vals, i, st);
+        let n = |i| Self::arg_f64(function, vals, i, st);
+        let ts = |i| { let v = s(i); Self::parse_ts(v).or_else(|| { st.fail(EvalError::InvalidTimestamp { function: function.into(), value: v.into() }); None }) };
+        // optional time zone argument, default UTC
+        let tz = |i: usize| {
+            if vals.len() <= i { return Zone::default(); }
+            calendar::zone(s(i)).unwrap_or_else(|| {
+                st.fail(EvalError::InvalidArgument { function: function.into(), detail: format!("time zone {:?}", s(i)) });
+                Zone::default()
+            })
+        };
+        let mismatch = |expected| st.fail(EvalError::TypeMismatch {
+            at: format!("{}#0", function), expected, found: type_name(vals.first().unwrap_or(&Value::Null)),
+        });
 
-                    // collections
-                    "length" | "len" => {
-                        if let Some(a) = vals.get(0).and_then(|v| v.as_array()) { json!(a.len()) }
-                        else if let Some(s) = vals.get(0).and_then(|v| v.as_str()) { json!(s.chars().count()) }
-                        else { json!(0) }
-                    }
-                    "empty" => json!(vals.get(0).and_then(|v| v.as_array()).map(|a| a.is_empty()).unwrap_or(true)),
-                    "contains" => {
-                        if let (Some(s), Some(sub)) = (vals.get(0).and_then(|v| v.as_str()), vals.get(1).and_then(|v| v.as_str())) {
-                            json!(s.contains(sub))
-                        } else if let Some(arr) = vals.get(0).and_then(|v| v.as_array()) {
-                            json!(arr.contains(vals.get(1).unwrap_or(&Value::Null)))
-                        } else { json!(false) }
-                    }
+        match function {
+            // time
+            "now" => json!(Self::now_rfc3339(meta)),
+            "before" => json!(ts(0).zip(ts(1)).map(|(x,y)| x < y).unwrap_or(false)),
+            "after" => json!(ts(0).zip(ts(1)).map(|(x,y)| x > y).unwrap_or(false)),
+            "age" => json!(ts(0).map(|x| (meta.execution_time - x).num_seconds()).unwrap_or(0)),
+            "time_bucket" => {
+                let (at, unit, z) = (ts(0), s(1), tz(2));
+                let b = at.and_then(|at| calendar::bucket(at, unit, z));
+                if b.is_none() && at.is_some() {
+                    st.fail(EvalError::InvalidArgument { function: function.into(), detail: format!("unit {:?}", unit) });
+                }
+                json!(b.unwrap_or_default())
+            }
+            "add_duration" => {
+                let d = calendar::parse_duration(s(1));
+                if d.is_none() { st.fail(EvalError::InvalidArgument { function: function.into(), detail: format!("duration {:?}", s(1)) }); }
+                let r = ts(0).zip(d).and_then(|(at, d)| calendar::add_duration(at, &d));
+                json!(r.map(calendar::rfc3339).unwrap_or_default())
+            }
+            "days_between" => { let z = tz(2); json!(ts(0).zip(ts(1)).map(|(a, b)| calendar::days_between(a, b, z)).unwrap_or(0)) }
+            "start_of_month" => { let z = tz(1); json!(ts(0).map(|at| calendar::rfc3339(calendar::start_of_month(at, z))).unwrap_or_default()) }
+            "weekday" => { let z = tz(1); json!(ts(0).map(|at| calendar::weekday(at, z)).unwrap_or(0)) }
+            "is_business_day" | "add_business_days" => {
+                let ci = if function == "is_business_day" { 1 } else { 2 };
+                let (at, z) = (ts(0), tz(ci + 1));
+                let cal = calendar::calendar(Self::arg(function, vals, ci, st));
+                if cal.is_none() { st.fail(EvalError::InvalidArgument { function: function.into(), detail: "calendar".into() }); }
+                let cal = cal.unwrap_or_else(|| calendar::Calendar { holidays: Default::default(), weekend: vec![6, 7] });
+                if function == "is_business_day" {
+                    return json!(at.is_some_and(|at| calendar::is_business_day(z.local(at).date(), &cal)));
+                }
+                let days = Self::arg(function, vals, 1, st);
+                let Some(n) = days.as_i64() else {
+                    st.fail(EvalError::TypeMismatch { at: format!("{}#1", function), expected: "integer", found: type_name(days) });
+                    return json!("");
+                };
+                let r = at.map(|at| calendar::add_business_days(at, n, &cal, z));
+                if let Some(None) = r {
+                    st.fail(EvalError::InvalidArgument { function: function.into(), detail: format!("more than {} days", calendar::MAX_CALENDAR_SCAN) });
+                }
+                json!(r.flatten().map(calendar::rfc3339).unwrap_or_default())
+            }
 
-                    // numbers
-                    "abs" => json!(Self::as_f64(vals.get(0).unwrap_or(&Value::Null)).map(|n| n.abs()).unwrap_or(0.0)),
-                    "floor" => json!(Self::as_f64(vals.get(0).unwrap_or(&Value::Null)).map(|n| n.floor()).unwrap_or(0.0)),
-                    "ceil" => json!(Self::as_f64(vals.get(0).unwrap_or(&Value::Null)).map(|n| n.ceil()).unwrap_or(0.0)),
-                    "min" => {
-                        let a = Self::as_f64(vals.get(0).unwrap_or(&Value::Null)).unwrap_or(0.0);
-                        let b = Self::as_f64(vals.get(1).unwrap_or(&Value::Null)).unwrap_or(0.0);
-                        json!(a.min(b))
-                    }
-                    "max" => {
-                        let a = Self::as_f64(vals.get(0).unwrap_or(&Value::Null)).unwrap_or(0.0);
-                        let b = Self::as_f64(vals.get(1).unwrap_or(&Value::Null)).unwrap_or(0.0);
-                        json!(a.max(b))
-                    }
-                    "add" => {
-                        let a = Self::as_f64(vals.get(0).unwrap_or(&Value::Null)).unwrap_or(0.0);
-                        let b = Self::as_f64(vals.get(1).unwrap_or(&Value::Null)).unwrap_or(0.0);
-                        json!(a + b)
-                    }
-                    "sub" => {
-                        let a = Self::as_f64(vals.get(0).unwrap_or(&Value::Null)).unwrap_or(0.0);
-                        let b = Self::as_f64(vals.get(1).unwrap_or(&Value::Null)).unwrap_or(0.0);
-                        json!(a - b)
-                    }
-                    "div" => {
-                        let a = Self::as_f64(vals.get(0).unwrap_or(&Value::Null)).unwrap_or(0.0);
-                        let b = Self::as_f64(vals.get(1).unwrap_or(&Value::Null)).unwrap_or(0.0);
-                        if b == 0.0 { json!(0.0) } else { json!(a / b) }
-                    }
+            // string (operands are NFC-normalized first)
+            "lower" => json!(canon::nfc(s(0)).to_lowercase()),
+            "upper" => json!(canon::nfc(s(0)).to_uppercase()),
+            "nfc" => json!(canon::nfc(s(0))),
+            "nfkc" => json!(canon::nfkc(s(0))),
+            "casefold" => json!(canon::casefold(s(0))),
+            "starts_with" => json!(canon::nfc(s(0)).starts_with(&canon::nfc(s(1)))),
+            "ends_with" => json!(canon::nfc(s(0)).ends_with(&canon::nfc(s(1)))),
+            "trim" => json!(canon::nfc(s(0)).trim()),
+            "substr" => {
+                let len = if vals.len() > 2 { Some(n(2) as i64) } else { None };
+                json!(text::substr(&canon::nfc(s(0)), n(1) as i64, len))
+            }
+            "matches" | "split" | "replace" | "concat" | "format" | "to_number" => {
+                let r = match function {
+                    "matches" => text::matches(&canon::nfc(s(0)), s(1)).map(|m| json!(m)),
+                    "split" => text::split(&canon::nfc(s(0)), &canon::nfc(s(1))).map(|p| json!(p)),
+                    "replace" => text::replace(&canon::nfc(s(0)), &canon::nfc(s(1)), &canon::nfc(s(2))).map(|x| json!(x)),
+                    "concat" => text::concat(vals).map(|x| json!(x)),
+                    "format" => text::format(s(0), vals.get(1..).unwrap_or_default()).map(|x| json!(x)),
+                    _ => text::to_number(Self::arg(function, vals, 0, st)),
+                };
+                r.unwrap_or_else(|detail| {
+                    st.fail(EvalError::InvalidArgument { function: function.into(), detail });
+                    match function { "matches" => json!(false), "split" => json!([]), "to_number" => Value::Null, _ => json!("") }
+                })
+            }
+            "to_string" => json!(text::to_string(Self::arg(function, vals, 0, st))),
 
-                    // crypto
-                    "sha256" => {
-                        let s = vals.get(0).and_then(|v| v.as_str()).unwrap_or("");
-                        json!(Self::sha256_hex(s.as_bytes()))
-                    }
-                    "verify_ed25519" => {
-                        let pk_b64 = vals.get(0).and_then(|v| v.as_str()).unwrap_or("");
-                        let msg = vals.get(1).and_then(|v| v.as_str()).unwrap_or("");
-                        let sig_b64 = vals.get(2).and_then(|v| v.as_str()).unwrap_or("");
+            // collections
+            "length" | "len" => match vals.first() {
+                Some(Value::Array(a)) => json!(a.len()),
+                Some(Value::String(x)) => json!(x.chars().count()),
+                _ => { mismatch("array or string"); json!(0) }
+            },
+            "empty" => match vals.first() {
+                Some(Value::Array(a)) => json!(a.is_empty()),
+                _ => { mismatch("array"); json!(true) }
+            },
+            "contains" => {
+                if let (Some(x), Some(sub)) = (vals.first().and_then(|v| v.as_str()), vals.get(1).and_then(|v| v.as_str())) {
+                    json!(canon::nfc(x).contains(&canon::nfc(sub)))
+                } else if let Some(arr) = vals.first().and_then(|v| v.as_array()) {
+                    let needle = Self::arg(function, vals, 1, st);
+                    json!(arr.iter().any(|x| canon::value_eq(x, needle)))
+                } else {
+                    if vals.first().is_some_and(|v| v.is_string()) { s(1); } else { mismatch("array or string"); }
+                    json!(false)
+                }
+            }
 
-                        let pk_bytes = match B64.decode(pk_b64) { Ok(b) => b, Err(_) => return json!(false) };
-                        let arr: [u8; 32] = match pk_bytes.as_slice().try_into() { Ok(a) => a, Err(_) => return json!(false) };
-                        let vk = match VerifyingKey::from_bytes(&arr) { Ok(v) => v, Err(_) => return json!(false) };
+            // numbers
+            "abs" => json!(n(0).abs()),
+            "floor" => json!(n(0).floor()),
+            "ceil" => json!(n(0).ceil()),
+            "min" => json!(n(0).min(n(1))),
+            "max" => json!(n(0).max(n(1))),
+            "add" => json!(n(0) + n(1)),
+            "sub" => json!(n(0) - n(1)),
+            "div" => {
+                let (a, b) = (n(0), n(1));
+                if b == 0.0 { st.fail(EvalError::DivisionByZero); json!(0.0) } else { json!(a / b) }
+            }
 
-                        let sig_bytes = match B64.decode(sig_b64) { Ok(b) => b, Err(_) => return json!(false) };
-                        let arr: [u8; 64] = match sig_bytes.as_slice().try_into() { Ok(a) => a, Err(_) => return json!(false) }; 
-                        let sig = Signature::from_bytes(&arr);
+            // crypto
+            "sha256" => json!(Self::sha256_hex(s(0).as_bytes())),
+            "verify_ed25519" => json!(Self::verify_ed25519_b64(s(0), s(1).as_bytes(), s(2))),
 
-                        json!(vk.verify(msg.as_bytes(), &sig).is_ok())
-                    }
+            "threshold_signatures" => {
+                let (ok, signers, e) = Self::threshold_signatures(vals, ctx);
+                if let Some(detail) = e.clone() { st.fail(EvalError::InvalidArgument { function: function.into(), detail }); }
+                st.record_signers(ok, GateValues { left: Some(json!(signers)), right: vals.get(2).cloned(), ..Default::default() }, e);
+                json!(ok)
+            }
 
-                    _ => Value::Null
+            // behavioral fingerprint (first argument: a bound fingerprint)
+            "amount_zscore" | "hour_anomaly" | "new_counterparty" | "op_type_anomaly" => {
+                let fp: Fingerprint = match vals.first().map(|v| serde_json::from_value(v.clone())) {
+                    Some(Ok(fp)) => fp,
+                    _ => { mismatch("fingerprint"); return Value::Null; }
+                };
+                match function {
+                    "amount_zscore" => json!(fingerprint::amount_zscore(&fp, n(1))),
+                    // optional timestamp, default: execution time
+                    "hour_anomaly" => json!(fingerprint::hour_anomaly(&fp, if vals.len() > 1 { ts(1).unwrap_or(meta.execution_time) } else { meta.execution_time })),
+                    "new_counterparty" => json!(fingerprint::new_counterparty(&fp, s(1))),
+                    _ => json!(fingerprint::op_type_anomaly(&fp, s(1))),
                 }
             }
+
+            _ => { st.fail(EvalError::UnknownFunction(function.into())); Value::Null }
+        }
+    }
+
+    // --------------------------
+    // Checked arguments (record the error, return the fallback)
+    // --------------------------
+    fn arg<'v>(function: &str, vals: &'v [Value], i: usize, st: &EvalState) -> &'v Value {
+        vals.get(i).unwrap_or_else(|| {
+            st.fail(EvalError::MissingArgument { function: function.into(), index: i });
+            &NULL
+        })
+    }
+
+    fn arg_str<'v>(function: &str, vals: &'v [Value], i: usize, st: &EvalState) -> &'v str {
+        let v = Self::arg(function, vals, i, st);
+        v.as_str().unwrap_or_else(|| {
+            st.fail(EvalError::TypeMismatch { at: format!("{}#{}", function, i), expected: "string", found: type_name(v) });
+            ""
+        })
+    }
+
+    fn arg_f64(function: &str, vals: &[Value], i: usize, st: &EvalState) -> f64 {
+        let v = Self::arg(function, vals, i, st);
+        Self::as_f64(v).unwrap_or_else(|| {
+            st.fail(EvalError::TypeMismatch { at: format!("{}#{}", function, i), expected: "number", found: type_name(v) });
+            0.0
+        })
+    }
+
+    fn as_bool(at: &str, v: &Value, st: &EvalState) -> bool {
+        v.as_bool().unwrap_or_else(|| {
+            st.fail(EvalError::TypeMismatch { at: at.into(), expected: "boolean", found: type_name(v) });
+            false
+        })
+    }
+
+    // --------------------------
+    // Collections (bounded, with a bound element variable)
+    // --------------------------
+    /// Returns the value and, for `all`/`any`, the deciding element.
+    #[allow(clippy::too_many_arguments)]
+    fn eval_collection(
+        op: CollectionOp, over: &Expr, var: &str, body: Option<&Expr>, ctx: &Value, scope: Option<&Scope>, meta: &ExecMeta, st: &EvalState,
+    ) -> Result<(Value, Option<ElementWitness>), EvalError> {
+        let items = match Self::eval(over, ctx, scope, meta, st) { Value::Array(a) => a, _ => return Err(EvalError::NotAnArray) };
+        if items.len() > MAX_COLLECTION_LEN { return Err(EvalError::CollectionTooLarge); }
+        // traced as one `element` node per visited element
+        let apply = |(i, x): (usize, &Value)| match body {
+            _ if !st.charge(FUEL_ELEMENT) => Value::Null,
+            Some(b) => {
+                st.enter();
+                let v = Self::eval(b, ctx, Some(&Scope { name: var, value: x, parent: scope }), meta, st);
+                st.exit("element", format!("{}[{}]", var, i), &v);
+                v
+            }
+            None => x.clone(),
+        };
+        let holds = |e: (usize, &Value)| apply(e).as_bool() == Some(true);
+        let witness = |(index, x): (usize, &Value)| ElementWitness { index, value: x.clone() };
+        let each = || items.iter().enumerate();
+
+        Ok(match op {
+            CollectionOp::All => match each().find(|e| !holds(*e)) {
+                Some(w) => (json!(false), Some(witness(w))),
+                None => (json!(true), None),
+            },
+            CollectionOp::Any => match each().find(|e| holds(*e)) {
+                Some(w) => (json!(true), Some(witness(w))),
+                None => (json!(false), None),
+            },
+            CollectionOp::Filter => (Value::Array(each().filter(|e| holds(*e)).map(|(_, x)| x.clone()).collect()), None),
+            CollectionOp::Map => (Value::Array(each().map(apply).collect()), None),
+            CollectionOp::Count if body.is_none() => (json!(items.len()), None),
+            CollectionOp::Count => (json!(each().filter(|e| holds(*e)).count()), None),
+            // integers sum to an integer (checked), anything else to a float
+            CollectionOp::Sum => {
+                let (mut int, mut float) = (Some(0i64), 0.0);
+                for e in each() {
+                    let v = apply(e);
+                    let n = Self::as_f64(&v).ok_or(EvalError::TypeMismatch { at: "sum".into(), expected: "number", found: type_name(&v) })?;
+                    int = int.zip(v.as_i64()).and_then(|(a, b)| a.checked_add(b));
+                    float += n;
+                }
+                (int.map(|i| json!(i)).unwrap_or(json!(float)), None)
+            }
+            // first occurrence wins; equality on the JCS form with strings in NFC
+            CollectionOp::Unique => {
+                let mut seen = std::collections::HashSet::new();
+                let out = each().map(apply).filter(|v| {
+                    let key = match v { Value::String(s) => Self::jcs_string(&canon::nfc(s)), _ => Self::jcs_string(v) };
+                    seen.insert(key)
+                }).collect();
+                (Value::Array(out), None)
+            }
+        })
+    }
+
+    // --------------------------
+    // Client-signed requests
+    // --------------------------
+    /// Bytes a client signs: `JCS({program, inputs, target_version, nonce, expiry})`.
+    pub fn client_request_message(req: &ExecReq, auth: &ClientSignature) -> String {
+        Self::jcs_string(&json!({
+            "program": req.program,
+            "inputs": req.inputs,
+            "target_version": req.target_version,
+            "nonce": auth.nonce,
+            "expiry": auth.expiry,
+        }))
+    }
+
+    // --------------------------
+    // Multisig approvals
+    // --------------------------
+    pub fn operation_hash(op: &Operation) -> String {
+        Self::jcs_hash(op)
+    }
+
+    /// `threshold_signatures(approvals, signer_set, k)` against `operation.hash` in
+    /// the context. Returns `(passed, verified signer ids, error)`.
+    ///
+    /// `signer_set` is `{id: public_key_b64}` or `[{id, public_key}]`. Each signer
+    /// (and each public key) counts once; an approval set naming the same signer or
+    /// key twice is rejected outright. Approvals from unknown signers or with bad
+    /// signatures are not counted.
+    fn threshold_signatures(vals: &[Value], ctx: &Value) -> (bool, Vec<String>, Option<String>) {
+        let op_hash = match ctx.pointer("/operation/hash").and_then(|v| v.as_str()) {
+            Some(h) => h,
+            None => return (false, vec![], Some("no_operation_bound".into())),
+        };
+        let approvals: Vec<Approval> = match vals.first().map(|v| serde_json::from_value(v.clone())) {
+            Some(Ok(a)) => a,
+            _ => return (false, vec![], Some("approvals_malformed".into())),
+        };
+        let signer_set: Vec<(String, String)> = match vals.get(1) {
+            Some(Value::Object(m)) => m.iter().filter_map(|(id, pk)| Some((id.clone(), pk.as_str()?.to_string()))).collect(),
+            Some(Value::Array(a)) => a.iter().filter_map(|e| Some((e.get("id")?.as_str()?.to_string(), e.get("public_key")?.as_str()?.to_string()))).collect(),
+            _ => return (false, vec![], Some("signer_set_malformed".into())),
+        };
+        let k = vals.get(2).and_then(|v| v.as_u64()).unwrap_or(0);
+
+        let mut seen_ids = std::collections::HashSet::new();
+        let mut seen_keys = std::collections::HashSet::new();
+        let mut signers = vec![];
+        for a in &approvals {
+            if !seen_ids.insert(a.signer_id.as_str()) {
+                return (false, vec![], Some(format!("duplicate_signer: {}", a.signer_id)));
+            }
+            let pk = match signer_set.iter().find(|(id, _)| id == &a.signer_id) { Some((_, pk)) => pk, None => continue };
+            if !Self::verify_ed25519_b64(pk, op_hash.as_bytes(), &a.signature) { continue; }
+            if !seen_keys.insert(pk.as_str()) {
+                return (false, vec![], Some(format!("duplicate_signer: {}", a.signer_id)));
+            }
+            signers.push(a.signer_id.clone());
+        }
+        signers.sort();
+
+        (k > 0 && signers.len() as u64 >= k, signers, None)
+    }
+
+    /// Operands `compare_strict` cannot order or search (it answers `false`).
+    fn compare_error(op: &CompareOp, l: &Value, r: &Value) -> Option<EvalError> {
+        let at = || compare_symbol(op);
+        match op {
+            CompareOp::Gt | CompareOp::Lt | CompareOp::Ge | CompareOp::Le => {
+                let bad = [l, r].into_iter().find(|v| Self::as_f64(v).is_none())?;
+                Some(EvalError::TypeMismatch { at: at(), expected: "number", found: type_name(bad) })
+            }
+            CompareOp::In if !(r.is_array() || (l.is_string() && r.is_string())) => {
+                Some(EvalError::TypeMismatch { at: at(), expected: "array or string", found: type_name(r) })
+            }
+            _ => None,
         }
     }
 
     fn compare_strict(op: &CompareOp, l: &Value, r: &Value) -> bool {
         match op {
-            CompareOp::Eq => l == r,
-            CompareOp::Ne => l != r,
+            CompareOp::Eq => canon::value_eq(l, r),
+            CompareOp::Ne => !canon::value_eq(l, r),
             CompareOp::Exists => !l.is_null(),
             CompareOp::In => {
-                if let Some(arr) = r.as_array() { arr.contains(l) }
-                else if let (Some(ls), Some(rs)) = (l.as_str(), r.as_str()) { rs.contains(ls) }
+                if let Some(arr) = r.as_array() { arr.iter().any(|x| canon::value_eq(l, x)) }
+                else if let (Some(ls), Some(rs)) = (l.as_str(), r.as_str()) { canon::nfc(rs).contains(&canon::nfc(ls)) }
                 else { false }
             }
             CompareOp::Gt | CompareOp::Lt | CompareOp::Ge | CompareOp::Le => {
@@ -290,16 +962,61 @@ impl Kernel {
     // --------------------------
     // Gate evaluation with evidence
     // --------------------------
-    fn eval_gate_expr(expr: &Expr, ctx: &Value, meta: &ExecMeta) -> (bool, GateValues, Option<String>) {
+    fn eval_gate_expr(expr: &Expr, ctx: &Value, scope: Option<&Scope>, meta: &ExecMeta, st: &EvalState) -> (bool, GateValues, Option<String>) {
+        if !st.admit(expr) { return (false, GateValues::default(), None); }
+        st.enter();
+        let r = Self::gate_node(expr, ctx, scope, meta, st);
+        if st.trace.is_some() { let (node, label) = trace_label(expr); st.exit(node, label, &json!(r.0)); }
+        st.leave();
+        r
+    }
+
+    fn gate_node(expr: &Expr, ctx: &Value, scope: Option<&Scope>, meta: &ExecMeta, st: &EvalState) -> (bool, GateValues, Option<String>) {
         match expr {
             Expr::Compare { op, left, right } => {
-                let l = Self::eval_expr(left, ctx, meta);
-                let r = Self::eval_expr(right, ctx, meta);
+                let l = Self::eval(left, ctx, scope, meta, st);
+                let r = Self::eval(right, ctx, scope, meta, st);
+                if let Some(e) = Self::compare_error(op, &l, &r) { st.fail(e); }
                 let ok = Self::compare_strict(op, &l, &r);
-                (ok, GateValues { left: Some(l), right: Some(r) }, None)
+                (ok, GateValues { left: Some(l), right: Some(r), ..Default::default() }, None)
             }
+            // evidence: the deciding element, with the body's own evidence for it
+            Expr::Collection { op: op @ (CollectionOp::All | CollectionOp::Any), over, var, body } => {
+                match Self::eval_collection(*op, over, var, body.as_deref(), ctx, scope, meta, st) {
+                    Err(e) => (false, GateValues::default(), Some(e.to_string())),
+                    Ok((v, element)) => {
+                        let mut values = match (&element, body) {
+                            // re-evaluated for evidence only: already traced and checked above
+                            (Some(w), Some(b)) => {
+                                let scope = Scope { name: var, value: &w.value, parent: scope };
+                                Self::eval_gate_expr(b, ctx, Some(&scope), meta, &st.evidence()).1
+                            }
+                            _ => GateValues::default(),
+                        };
+                        values.element = element;
+                        (v.as_bool() == Some(true), values, None)
+                    }
+                }
+            }
+            // evidence comes from the body / the branch taken
+            Expr::Let { name, value, body } => {
+                let v = Self::eval(value, ctx, scope, meta, st);
+                Self::eval_gate_expr(body, ctx, Some(&Scope { name, value: &v, parent: scope }), meta, st)
+            }
+            Expr::If { cond, then, otherwise } => {
+                let branch = if Self::as_bool("if", &Self::eval(cond, ctx, scope, meta, st), st) { then } else { otherwise };
+                Self::eval_gate_expr(branch, ctx, scope, meta, st)
+            }
+            // evidence: the referenced chip's failed gates and proof
+            Expr::Chip { reference } => match Self::eval_chip_ref(reference, ctx, meta, st) {
+                None => (false, GateValues::default(), None),
+                Some(p) => {
+                    let values = GateValues { left: Some(json!(p.failed_gates)), subproof: Some(p.proof_hash.clone()), ..Default::default() };
+                    (p.final_result == 1, values, None)
+                }
+            },
             _ => {
-                let v = Self::eval_expr(expr, ctx, meta);
+                let v = Self::eval_node(expr, ctx, scope, meta, st);
                 match v.as_bool() {
                     Some(b) => (b, GateValues::default(), None),
                     None => (false, GateValues::default(), Some("gate_not_boolean".into())),
@@ -311,11 +1028,60 @@ impl Kernel {
     // --------------------------
     // Chip execution -> Proof (+ optional signature)
     // --------------------------
-    pub fn execute_chip_signed(chip: &Chip, ctx: &Value, meta: &ExecMeta, keys: &KeyMaterial) -> Proof {
+    /// Evaluates a chip into an unsealed proof (`proof_hash` empty, unsigned).
+    /// Callers may attach execution-level evidence before [`Kernel::seal_proof`].
+    /// Running out of fuel, or nesting too deep, fails the gate and DENYs; gates
+    /// after the fuel ran out are not evaluated. Each chip referenced through
+    /// `opts.chips` runs on the same context with the fuel left and its sealed,
+    /// unsigned proof goes into `proof.subproofs`. A reference cycle, or nesting
+    /// beyond [`MAX_CHIP_NESTING`], DENYs.
+    pub fn execute_chip(chip: &Chip, ctx: &Value, meta: &ExecMeta, opts: &ExecOptions) -> Proof {
+        let fuel = FuelMeter::new(opts.fuel_limit.unwrap_or_else(|| Self::fuel_limit(chip.fuel)));
+        Self::run_chip(chip, ctx, meta, opts.trace, &fuel, opts.chips, opts.functions, vec![Self::compute_chip_hash(chip)]).0
+    }
+
+    /// Evaluates `chip` on `fuel`; `chain` holds the chips being evaluated,
+    /// outermost first, ending with this one. Returns the unsealed proof and the
+    /// error that denied it outright, if any.
+    #[allow(clippy::too_many_arguments)]
+    fn run_chip(
+        chip: &Chip, ctx: &Value, meta: &ExecMeta, trace: bool, fuel: &FuelMeter,
+        resolver: &dyn ChipResolver, registry: &dyn FunctionRegistry, chain: Vec<Hash>,
+    ) -> (Proof, Option<EvalError>) {
+        let scope = ChipScope { resolver, chain, subproofs: RefCell::new(vec![]) };
+        let mut fatal: Option<EvalError> = None;
         let mut gates: Vec<GateResult> = vec![];
         for g in &chip.gates {
-            let (result, values, error) = Self::eval_gate_expr(&g.expr, ctx, meta);
-            gates.push(GateResult { id: g.id.clone(), result, values, error });
+            if fuel.exhausted() {
+                let error = Some(EvalError::FuelExhausted.to_string());
+                gates.push(GateResult { id: g.id.clone(), result: false, values: GateValues::default(), error, diagnostic: None, trace: None });
+                continue;
+            }
+            let mut st = EvalState::new(registry, trace, fuel.clone());
+            st.chips = Some(&scope);
+            let (mut result, mut values, mut error) = Self::eval_gate_expr(&g.expr, ctx, None, meta, &st);
+            // threshold_signatures anywhere in the gate: the verified signer set (left)
+            // against the threshold (right), unless the gate has evidence of its own
+            if let Some((_, signers, e)) = st.signers.take() {
+                if values.left.is_none() && values.right.is_none() {
+                    values = GateValues { element: values.element, subproof: values.subproof, ..signers };
+                }
+                error = error.or(e);
+            }
+            let (eval_error, trace) = st.into_parts();
+            // strict: the first evaluation error is the gate's cause, and the gate fails;
+            // fatal errors do so for every chip. Otherwise it is only a diagnostic.
+            let mut diagnostic = None;
+            if let Some(e) = eval_error {
+                if chip.strict || e.is_fatal() {
+                    if e.is_fatal() && fatal.is_none() { fatal = Some(e.clone()); }
+                    result = false;
+                    error = Some(e.to_string());
+                } else {
+                    diagnostic = Some(e.to_string());
+                }
+            }
+            gates.push(GateResult { id: g.id.clone(), result, values, error, diagnostic, trace: trace.map(Box::new) });
         }
 
         let passed = gates.iter().filter(|g| g.result).count();
@@ -351,59 +1117,307 @@ impl Kernel {
             }
         };
 
+        // strict: any evaluation error is a DENY, whatever the composition
+        let final_result = if chip.strict && gates.iter().any(|g| g.error.is_some()) { 0 } else { final_result };
+        let fatal = fatal.or_else(|| fuel.exhausted().then_some(EvalError::FuelExhausted));
+        let final_result = if fatal.is_some() { 0 } else { final_result };
+
         let failed_gates: Vec<String> = gates.iter().filter(|g| !g.result).map(|g| g.id.clone()).collect();
 
-        let mut proof = Proof {
+        let proof = Proof {
             chip_hash: chip.hash.clone(),
             evaluated_at: Self::now_rfc3339(meta),
             context_snapshot: ctx.clone(),
             gates,
             failed_gates,
             final_result,
+            functions: functions::referenced(chip.gates.iter().map(|g| &g.expr), registry),
+            fuel: Some(fuel.usage()),
+            subproofs: scope.subproofs.into_inner(),
+            envelopes: vec![],
             proof_hash: "".into(),
             signature: None,
+            key_id: None,
         };
+        (proof, fatal)
+    }
+
+    /// Evaluates a referenced chip on the same context with the fuel left and
+    /// records its sealed proof. `None` (with the error recorded) if the
+    /// reference does not resolve, closes a cycle or nests too deep.
+    fn eval_chip_ref(reference: &str, ctx: &Value, meta: &ExecMeta, st: &EvalState) -> Option<Proof> {
+        let resolved = st.chips.and_then(|scope| scope.resolver.resolve_chip(reference).map(|c| (scope, c)));
+        let Some((scope, mut chip)) = resolved else {
+            st.fail(EvalError::UnknownChip(reference.into()));
+            return None;
+        };
+        chip.hash = Self::compute_chip_hash(&chip);
+        if scope.chain.contains(&chip.hash) { st.fail(EvalError::ChipCycle(reference.into())); return None; }
+        if scope.chain.len() >= MAX_CHIP_NESTING { st.fail(EvalError::DepthExceeded); return None; }
+
+        let mut chain = scope.chain.clone();
+        chain.push(chip.hash.clone());
+        let fuel = FuelMeter::new(st.fuel.remaining());
+        let (mut proof, fatal) = Self::run_chip(&chip, ctx, meta, st.trace.is_some(), &fuel, scope.resolver, st.functions, chain);
+        proof.proof_hash = Self::compute_proof_hash(&proof);
+        st.charge(fuel.used());
+        // what denied the referenced chip outright denies this one too
+        if let Some(e) = fatal { st.fail(e); }
+
+        let mut subproofs = scope.subproofs.borrow_mut();
+        if !subproofs.iter().any(|p| p.proof_hash == proof.proof_hash) { subproofs.push(proof.clone()); }
+        Some(proof)
+    }
+
+    /// Computes `proof_hash` and signs it (if a signing key is configured).
+    pub fn seal_proof(proof: &mut Proof, keys: &KeyMaterial) {
+        proof.proof_hash = Self::compute_proof_hash(proof);
+        proof.signature = keys.sign_b64(proof.proof_hash.as_bytes());
+        proof.key_id = proof.signature.as_ref().and(keys.key_id.clone());
+    }
 
-        // proof_hash excludes signature + proof_hash itself
+    /// proof_hash excludes signature, key_id and proof_hash itself
+    pub fn compute_proof_hash(proof: &Proof) -> String {
         let mut tmp = proof.clone();
         tmp.proof_hash = "".into();
         tmp.signature = None;
-        proof.proof_hash = Self::jcs_hash(&tmp);
+        tmp.key_id = None;
+        Self::jcs_hash(&tmp)
+    }
 
-        if let Some(sig) = keys.sign_b64(proof.proof_hash.as_bytes()) {
-            proof.signature = Some(sig);
-        }
+    /// record_hash excludes signature, key_id and record_hash itself
+    pub fn compute_record_hash(record: &EffectRecord) -> String {
+        let mut tmp = record.clone();
+        tmp.record_hash = "".into();
+        tmp.record_signature = None;
+        tmp.record_key_id = None;
+        Self::jcs_hash(&tmp)
+    }
 
-        proof
+    pub fn sign_record(record: &mut EffectRecord, keys: &KeyMaterial) {
+        record.record_hash = Self::compute_record_hash(record);
+        record.record_signature = keys.sign_b64(record.record_hash.as_bytes());
+        record.record_key_id = record.record_signature.as_ref().and(keys.key_id.clone());
+    }
+
+    /// Recomputes `record_hash` and checks the signature (if any) with the
+    /// historical key named by `record_key_id`.
+    pub fn verify_record(record: &EffectRecord, keys: &KeyMaterial) -> bool {
+        if Self::compute_record_hash(record) != record.record_hash { return false; }
+        match record.record_signature.as_deref() {
+            Some(sig) => keys.verify_at(record.record_hash.as_bytes(), sig, record.record_key_id.as_deref(), &record.timestamp),
+            None => true,
+        }
     }
 
     // --------------------------
     // Proof verification (chip + snapshot + signature)
     // --------------------------
-    pub fn verify_proof(proof: &Proof, chip: &Chip, keys: &KeyMaterial) -> bool {
+    /// Runs every check and reports which ran and which passed. `valid` is true
+    /// only if no check failed; under [`SignaturePolicy::Required`] an unsigned
+    /// proof (or one that cannot be checked for lack of keys) fails. The replay
+    /// resolves chip references through `opts.chips` and must reproduce every
+    /// embedded sub-proof.
+    pub fn verify_proof(proof: &Proof, chip: &Chip, keys: &KeyMaterial, opts: &ExecOptions) -> ProofVerification {
+        let policy = opts.signature_policy;
+        let mut divergence: Option<Divergence> = None;
+        let mut diverge = |stage: VerifyStage, gate: Option<String>, detail: String| {
+            if divergence.is_none() { divergence = Some(Divergence { stage, gate, detail }); }
+        };
+
         // chip hash
-        if proof.chip_hash != chip.hash { return false; }
+        let chip_hash = CheckStatus::from(proof.chip_hash == chip.hash);
+        if chip_hash == CheckStatus::Failed {
+            diverge(VerifyStage::ChipHash, None, format!("proof={} chip={}", proof.chip_hash, chip.hash));
+        }
 
         // recompute proof_hash (exclude signature/proof_hash)
-        let mut tmp = proof.clone();
-        tmp.proof_hash = "".into();
-        tmp.signature = None;
-        let recomputed = Self::jcs_hash(&tmp);
-        if recomputed != proof.proof_hash { return false; }
+        let recomputed_proof_hash = Self::compute_proof_hash(proof);
+        let proof_hash = CheckStatus::from(recomputed_proof_hash == proof.proof_hash);
+        if proof_hash == CheckStatus::Failed {
+            diverge(VerifyStage::ProofHash, None, format!("recorded={} recomputed={}", proof.proof_hash, recomputed_proof_hash));
+        }
 
-        // deterministic re-exec at same evaluated_at time
+        // deterministic re-exec at same evaluated_at time; every gate must replay identically
         let exec_time = DateTime::parse_from_rfc3339(&proof.evaluated_at)
             .map(|dt| dt.with_timezone(&Utc))
             .unwrap_or_else(|_| Utc::now());
         let meta = ExecMeta { tx_id: "verify".into(), execution_time: exec_time };
-        let check = Self::execute_chip_signed(chip, &proof.context_snapshot, &meta, &KeyMaterial { signing: None, verifying: None });
-        if check.final_result != proof.final_result { return false; }
+        let replay = ExecOptions {
+            trace: proof.gates.iter().any(|g| g.trace.is_some()),
+            fuel_limit: proof.fuel.map(|f| f.limit),
+            ..*opts
+        };
+        let check = Self::execute_chip(chip, &proof.context_snapshot, &meta, &replay);
 
-        // signature verify if present and verifying key exists
-        if let (Some(sig_b64), true) = (proof.signature.as_deref(), keys.verifying.is_some()) {
-            if !keys.verify_sig_b64(proof.proof_hash.as_bytes(), sig_b64) { return false; }
+        // functions the chip calls must resolve to the recorded versions (proofs
+        // from before function pinning record none)
+        let functions_match = proof.functions.is_empty() || proof.functions == check.functions;
+        if !functions_match {
+            let changed: Vec<String> = check.functions.iter()
+                .filter(|f| !proof.functions.contains(f))
+                .map(|f| format!("{}@{}", f.name, f.version))
+                .collect();
+            diverge(VerifyStage::Functions, None, format!("changed=[{}]", changed.join(",")));
         }
 
-        true
+        let gates = Self::replay_gates(&proof.gates, &check.gates);
+        if let Some(g) = gates.iter().find(|g| !g.matches) {
+            let detail = match (&g.recorded, &g.replayed) {
+                (Some(r), Some(p)) if r.result != p.result => format!("recorded={} replayed={}", r.result, p.result),
+                (Some(_), Some(_)) => "evidence_differs".into(),
+                (None, _) => "missing_from_proof".into(),
+                (_, None) => "not_in_chip".into(),
+            };
+            diverge(VerifyStage::Gate, Some(g.id.clone()), detail);
+        }
+        // embedded proofs of referenced chips, by content (their recorded hashes
+        // are part of what is checked)
+        let subproofs_match = proof.subproofs.len() == check.subproofs.len()
+            && proof.subproofs.iter().zip(&check.subproofs).all(|(r, p)| Self::same_subproof(r, p));
+        if !subproofs_match {
+            let differs = proof.subproofs.iter().zip(&check.subproofs)
+                .find(|(r, p)| !Self::same_subproof(r, p))
+                .map(|(r, _)| format!("chip={}", r.chip_hash))
+                .unwrap_or_else(|| format!("recorded={} replayed={}", proof.subproofs.len(), check.subproofs.len()));
+            diverge(VerifyStage::Subproofs, None, differs);
+        }
+        if check.final_result != proof.final_result {
+            diverge(VerifyStage::FinalResult, None, format!("recorded={} replayed={}", proof.final_result, check.final_result));
+        }
+        // proofs from before fuel metering record none
+        let fuel_matches = proof.fuel.is_none() || proof.fuel == check.fuel;
+        if !fuel_matches {
+            let used = |f: Option<FuelUsage>| f.map_or(0, |f| f.used);
+            diverge(VerifyStage::Fuel, None, format!("recorded={} replayed={}", used(proof.fuel), used(check.fuel)));
+        }
+        let replay = CheckStatus::from(
+            functions_match && subproofs_match && fuel_matches && gates.iter().all(|g| g.matches) && check.final_result == proof.final_result && check.failed_gates == proof.failed_gates
+        );
+
+        // signature (historical key by key_id)
+        let has_keys = keys.verifying.is_some() || !keys.keyring.keys.is_empty();
+        let signature = match (policy, proof.signature.as_deref()) {
+            (SignaturePolicy::Ignored, _) => CheckStatus::Skipped,
+            (SignaturePolicy::Required, None) => CheckStatus::Failed,
+            (SignaturePolicy::Optional, None) => CheckStatus::Skipped,
+            (SignaturePolicy::Required, Some(_)) if !has_keys => CheckStatus::Failed,
+            (SignaturePolicy::Optional, Some(_)) if !has_keys => CheckStatus::Skipped,
+            (_, Some(sig_b64)) => CheckStatus::from(
+                keys.verify_at(proof.proof_hash.as_bytes(), sig_b64, proof.key_id.as_deref(), &proof.evaluated_at)
+            ),
+        };
+        if signature == CheckStatus::Failed {
+            let detail = if proof.signature.is_none() { "signature_required" } else { "signature_invalid" };
+            diverge(VerifyStage::Signature, None, detail.into());
+        }
+
+        let checks = [chip_hash, proof_hash, replay, signature];
+        ProofVerification {
+            valid: !checks.contains(&CheckStatus::Failed),
+            signature_policy: policy,
+            chip_hash,
+            proof_hash,
+            replay,
+            signature,
+            recomputed_proof_hash,
+            replayed_final_result: check.final_result,
+            gates,
+            divergence,
+        }
+    }
+
+    /// Pairs recorded and replayed gates by position (the chip hash fixes their
+    /// order) and compares them with [`Kernel::same_gate`]. The reported id is
+    /// the recorded gate's.
+    fn replay_gates(recorded: &[GateResult], replayed: &[GateResult]) -> Vec<GateReplay> {
+        let n = recorded.len().max(replayed.len());
+        (0..n).map(|i| {
+            let r = recorded.get(i).cloned();
+            let p = replayed.get(i).cloned();
+            let id = r.as_ref().or(p.as_ref()).map(|g| g.id.clone()).unwrap_or_default();
+            let matches = match (&r, &p) {
+                (Some(a), Some(b)) => Self::same_gate(a, b),
+                _ => false,
+            };
+            GateReplay { id, matches, recorded: r, replayed: p }
+        }).collect()
+    }
+
+    /// Replay equality of two gates: id, result, evidence, trace and the code of
+    /// the error (the text before the first `:`); lenient diagnostics are ignored. The error's wording is not
+    /// part of the contract, so rewording a message keeps old proofs valid; nor
+    /// are embedded proof hashes, which [`Kernel::same_subproof`] compares by content.
+    fn same_gate(a: &GateResult, b: &GateResult) -> bool {
+        let evidence = |g: &GateResult| {
+            let values = GateValues { subproof: None, ..g.values.clone() };
+            (serde_json::to_value(values).ok(), serde_json::to_value(&g.trace).ok())
+        };
+        a.id == b.id && a.result == b.result && error_code(&a.error) == error_code(&b.error) && evidence(a) == evidence(b)
+    }
+
+    /// A recorded embedded proof against its replay: intact (its hash recomputes),
+    /// for the same chip, with the same outcome, fuel and gates, recursively.
+    fn same_subproof(recorded: &Proof, replayed: &Proof) -> bool {
+        Self::compute_proof_hash(recorded) == recorded.proof_hash
+            && recorded.chip_hash == replayed.chip_hash
+            && recorded.final_result == replayed.final_result
+            && recorded.failed_gates == replayed.failed_gates
+            && (recorded.fuel.is_none() || recorded.fuel == replayed.fuel)
+            && recorded.gates.len() == replayed.gates.len()
+            && recorded.gates.iter().zip(&replayed.gates).all(|(a, b)| Self::same_gate(a, b))
+            && recorded.subproofs.len() == replayed.subproofs.len()
+            && recorded.subproofs.iter().zip(&replayed.subproofs).all(|(a, b)| Self::same_subproof(a, b))
+    }
+
+    // --------------------------
+    // Standard envelopes (JWS / COSE_Sign1)
+    // --------------------------
+    pub fn verify_jws_compact(token: &str, keys: &KeyMaterial) -> Result<EnvelopeContent, UblError> {
+        Self::open_envelope(envelope::parse_jws_compact(token)?, keys)
+    }
+
+    pub fn verify_jws_json(jws: &JwsJson, keys: &KeyMaterial) -> Result<EnvelopeContent, UblError> {
+        Self::open_envelope(envelope::parse_jws_json(jws)?, keys)
+    }
+
+    pub fn verify_cose_sign1(bytes: &[u8], keys: &KeyMaterial) -> Result<EnvelopeContent, UblError> {
+        Self::open_envelope(envelope::parse_cose_sign1(bytes)?, keys)
+    }
+
+    pub fn verify_envelope(env: &SignedEnvelope, keys: &KeyMaterial) -> Result<EnvelopeContent, UblError> {
+        match env {
+            SignedEnvelope::JwsCompact { token } => Self::verify_jws_compact(token, keys),
+            SignedEnvelope::JwsJson { jws } => Self::verify_jws_json(jws, keys),
+            SignedEnvelope::CoseSign1 { data_b64 } => {
+                let bytes = B64.decode(data_b64).map_err(|_| UblError::Validation("envelope_malformed: cose_encoding".into()))?;
+                Self::verify_cose_sign1(&bytes, keys)
+            }
+        }
+    }
+
+    /// Checks the envelope signature with the keyring key named by `kid`, valid at
+    /// the artifact's own timestamp. The payload must be canonical JCS.
+    fn open_envelope(opened: Opened, keys: &KeyMaterial) -> Result<EnvelopeContent, UblError> {
+        let value: Value = serde_json::from_slice(&opened.payload)
+            .map_err(|_| UblError::Validation("envelope_malformed: payload_json".into()))?;
+        if Self::jcs_string(&value).as_bytes() != opened.payload.as_slice() {
+            return Err(UblError::Validation("envelope_payload_not_jcs".into()));
+        }
+
+        let content = match opened.content_type.as_deref() {
+            Some(envelope::PROOF_TYPE) => EnvelopeContent::Proof(serde_json::from_value(value)?),
+            Some(envelope::RECORD_TYPE) => EnvelopeContent::Record(serde_json::from_value(value)?),
+            other => return Err(UblError::Validation(format!("envelope_unknown_type: {}", other.unwrap_or("none")))),
+        };
+        let signed_at = match &content {
+            EnvelopeContent::Proof(p) => &p.evaluated_at,
+            EnvelopeContent::Record(r) => &r.timestamp,
+        };
+
+        if !keys.verify_at(&opened.signing_input, &B64.encode(&opened.signature), opened.kid.as_deref(), signed_at) {
+            return Err(UblError::Validation("envelope_signature_invalid".into()));
+        }
+        Ok(content)
     }
 }
@@ -0,0 +1,295 @@
+//! Standard signature envelopes for proofs and records: JWS (compact and
+//! flattened JSON) and COSE_Sign1, both EdDSA over the JCS payload.
+//!
+//! The artifact is carried unchanged, including its own `signature`; the
+//! envelope adds a second, standard signature by the same kernel key, with
+//! `kid` set to the keyring key id. Verification lives in `engine`.
+
+use crate::engine::{Kernel, KeyMaterial};
+use crate::error::UblError;
+use crate::types::{EffectRecord, EnvelopeFormat, JwsJson, Proof, SignedEnvelope};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64URL, engine::general_purpose::STANDARD as B64, Engine as _};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+pub const PROOF_TYPE: &str = "application/ubl-proof+json";
+pub const RECORD_TYPE: &str = "application/ubl-record+json";
+
+const COSE_ALG_EDDSA: i64 = -8;
+const COSE_SIGN1_TAG: u64 = 18;
+
+/// Artifacts that can be wrapped; `TYPE` goes into JWS `typ` / COSE content type.
+pub trait Enveloped: Serialize {
+    const TYPE: &'static str;
+}
+
+impl Enveloped for Proof { const TYPE: &'static str = PROOF_TYPE; }
+impl Enveloped for EffectRecord { const TYPE: &'static str = RECORD_TYPE; }
+
+/// Payload of a verified envelope.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum EnvelopeContent {
+    Proof(Proof),
+    Record(EffectRecord),
+}
+
+/// Parsed envelope, signature not yet checked.
+pub struct Opened {
+    pub kid: Option<String>,
+    pub content_type: Option<String>,
+    pub signing_input: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+// ----------------------
+// Serializers
+// ----------------------
+pub fn wrap<T: Enveloped>(artifact: &T, format: EnvelopeFormat, keys: &KeyMaterial) -> Option<SignedEnvelope> {
+    Some(match format {
+        EnvelopeFormat::JwsCompact => SignedEnvelope::JwsCompact { token: jws_compact(artifact, keys)? },
+        EnvelopeFormat::JwsJson => SignedEnvelope::JwsJson { jws: jws_json(artifact, keys)? },
+        EnvelopeFormat::CoseSign1 => SignedEnvelope::CoseSign1 { data_b64: B64.encode(cose_sign1(artifact, keys)?) },
+    })
+}
+
+/// `BASE64URL(header) . BASE64URL(JCS(artifact)) . BASE64URL(sig)`. None without a signing key.
+pub fn jws_compact<T: Enveloped>(artifact: &T, keys: &KeyMaterial) -> Option<String> {
+    let jws = jws_json(artifact, keys)?;
+    Some(format!("{}.{}.{}", jws.protected, jws.payload, jws.signature))
+}
+
+pub fn jws_json<T: Enveloped>(artifact: &T, keys: &KeyMaterial) -> Option<JwsJson> {
+    let header = match &keys.key_id {
+        Some(kid) => json!({ "alg": "EdDSA", "kid": kid, "typ": T::TYPE }),
+        None => json!({ "alg": "EdDSA", "typ": T::TYPE }),
+    };
+    let protected = B64URL.encode(header.to_string());
+    let payload = B64URL.encode(Kernel::jcs_string(artifact));
+    let sig = keys.sign(format!("{}.{}", protected, payload).as_bytes())?;
+    Some(JwsJson { protected, payload, signature: B64URL.encode(sig) })
+}
+
+/// Tagged COSE_Sign1 (RFC 9052): protected `{1: EdDSA, 3: content type, 4: kid}`,
+/// empty unprotected map, attached JCS payload.
+pub fn cose_sign1<T: Enveloped>(artifact: &T, keys: &KeyMaterial) -> Option<Vec<u8>> {
+    let payload = Kernel::jcs_string(artifact).into_bytes();
+
+    let mut protected_map = vec![
+        (Cbor::Uint(1), Cbor::int(COSE_ALG_EDDSA)),
+        (Cbor::Uint(3), Cbor::Text(T::TYPE.into())),
+    ];
+    if let Some(kid) = &keys.key_id {
+        protected_map.push((Cbor::Uint(4), Cbor::Bytes(kid.as_bytes().to_vec())));
+    }
+    let protected = Cbor::Map(protected_map).encode();
+
+    let sig = keys.sign(&sig_structure(&protected, &payload))?;
+    let msg = Cbor::Tag(COSE_SIGN1_TAG, Box::new(Cbor::Array(vec![
+        Cbor::Bytes(protected),
+        Cbor::Map(vec![]),
+        Cbor::Bytes(payload),
+        Cbor::Bytes(sig),
+    ])));
+    Some(msg.encode())
+}
+
+/// `Sig_structure = ["Signature1", protected, external_aad = h'', payload]`.
+fn sig_structure(protected: &[u8], payload: &[u8]) -> Vec<u8> {
+    Cbor::Array(vec![
+        Cbor::Text("Signature1".into()),
+        Cbor::Bytes(protected.to_vec()),
+        Cbor::Bytes(vec![]),
+        Cbor::Bytes(payload.to_vec()),
+    ]).encode()
+}
+
+// ----------------------
+// Parsers (structure + algorithm only; signatures are checked by the engine)
+// ----------------------
+fn invalid(detail: &str) -> UblError {
+    UblError::Validation(format!("envelope_malformed: {}", detail))
+}
+
+pub fn parse_jws_compact(token: &str) -> Result<Opened, UblError> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    match parts.as_slice() {
+        [protected, payload, signature] => parse_jws_parts(protected, payload, signature),
+        _ => Err(invalid("jws_parts")),
+    }
+}
+
+pub fn parse_jws_json(jws: &JwsJson) -> Result<Opened, UblError> {
+    parse_jws_parts(&jws.protected, &jws.payload, &jws.signature)
+}
+
+fn parse_jws_parts(protected: &str, payload: &str, signature: &str) -> Result<Opened, UblError> {
+    let header_bytes = B64URL.decode(protected).map_err(|_| invalid("jws_header_encoding"))?;
+    let header: Value = serde_json::from_slice(&header_bytes).map_err(|_| invalid("jws_header_json"))?;
+    if header.get("alg").and_then(|a| a.as_str()) != Some("EdDSA") {
+        return Err(UblError::Validation("envelope_unsupported_alg".into()));
+    }
+    if header.get("crit").is_some() {
+        return Err(UblError::Validation("envelope_unsupported_crit".into()));
+    }
+    let str_header = |name: &str| header.get(name).and_then(|v| v.as_str()).map(|s| s.to_string());
+
+    Ok(Opened {
+        kid: str_header("kid"),
+        content_type: str_header("typ"),
+        signing_input: format!("{}.{}", protected, payload).into_bytes(),
+        signature: B64URL.decode(signature).map_err(|_| invalid("jws_signature_encoding"))?,
+        payload: B64URL.decode(payload).map_err(|_| invalid("jws_payload_encoding"))?,
+    })
+}
+
+pub fn parse_cose_sign1(bytes: &[u8]) -> Result<Opened, UblError> {
+    let item = Cbor::decode(bytes).ok_or_else(|| invalid("cbor"))?;
+    let item = match item {
+        Cbor::Tag(COSE_SIGN1_TAG, inner) => *inner,
+        Cbor::Tag(..) => return Err(invalid("cose_tag")),
+        other => other,
+    };
+    let (protected, unprotected, payload, signature) = match item {
+        Cbor::Array(v) => match <[Cbor; 4]>::try_from(v) {
+            Ok([Cbor::Bytes(p), Cbor::Map(u), Cbor::Bytes(pl), Cbor::Bytes(s)]) => (p, u, pl, s),
+            _ => return Err(invalid("cose_structure")),
+        },
+        _ => return Err(invalid("cose_structure")),
+    };
+
+    let protected_map = match Cbor::decode(&protected) {
+        Some(Cbor::Map(m)) => m,
+        _ => return Err(invalid("cose_protected")),
+    };
+    let label = |map: &[(Cbor, Cbor)], l: u64| map.iter().find(|(k, _)| *k == Cbor::Uint(l)).map(|(_, v)| v.clone());
+
+    if label(&protected_map, 1) != Some(Cbor::int(COSE_ALG_EDDSA)) {
+        return Err(UblError::Validation("envelope_unsupported_alg".into()));
+    }
+    if label(&protected_map, 2).is_some() {
+        return Err(UblError::Validation("envelope_unsupported_crit".into()));
+    }
+    let content_type = match label(&protected_map, 3) {
+        Some(Cbor::Text(t)) => Some(t),
+        _ => None,
+    };
+    let kid = match label(&protected_map, 4).or_else(|| label(&unprotected, 4)) {
+        Some(Cbor::Bytes(b)) => Some(String::from_utf8(b).map_err(|_| invalid("cose_kid"))?),
+        _ => None,
+    };
+
+    Ok(Opened { kid, content_type, signing_input: sig_structure(&protected, &payload), signature, payload })
+}
+
+// ----------------------
+// Minimal CBOR (RFC 8949): definite lengths, no floats/simple values
+// ----------------------
+#[derive(Debug, Clone, PartialEq)]
+enum Cbor {
+    Uint(u64),
+    Nint(u64), // value = -1 - n
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Tag(u64, Box<Cbor>),
+}
+
+const MAX_CBOR_DEPTH: usize = 8;
+
+impl Cbor {
+    fn int(i: i64) -> Self {
+        if i >= 0 { Cbor::Uint(i as u64) } else { Cbor::Nint((-1 - i) as u64) }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Cbor::Uint(n) => head(out, 0, *n),
+            Cbor::Nint(n) => head(out, 1, *n),
+            Cbor::Bytes(b) => { head(out, 2, b.len() as u64); out.extend_from_slice(b); }
+            Cbor::Text(t) => { head(out, 3, t.len() as u64); out.extend_from_slice(t.as_bytes()); }
+            Cbor::Array(v) => { head(out, 4, v.len() as u64); v.iter().for_each(|x| x.write(out)); }
+            Cbor::Map(m) => { head(out, 5, m.len() as u64); m.iter().for_each(|(k, v)| { k.write(out); v.write(out); }); }
+            Cbor::Tag(t, x) => { head(out, 6, *t); x.write(out); }
+        }
+    }
+
+    /// Decodes exactly one item; trailing bytes are rejected.
+    fn decode(bytes: &[u8]) -> Option<Cbor> {
+        let mut r = Reader { buf: bytes, pos: 0 };
+        let item = r.item(0)?;
+        (r.pos == bytes.len()).then_some(item)
+    }
+}
+
+fn head(out: &mut Vec<u8>, major: u8, n: u64) {
+    let m = major << 5;
+    match n {
+        0..=23 => out.push(m | n as u8),
+        24..=0xff => { out.push(m | 24); out.push(n as u8); }
+        0x100..=0xffff => { out.push(m | 25); out.extend_from_slice(&(n as u16).to_be_bytes()); }
+        0x1_0000..=0xffff_ffff => { out.push(m | 26); out.extend_from_slice(&(n as u32).to_be_bytes()); }
+        _ => { out.push(m | 27); out.extend_from_slice(&n.to_be_bytes()); }
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(n).filter(|&e| e <= self.buf.len())?;
+        let s = &self.buf[self.pos..end];
+        self.pos = end;
+        Some(s)
+    }
+
+    fn head(&mut self) -> Option<(u8, u64)> {
+        let b = self.take(1)?[0];
+        let n = match b & 0x1f {
+            i @ 0..=23 => i as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().ok()?) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().ok()?) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().ok()?),
+            _ => return None, // indefinite lengths / reserved
+        };
+        Some((b >> 5, n))
+    }
+
+    /// Element count, bounded by the remaining input (each element is >= 1 byte).
+    fn count(&self, n: u64) -> Option<usize> {
+        usize::try_from(n).ok().filter(|&n| n <= self.buf.len() - self.pos)
+    }
+
+    fn item(&mut self, depth: usize) -> Option<Cbor> {
+        if depth > MAX_CBOR_DEPTH { return None; }
+        let (major, n) = self.head()?;
+        Some(match major {
+            0 => Cbor::Uint(n),
+            1 => Cbor::Nint(n),
+            2 => Cbor::Bytes(self.take(usize::try_from(n).ok()?)?.to_vec()),
+            3 => Cbor::Text(String::from_utf8(self.take(usize::try_from(n).ok()?)?.to_vec()).ok()?),
+            4 => {
+                let n = self.count(n)?;
+                Cbor::Array((0..n).map(|_| self.item(depth + 1)).collect::<Option<_>>()?)
+            }
+            5 => {
+                let n = self.count(n)?;
+                Cbor::Map((0..n).map(|_| Some((self.item(depth + 1)?, self.item(depth + 1)?))).collect::<Option<_>>()?)
+            }
+            6 => Cbor::Tag(n, Box::new(self.item(depth + 1)?)),
+            _ => return None,
+        })
+    }
+}
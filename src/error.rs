@@ -14,6 +14,8 @@ pub enum UblError {
     LogicDenied(String), // UBL-0x01
     #[error("Unauthorized")]
     Unauthorized, // UBL-0x40
+    #[error("Forbidden: {0}")]
+    Forbidden(String), // UBL-0x41
     #[error("Ledger IO Error: {0}")]
     LedgerIo(String), // UBL-0x30
     #[error("Serialization Error: {0}")]
@@ -32,6 +34,7 @@ impl IntoResponse for UblError {
             UblError::Validation(_) => (StatusCode::BAD_REQUEST, "UBL-0x20"),
             UblError::LogicDenied(_) => (StatusCode::UNPROCESSABLE_ENTITY, "UBL-0x01"),
             UblError::Unauthorized => (StatusCode::UNAUTHORIZED, "UBL-0x40"),
+            UblError::Forbidden(_) => (StatusCode::FORBIDDEN, "UBL-0x41"),
             UblError::LedgerIo(_) => (StatusCode::INTERNAL_SERVER_ERROR, "UBL-0x30"),
             _ => (StatusCode::INTERNAL_SERVER_ERROR, "UBL-0x99"),
         };
@@ -39,3 +42,45 @@ impl IntoResponse for UblError {
         (status, body).into_response()
     }
 }
+
+/// Why an expression could not be evaluated as written. The evaluator still
+/// produces its fallback value (`null`, `false`, `0`); strict chips turn the
+/// error into a DENY and report it in the gate's `error`.
+///
+/// Each message starts with a stable code (`unknown_function`, ...). Proof
+/// replay compares only that code, so the text after the `:` may be reworded.
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum EvalError {
+    #[error("unknown_function: {0}")]
+    UnknownFunction(String),
+    #[error("missing_argument: {function} #{index}")]
+    MissingArgument { function: String, index: usize },
+    #[error("arity: {function} takes {expected}, got {found}")]
+    Arity { function: String, expected: usize, found: usize },
+    #[error("type_mismatch: {at} expected {expected}, got {found}")]
+    TypeMismatch { at: String, expected: &'static str, found: &'static str },
+    #[error("invalid_timestamp: {function}: {value:?}")]
+    InvalidTimestamp { function: String, value: String },
+    #[error("invalid_argument: {function}: {detail}")]
+    InvalidArgument { function: String, detail: String },
+    #[error("division_by_zero")]
+    DivisionByZero,
+    #[error("collection_not_array")]
+    NotAnArray,
+    #[error("collection_too_large")]
+    CollectionTooLarge,
+    #[error("fuel_exhausted")]
+    FuelExhausted,
+    #[error("depth_exceeded")]
+    DepthExceeded,
+    #[error("unknown_chip: {0}")]
+    UnknownChip(String),
+    #[error("chip_cycle: {0}")]
+    ChipCycle(String),
+}
+
+impl EvalError {
+    /// Resource limits and chip cycles: they DENY every chip, strict or not, and
+    /// the chips that reference it.
+    pub fn is_fatal(&self) -> bool { matches!(self, EvalError::FuelExhausted | EvalError::DepthExceeded | EvalError::ChipCycle(_)) }
+}
@@ -0,0 +1,77 @@
+//! Behavioral fingerprints for Shadow Validation.
+//!
+//! A fingerprint summarizes a principal's allowed operations: amount
+//! distribution, UTC hours, counterparties and operation types. Like
+//! trajectories it is derived from `EffectRecord` activities only. Denied
+//! attempts are left out so they cannot shift the baseline. Chips bind it with
+//! `ContextSource::Fingerprint` and score against it with the pure functions
+//! below, so replay only needs the context snapshot.
+
+use crate::engine::Kernel;
+use crate::types::{EffectRecord, Fingerprint};
+use chrono::{DateTime, Timelike, Utc};
+use std::collections::HashMap;
+
+pub type FingerprintIndex = HashMap<String, Fingerprint>;
+
+pub fn index(history: &[EffectRecord]) -> FingerprintIndex {
+    let mut idx = FingerprintIndex::new();
+    for r in history { record(&mut idx, r); }
+    idx
+}
+
+pub fn record(idx: &mut FingerprintIndex, r: &EffectRecord) {
+    let a = match &r.activity { Some(a) if a.allowed => a, _ => return };
+    let fp = idx.entry(a.principal.clone())
+        .or_insert_with(|| Fingerprint { principal: a.principal.clone(), hours: vec![0; 24], ..Default::default() });
+
+    fp.as_of_version = r.resulting_version;
+    fp.operations += 1;
+    if let Some(x) = a.amount {
+        let s = &mut fp.amounts;
+        s.count += 1;
+        let delta = x - s.mean;
+        s.mean += delta / s.count as f64;
+        s.m2 += delta * (x - s.mean);
+        s.stddev = (s.m2 / s.count as f64).sqrt();
+    }
+    if let Some(t) = Kernel::parse_ts(&r.timestamp) { fp.hours[t.hour() as usize] += 1; }
+    if let Some(cp) = &a.counterparty { *fp.counterparties.entry(cp.clone()).or_default() += 1; }
+    *fp.op_types.entry(a.program.clone()).or_default() += 1;
+}
+
+pub fn as_of(idx: &FingerprintIndex, principal: &str, version: u64) -> Fingerprint {
+    let mut fp = idx.get(principal).cloned()
+        .unwrap_or_else(|| Fingerprint { principal: principal.to_string(), hours: vec![0; 24], ..Default::default() });
+    fp.as_of_version = version;
+    fp
+}
+
+// --------------------------
+// Scores (0 = typical; no history = no signal)
+// --------------------------
+/// `(amount - mean) / stddev`; 0 with fewer than two amounts or zero spread.
+pub fn amount_zscore(fp: &Fingerprint, amount: f64) -> f64 {
+    let s = &fp.amounts;
+    if s.count < 2 || s.stddev == 0.0 { return 0.0; }
+    (amount - s.mean) / s.stddev
+}
+
+/// `1 - hits(hour) / hits(busiest hour)`: 0 at the usual hour, 1 at a never-seen one.
+pub fn hour_anomaly(fp: &Fingerprint, at: DateTime<Utc>) -> f64 {
+    let max = fp.hours.iter().copied().max().unwrap_or(0);
+    if max == 0 { return 0.0; }
+    1.0 - fp.hours.get(at.hour() as usize).copied().unwrap_or(0) as f64 / max as f64
+}
+
+/// True if the principal never completed an operation with `counterparty`.
+pub fn new_counterparty(fp: &Fingerprint, counterparty: &str) -> bool {
+    !fp.counterparties.contains_key(counterparty)
+}
+
+/// `1 - count(program) / count(most frequent program)`.
+pub fn op_type_anomaly(fp: &Fingerprint, program: &str) -> f64 {
+    let max = fp.op_types.values().copied().max().unwrap_or(0);
+    if max == 0 { return 0.0; }
+    1.0 - fp.op_types.get(program).copied().unwrap_or(0) as f64 / max as f64
+}
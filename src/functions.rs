@@ -0,0 +1,230 @@
+//! Function registry for `Expr::Call`.
+//!
+//! Each function has a name, a version and a type signature. Its content hash
+//! (`FunctionRef::hash`, over the JCS of the spec) goes into every proof whose
+//! chip calls it, so a changed builtin or embedder function shows up as a replay
+//! divergence instead of a silently different decision.
+//!
+//! Evaluation uses the builtins unless it is handed a registry: the ledger's
+//! (chips, computed context, effects), or `ExecOptions::functions` for a single
+//! execution or replay:
+//!
+//! ```ignore
+//! let mut fns = Functions::builtins();
+//! fns.register(FunctionSpec::new("iban_valid", "1", &[ArgType::String], ArgType::Bool), |args, _cx| {
+//!     json!(iban::valid(args[0].as_str().unwrap_or("")))
+//! })?;
+//! let ledger = Ledger::new().with_functions(Arc::new(fns));
+//! ```
+
+use crate::engine::{CallContext, Kernel};
+use crate::error::UblError;
+use crate::types::{Expr, FunctionRef};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, LazyLock};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ArgType { Any, Bool, Number, String, Array, Object }
+
+impl ArgType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ArgType::Any => "any", ArgType::Bool => "boolean", ArgType::Number => "number",
+            ArgType::String => "string", ArgType::Array => "array", ArgType::Object => "object",
+        }
+    }
+
+    pub fn accepts(self, v: &Value) -> bool {
+        match self {
+            ArgType::Any => true,
+            ArgType::Bool => v.is_boolean(),
+            ArgType::Number => v.is_number(),
+            ArgType::String => v.is_string(),
+            ArgType::Array => v.is_array(),
+            ArgType::Object => v.is_object(),
+        }
+    }
+}
+
+/// Name, version and signature; the last `optional` params may be omitted and,
+/// if `variadic`, the last one repeated.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FunctionSpec {
+    pub name: String,
+    pub version: String,
+    pub params: Vec<ArgType>,
+    #[serde(default)]
+    pub optional: usize,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub variadic: bool,
+    pub returns: ArgType,
+}
+
+impl FunctionSpec {
+    pub fn new(name: &str, version: &str, params: &[ArgType], returns: ArgType) -> Self {
+        Self { name: name.into(), version: version.into(), params: params.to_vec(), optional: 0, variadic: false, returns }
+    }
+
+    pub fn optional(mut self, n: usize) -> Self { self.optional = n; self }
+
+    pub fn variadic(mut self) -> Self { self.variadic = true; self }
+
+    pub fn min_arity(&self) -> usize { self.params.len().saturating_sub(self.optional) }
+
+    pub fn max_arity(&self) -> Option<usize> { (!self.variadic).then_some(self.params.len()) }
+
+    /// Expected type of argument `i` (`None` past the end).
+    pub fn param(&self, i: usize) -> Option<ArgType> {
+        self.params.get(i).or(if self.variadic { self.params.last() } else { None }).copied()
+    }
+
+    pub fn hash(&self) -> String { Kernel::jcs_hash(self) }
+
+    pub fn reference(&self) -> FunctionRef {
+        FunctionRef { name: self.name.clone(), version: self.version.clone(), hash: self.hash() }
+    }
+}
+
+/// A native function. It reports problems through [`CallContext::fail`] and still
+/// returns a value (its fallback), like the builtins.
+pub type NativeFn = Arc<dyn Fn(&[Value], &CallContext) -> Value + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Function {
+    pub spec: FunctionSpec,
+    pub call: NativeFn,
+}
+
+pub trait FunctionRegistry: Send + Sync {
+    fn get(&self, name: &str) -> Option<&Function>;
+}
+
+/// Map-backed registry.
+#[derive(Clone, Default)]
+pub struct Functions {
+    fns: HashMap<String, Function>,
+}
+
+impl FunctionRegistry for Functions {
+    fn get(&self, name: &str) -> Option<&Function> { self.fns.get(name) }
+}
+
+impl Functions {
+    /// The kernel builtins.
+    pub fn builtins() -> Self {
+        use ArgType::*;
+        let specs = [
+            // time
+            FunctionSpec::new("now", "1", &[], String),
+            FunctionSpec::new("before", "1", &[String, String], Bool),
+            FunctionSpec::new("after", "1", &[String, String], Bool),
+            FunctionSpec::new("age", "1", &[String], Number),
+            FunctionSpec::new("time_bucket", "2", &[String, String, String], String).optional(1),
+            FunctionSpec::new("add_duration", "1", &[String, String], String),
+            FunctionSpec::new("days_between", "1", &[String, String, String], Number).optional(1),
+            FunctionSpec::new("start_of_month", "1", &[String, String], String).optional(1),
+            FunctionSpec::new("weekday", "1", &[String, String], Number).optional(1),
+            FunctionSpec::new("is_business_day", "1", &[String, Any, String], Bool).optional(1),
+            FunctionSpec::new("add_business_days", "1", &[String, Number, Any, String], String).optional(1),
+            // string
+            FunctionSpec::new("lower", "1", &[String], String),
+            FunctionSpec::new("upper", "1", &[String], String),
+            FunctionSpec::new("nfc", "1", &[String], String),
+            FunctionSpec::new("nfkc", "1", &[String], String),
+            FunctionSpec::new("casefold", "1", &[String], String),
+            FunctionSpec::new("starts_with", "1", &[String, String], Bool),
+            FunctionSpec::new("ends_with", "1", &[String, String], Bool),
+            FunctionSpec::new("matches", "1", &[String, String], Bool),
+            FunctionSpec::new("split", "1", &[String, String], Array),
+            FunctionSpec::new("trim", "1", &[String], String),
+            FunctionSpec::new("substr", "1", &[String, Number, Number], String).optional(1),
+            FunctionSpec::new("replace", "1", &[String, String, String], String),
+            FunctionSpec::new("concat", "1", &[Any], String).optional(1).variadic(),
+            FunctionSpec::new("format", "1", &[String, Any], String).optional(1).variadic(),
+            FunctionSpec::new("to_number", "1", &[Any], Number),
+            FunctionSpec::new("to_string", "1", &[Any], String),
+            // collections
+            FunctionSpec::new("length", "1", &[Any], Number),
+            FunctionSpec::new("len", "1", &[Any], Number),
+            FunctionSpec::new("empty", "1", &[Array], Bool),
+            FunctionSpec::new("contains", "1", &[Any, Any], Bool),
+            // numbers
+            FunctionSpec::new("abs", "1", &[Number], Number),
+            FunctionSpec::new("floor", "1", &[Number], Number),
+            FunctionSpec::new("ceil", "1", &[Number], Number),
+            FunctionSpec::new("min", "1", &[Number, Number], Number),
+            FunctionSpec::new("max", "1", &[Number, Number], Number),
+            FunctionSpec::new("add", "1", &[Number, Number], Number),
+            FunctionSpec::new("sub", "1", &[Number, Number], Number),
+            FunctionSpec::new("div", "1", &[Number, Number], Number),
+            // crypto
+            FunctionSpec::new("sha256", "1", &[String], String),
+            FunctionSpec::new("verify_ed25519", "1", &[String, String, String], Bool),
+            FunctionSpec::new("threshold_signatures", "1", &[Array, Any, Number], Bool),
+            // behavioral fingerprint
+            FunctionSpec::new("amount_zscore", "1", &[Object, Number], Number),
+            FunctionSpec::new("hour_anomaly", "1", &[Object, String], Number).optional(1),
+            FunctionSpec::new("new_counterparty", "1", &[Object, String], Bool),
+            FunctionSpec::new("op_type_anomaly", "1", &[Object, String], Number),
+        ];
+        let mut fns = Self::default();
+        for spec in specs {
+            let name = spec.name.clone();
+            let call: NativeFn = Arc::new(move |args, cx| Kernel::call_builtin(&name, args, cx));
+            fns.fns.insert(spec.name.clone(), Function { spec, call });
+        }
+        fns
+    }
+
+    /// Adds a function; names are unique (replace a builtin by building the
+    /// registry without it).
+    pub fn register(
+        &mut self, spec: FunctionSpec, f: impl Fn(&[Value], &CallContext) -> Value + Send + Sync + 'static,
+    ) -> Result<(), UblError> {
+        if self.fns.contains_key(&spec.name) {
+            return Err(UblError::Validation(format!("function_exists: {}", spec.name)));
+        }
+        if spec.optional > spec.params.len() || (spec.variadic && spec.params.is_empty()) {
+            return Err(UblError::Validation(format!("function_spec_invalid: {}", spec.name)));
+        }
+        self.fns.insert(spec.name.clone(), Function { spec, call: Arc::new(f) });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Function> { self.fns.remove(name) }
+
+    pub fn specs(&self) -> Vec<&FunctionSpec> {
+        let mut v: Vec<_> = self.fns.values().map(|f| &f.spec).collect();
+        v.sort_by(|a, b| a.name.cmp(&b.name));
+        v
+    }
+}
+
+static BUILTINS: LazyLock<Functions> = LazyLock::new(Functions::builtins);
+
+/// The builtins, shared; what evaluation uses unless it is given a registry.
+pub fn builtin_registry() -> &'static Functions { &BUILTINS }
+
+/// Functions a set of expressions may call (statically, every branch), resolved
+/// against `registry` and sorted by name. Unknown names are left out; strict
+/// chips reject them at evaluation.
+pub fn referenced<'a>(exprs: impl IntoIterator<Item = &'a Expr>, registry: &dyn FunctionRegistry) -> Vec<FunctionRef> {
+    let mut names = BTreeSet::new();
+    for e in exprs { collect_calls(e, &mut names); }
+    names.iter().filter_map(|n| registry.get(n)).map(|f| f.spec.reference()).collect()
+}
+
+fn collect_calls<'a>(e: &'a Expr, out: &mut BTreeSet<&'a str>) {
+    match e {
+        Expr::Literal { .. } | Expr::Path { .. } | Expr::Chip { .. } => {}
+        Expr::Compare { left, right, .. } => { collect_calls(left, out); collect_calls(right, out); }
+        Expr::Logic { args, .. } => args.iter().for_each(|a| collect_calls(a, out)),
+        Expr::Call { function, args } => { out.insert(function); args.iter().for_each(|a| collect_calls(a, out)); }
+        Expr::Collection { over, body, .. } => { collect_calls(over, out); if let Some(b) = body { collect_calls(b, out); } }
+        Expr::Let { value, body, .. } => { collect_calls(value, out); collect_calls(body, out); }
+        Expr::If { cond, then, otherwise } => { collect_calls(cond, out); collect_calls(then, out); collect_calls(otherwise, out); }
+    }
+}
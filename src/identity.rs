@@ -0,0 +1,103 @@
+//! Principals, authentication and capability scopes.
+//!
+//! Callers authenticate with an API token (`authorization: Bearer <token>` or
+//! `x-ubl-key`) or, on `/execute`, with a client-signed request. The legacy
+//! `UBL_API_KEY` authenticates as `root` (admin). With neither `UBL_API_KEY` nor
+//! any registered principal the kernel runs open, as `anonymous` with all scopes.
+
+use crate::engine::Kernel;
+use crate::error::UblError;
+use crate::ledger::Ledger;
+use crate::types::{Chip, Principal};
+use axum::http::HeaderMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action { Register, Execute, Verify, Admin }
+
+impl Action {
+    fn scope(self) -> &'static str {
+        match self { Action::Register => "register", Action::Execute => "execute", Action::Verify => "verify", Action::Admin => "admin" }
+    }
+}
+
+/// The authenticated principal of a request.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub id: String,
+    pub scopes: Vec<String>,
+}
+
+impl From<&Principal> for Caller {
+    fn from(p: &Principal) -> Self { Self { id: p.id.clone(), scopes: p.scopes.clone() } }
+}
+
+impl Caller {
+    fn admin(id: &str) -> Self { Self { id: id.into(), scopes: vec!["admin".into()] } }
+
+    fn has(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope || s == "admin")
+    }
+
+    /// Coarse check per endpoint. `Execute` also passes with any program/chip scope;
+    /// [`Caller::require_execute`] then checks the concrete program and chip.
+    pub fn require(&self, action: Action) -> Result<(), UblError> {
+        let ok = self.has(action.scope())
+            || (action == Action::Execute && self.scopes.iter().any(|s| s.starts_with("program:") || s.starts_with("chip:")));
+        if ok { Ok(()) } else { Err(UblError::Forbidden(format!("{} lacks scope: {}", self.id, action.scope()))) }
+    }
+
+    pub fn require_execute(&self, program: &str, chip: &Chip) -> Result<(), UblError> {
+        let ok = self.has("execute")
+            || self.has(&format!("program:{}", program))
+            || self.has(&format!("chip:{}", chip.name))
+            || self.has(&format!("chip:{}", chip.hash));
+        if ok { Ok(()) } else { Err(UblError::Forbidden(format!("{} may not execute: {}", self.id, program))) }
+    }
+}
+
+pub fn token_hash(token: &str) -> String {
+    Kernel::sha256_hex(token.as_bytes())
+}
+
+pub fn valid_scope(scope: &str) -> bool {
+    match scope.split_once(':') {
+        Some(("program" | "chip", target)) => !target.is_empty(),
+        Some(_) => false,
+        None => matches!(scope, "admin" | "register" | "verify" | "execute"),
+    }
+}
+
+fn bearer(headers: &HeaderMap) -> Option<&str> {
+    let auth = headers.get("authorization").and_then(|h| h.to_str().ok());
+    auth.and_then(|a| a.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-ubl-key").and_then(|h| h.to_str().ok()))
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+}
+
+/// Constant-time token comparison (over the fixed-length hashes, so the
+/// length does not leak either).
+fn same_token(a: &str, b: &str) -> bool {
+    let (a, b) = (token_hash(a), token_hash(b));
+    a.bytes().zip(b.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Resolves the caller from the request token.
+pub fn authenticate(headers: &HeaderMap, ledger: &Ledger) -> Result<Caller, UblError> {
+    let root_key = std::env::var("UBL_API_KEY").ok();
+    match bearer(headers) {
+        Some(token) if root_key.as_deref().is_some_and(|k| same_token(k, token)) => Ok(Caller::admin("root")),
+        Some(token) => ledger.principal_by_token_hash(&token_hash(token))
+            .map(|p| Caller::from(&p))
+            .ok_or(UblError::Unauthorized),
+        None if root_key.is_none() && !ledger.has_principals() => Ok(Caller::admin("anonymous")),
+        None => Err(UblError::Unauthorized),
+    }
+}
+
+/// `authenticate` + `require(action)`.
+pub fn authorize(headers: &HeaderMap, ledger: &Ledger, action: Action) -> Result<Caller, UblError> {
+    let caller = authenticate(headers, ledger)?;
+    caller.require(action)?;
+    Ok(caller)
+}
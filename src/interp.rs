@@ -32,11 +32,7 @@ pub fn interpolate_str(template: &str, ctx: &Value, proof: Option<&Proof>, meta:
 
     // Token resolver: {path} and {{path}}
     for (open, close) in [("{", "}"), ("{{", "}}")] {
-        loop {
-            let start = match out.find(open) {
-                Some(s) => s,
-                None => break,
-            };
+        while let Some(start) = out.find(open) {
             let rest = &out[start + open.len()..];
             let end_rel = match rest.find(close) {
                 Some(e) => e,
@@ -0,0 +1,60 @@
+use crate::error::UblError;
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use ed25519_dalek::SigningKey;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Private halves of kernel keys generated by rotation (key_id -> base64 seed).
+///
+/// Kept outside the ledger file: the ledger only ever holds public keyring
+/// entries, so it can be exported and audited without leaking signing keys.
+/// `UBL_KEYSTORE_PATH` sets the file (default [`DEFAULT_KEYSTORE_FILE`]).
+pub const DEFAULT_KEYSTORE_FILE: &str = "ubl_keys.json";
+
+pub fn path() -> PathBuf {
+    std::env::var("UBL_KEYSTORE_PATH").map(PathBuf::from).unwrap_or_else(|_| PathBuf::from(DEFAULT_KEYSTORE_FILE))
+}
+
+/// A missing keystore is empty; an unreadable or corrupt one is an error, never
+/// empty, so [`store`] cannot overwrite the keys it holds.
+fn read_all(path: &Path) -> Result<HashMap<String, String>, UblError> {
+    if !path.exists() { return Ok(HashMap::new()); }
+    let io = |e: String| UblError::LedgerIo(format!("keystore {}: {}", path.display(), e));
+    let content = std::fs::read_to_string(path).map_err(|e| io(e.to_string()))?;
+    serde_json::from_str(&content).map_err(|e| io(e.to_string()))
+}
+
+pub fn load(key_id: &str) -> Result<Option<SigningKey>, UblError> {
+    let all = read_all(&path())?;
+    let Some(seed) = all.get(key_id) else { return Ok(None) };
+    let arr: Option<[u8; 32]> = B64.decode(seed).ok().and_then(|b| b.as_slice().try_into().ok());
+    let arr = arr.ok_or_else(|| UblError::LedgerIo(format!("keystore: malformed key {}", key_id)))?;
+    Ok(Some(SigningKey::from_bytes(&arr)))
+}
+
+pub fn store(key_id: &str, sk: &SigningKey) -> Result<(), UblError> {
+    let path = path();
+    let mut all = read_all(&path)?;
+    all.insert(key_id.to_string(), B64.encode(sk.to_bytes()));
+    let json_str = serde_json::to_string_pretty(&all)?;
+
+    let tmp_file = format!("{}.tmp", path.display());
+    let io = |e: std::io::Error| UblError::LedgerIo(e.to_string());
+    // a leftover tmp file may have looser permissions; start from a fresh one
+    match std::fs::remove_file(&tmp_file) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(io(e)),
+        _ => {}
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut f = options.open(&tmp_file).map_err(io)?;
+    f.write_all(json_str.as_bytes()).map_err(io)?;
+    f.sync_all().map_err(io)?;
+    std::fs::rename(&tmp_file, &path).map_err(io)
+}
@@ -1,21 +1,55 @@
+use crate::bundle::{ProofBundle, BUNDLE_VERSION};
 use crate::error::UblError;
-use crate::engine::{Kernel, ExecMeta, KeyMaterial};
+use crate::engine::{ChipResolver, Kernel, ExecMeta, FuelMeter, KeyMaterial, FUEL_EFFECT};
+use crate::functions::{FunctionRegistry, Functions};
 use crate::interp;
+use crate::identity;
+use crate::keystore;
+use crate::trajectory::{self, TrajectoryIndex};
+use crate::fingerprint::{self, FingerprintIndex};
 use crate::types::*;
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use ed25519_dalek::SigningKey;
 use parking_lot::RwLock;
+use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::{fs::File, path::Path, sync::Arc};
-use tracing::info;
+use std::{fs::File, path::PathBuf, sync::Arc};
+use tracing::{info, warn};
 
 const DB_FILE: &str = "ubl_ledger.json";
 
+/// `program_hash` of kernel-issued records (no program involved).
+pub const KEY_ROTATION: &str = "KERNEL:key_rotation";
+pub const KEY_REVOCATION: &str = "KERNEL:key_revocation";
+
+/// Event recorded instead of a DENY's effects when they run out of fuel.
+pub const EFFECTS_FAILED: &str = "KERNEL:effects_failed";
+
+/// Longest accepted lifetime of a client-signed request; bounds nonce storage.
+pub const MAX_REQUEST_TTL_SECS: i64 = 3600;
+
 #[derive(Serialize, Deserialize, Clone, Default)]
 pub struct LedgerState {
     pub meta: Meta,
     pub registry: Registry,
     pub root: Value,              // entity tree
     pub history: Vec<EffectRecord>,
+    #[serde(default)]
+    pub keyring: Keyring,
+    #[serde(default)]
+    pub nonces: std::collections::HashMap<String, std::collections::HashMap<String, Timestamp>>, // client id -> nonce -> expiry
+    #[serde(skip)]
+    pub trajectories: TrajectoryIndex, // derived from history on load
+    #[serde(skip)]
+    pub fingerprints: FingerprintIndex, // derived from history on load
+}
+
+/// Who caused a transaction; stored in its `EffectRecord` (covered by `record_hash`).
+#[derive(Clone, Debug, Default)]
+pub struct Origin {
+    pub client_signature: Option<ClientSignature>,
+    pub activity: Option<Activity>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Default)]
@@ -30,26 +64,82 @@ pub struct Registry {
     #[serde(default)]
     pub chip_names: std::collections::HashMap<String, String>, // name -> hash
     pub programs: std::collections::HashMap<String, Program>,
+    #[serde(default)]
+    pub program_versions: std::collections::HashMap<String, Program>, // hash -> program (all versions)
+    #[serde(default)]
+    pub barrier: BarrierRegistry,
+    #[serde(default)]
+    pub principals: std::collections::HashMap<String, Principal>,
+    #[serde(default)]
+    pub principal_tokens: std::collections::HashMap<String, String>, // token hash -> principal id
 }
 
 pub struct Ledger {
     state: Arc<RwLock<LedgerState>>,
+    /// Loaded once from the environment, keyring and keystore; reloaded when
+    /// the keyring changes.
+    keys: RwLock<KeyMaterial>,
+    /// Where [`Ledger::commit`] persists the state; `None` keeps it in memory.
+    path: Option<PathBuf>,
+    /// Functions the ledger's chips, computed context and effects call.
+    functions: Arc<dyn FunctionRegistry>,
+}
+
+impl Default for Ledger {
+    fn default() -> Self { Self::new() }
 }
 
 impl Ledger {
     pub fn new() -> Self {
-        let state = if Path::new(DB_FILE).exists() {
-            let content = std::fs::read_to_string(DB_FILE).unwrap_or_default();
+        Self::open(DB_FILE)
+    }
+
+    /// The ledger persisted at `path` (empty if the file does not exist yet).
+    pub fn open(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let state: LedgerState = if path.exists() {
+            let content = std::fs::read_to_string(&path).unwrap_or_default();
             serde_json::from_str(&content).unwrap_or_default()
         } else {
-            LedgerState {
-                meta: Meta { version: 0, created_at: chrono::Utc::now().to_rfc3339() },
-                root: json!({}),
-                ..Default::default()
-            }
+            Self::genesis()
         };
+        Self::mount(state, Some(path))
+    }
+
+    /// An empty ledger that is never written to disk (`commit` is a no-op).
+    pub fn in_memory() -> Self {
+        Self::mount(Self::genesis(), None)
+    }
+
+    fn genesis() -> LedgerState {
+        LedgerState {
+            meta: Meta { version: 0, created_at: chrono::Utc::now().to_rfc3339() },
+            root: json!({}),
+            ..Default::default()
+        }
+    }
+
+    fn mount(mut state: LedgerState, path: Option<PathBuf>) -> Self {
+        state.registry.principal_tokens = state.registry.principals.values()
+            .filter_map(|p| Some((p.token_hash.clone()?, p.id.clone())))
+            .collect();
+        state.trajectories = trajectory::index(&state.history);
+        state.fingerprints = fingerprint::index(&state.history);
         info!("📚 Ledger Mounted. Version: {}", state.meta.version);
-        Self { state: Arc::new(RwLock::new(state)) }
+        let keys = RwLock::new(Self::load_key_material(&state.keyring));
+        Self { state: Arc::new(RwLock::new(state)), keys, path, functions: Arc::new(Functions::builtins()) }
+    }
+
+    /// Evaluates against `functions` instead of the builtins; set it before
+    /// serving, and keep it when restarting on the same ledger file so recorded
+    /// proofs still replay.
+    pub fn with_functions(mut self, functions: Arc<dyn FunctionRegistry>) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    pub fn functions(&self) -> &dyn FunctionRegistry {
+        &*self.functions
     }
 
     pub fn snapshot_root(&self) -> Value {
@@ -74,6 +164,14 @@ impl Ledger {
         st.registry.chips.get(h).cloned()
     }
 
+    /// A chip by hash or `CHIP:name`, as programs and `Expr::Chip` name them.
+    pub fn resolve_chip(&self, reference: &str) -> Option<Chip> {
+        match reference.strip_prefix("CHIP:") {
+            Some(name) => self.get_chip_by_name(name),
+            None => self.get_chip(reference),
+        }
+    }
+
     pub fn list_chips(&self) -> Vec<(String, String, String)> {
         self.state.read().registry.chips.iter()
             .map(|(h, c)| (h.clone(), c.name.clone(), c.description.clone()))
@@ -86,38 +184,130 @@ impl Ledger {
             .collect()
     }
 
-    pub fn register_chip(&self, mut chip: Chip) -> Result<String, UblError> {
-        let computed = Kernel::compute_chip_hash(&chip);
-        chip.hash = computed.clone();
+    pub fn register_chip(&self, chip: Chip) -> Result<String, UblError> {
+        self.state.write().registry.add_chip(chip)
+    }
+
+    pub fn register_program(&self, program: Program) -> Result<String, UblError> {
+        self.state.write().registry.add_program(program)
+    }
+
+    /// Registers a policy document's chips, then its programs, all or nothing.
+    /// Returns their hashes.
+    pub fn register_policy(&self, chips: Vec<Chip>, programs: Vec<Program>) -> Result<(Vec<String>, Vec<String>), UblError> {
         let mut st = self.state.write();
+        let mut registry = st.registry.clone();
+        let chips = chips.into_iter().map(|c| registry.add_chip(c)).collect::<Result<Vec<_>, _>>()?;
+        let programs = programs.into_iter().map(|p| registry.add_program(p)).collect::<Result<Vec<_>, _>>()?;
+        st.registry = registry;
+        Ok((chips, programs))
+    }
 
-        // Enforce unique chip name -> hash mapping (unless identical logic).
-        if let Some(existing) = st.registry.chip_names.get(&chip.name) {
-            if existing != &computed {
-                return Err(UblError::Validation(format!(
-                    "chip_name_conflict: name='{}' existing_hash='{}' new_hash='{}'",
-                    chip.name, existing, computed
-                )));
+    pub fn barrier_registry(&self) -> BarrierRegistry {
+        self.state.read().registry.barrier.clone()
+    }
+
+    pub fn register_source(&self, source: TrustedSource) -> Result<String, UblError> {
+        if Kernel::parse_verifying_key_b64(&source.public_key).is_none() {
+            return Err(UblError::Validation(format!("invalid_public_key: {}", source.id)));
+        }
+        let id = source.id.clone();
+        self.state.write().registry.barrier.sources.insert(id.clone(), source);
+        Ok(id)
+    }
+
+    pub fn register_principal(&self, mut principal: Principal) -> Result<String, UblError> {
+        if principal.public_key.is_none() && principal.token_hash.is_none() {
+            return Err(UblError::Validation(format!("principal_without_credentials: {}", principal.id)));
+        }
+        if principal.public_key.as_deref().is_some_and(|pk| Kernel::parse_verifying_key_b64(pk).is_none()) {
+            return Err(UblError::Validation(format!("invalid_public_key: {}", principal.id)));
+        }
+        if principal.token_hash.as_deref().is_some_and(|h| h.len() != 64 || !h.bytes().all(|b| b.is_ascii_hexdigit())) {
+            return Err(UblError::Validation(format!("invalid_token_hash: {}", principal.id)));
+        }
+        if let Some(bad) = principal.scopes.iter().find(|s| !identity::valid_scope(s)) {
+            return Err(UblError::Validation(format!("invalid_scope: {}", bad)));
+        }
+        principal.token_hash = principal.token_hash.map(|h| h.to_ascii_lowercase());
+        let id = principal.id.clone();
+        let mut st = self.state.write();
+        let reg = &mut st.registry;
+        if let Some(h) = &principal.token_hash {
+            if reg.principal_tokens.get(h).is_some_and(|owner| owner != &id) {
+                return Err(UblError::Validation(format!("token_hash_conflict: {}", id)));
             }
         }
+        if let Some(old) = reg.principals.get(&id).and_then(|p| p.token_hash.as_ref()) {
+            reg.principal_tokens.remove(old);
+        }
+        if let Some(h) = &principal.token_hash { reg.principal_tokens.insert(h.clone(), id.clone()); }
+        reg.principals.insert(id.clone(), principal);
+        Ok(id)
+    }
 
-        st.registry.chip_names.insert(chip.name.clone(), computed.clone());
-        st.registry.chips.insert(computed.clone(), chip);
-        Ok(computed)
+    /// Trajectory of `principal` as of the current version.
+    pub fn trajectory(&self, principal: &str, meta: &ExecMeta) -> Trajectory {
+        let st = self.state.read();
+        trajectory::as_of(&st.trajectories, principal, st.meta.version, meta.execution_time)
     }
 
-    pub fn register_program(&self, mut program: Program) -> Result<String, UblError> {
-        let computed = Kernel::compute_program_hash(&program);
-        program.hash = computed.clone();
-        self.state.write().registry.programs.insert(program.name.clone(), program);
-        Ok(computed)
+    /// Behavioral fingerprint of `principal` as of the current version.
+    pub fn fingerprint(&self, principal: &str) -> Fingerprint {
+        let st = self.state.read();
+        fingerprint::as_of(&st.fingerprints, principal, st.meta.version)
+    }
+
+    pub fn has_principals(&self) -> bool {
+        !self.state.read().registry.principals.is_empty()
+    }
+
+    pub fn principal_by_token_hash(&self, hash: &str) -> Option<Principal> {
+        let st = self.state.read();
+        let id = st.registry.principal_tokens.get(hash)?;
+        st.registry.principals.get(id).cloned()
+    }
+
+    /// Verifies a client-signed request and returns its principal: registered key, signature over
+    /// [`Kernel::client_request_message`], unexpired (at most
+    /// [`MAX_REQUEST_TTL_SECS`] ahead) and an unused nonce. The nonce is only
+    /// consumed when the transaction is applied.
+    pub fn verify_client_request(&self, req: &ExecReq, auth: &ClientSignature, meta: &ExecMeta) -> Result<Principal, UblError> {
+        let st = self.state.read();
+        let principal = st.registry.principals.get(&auth.signer_id)
+            .ok_or_else(|| UblError::Validation(format!("unknown_client: {}", auth.signer_id)))?;
+        let public_key = principal.public_key.as_deref()
+            .ok_or_else(|| UblError::Validation(format!("client_has_no_key: {}", auth.signer_id)))?;
+
+        let expiry = Kernel::parse_ts(&auth.expiry)
+            .ok_or_else(|| UblError::Validation("request_expiry_invalid".into()))?;
+        if expiry <= meta.execution_time {
+            return Err(UblError::Validation("request_expired".into()));
+        }
+        if (expiry - meta.execution_time).num_seconds() > MAX_REQUEST_TTL_SECS {
+            return Err(UblError::Validation("request_expiry_too_far".into()));
+        }
+
+        let msg = Kernel::client_request_message(req, auth);
+        if !Kernel::verify_ed25519_b64(public_key, msg.as_bytes(), &auth.signature) {
+            return Err(UblError::Validation(format!("client_signature_invalid: {}", auth.signer_id)));
+        }
+        if st.nonce_used(auth) {
+            return Err(UblError::Validation(format!("nonce_replayed: {}", auth.nonce)));
+        }
+        Ok(principal.clone())
+    }
+
+    pub fn set_barrier_policy(&self, policy: BarrierPolicy) {
+        self.state.write().registry.barrier.policies.insert(policy.content_type, policy.unsigned);
     }
 
     pub async fn commit(&self) -> Result<(), UblError> {
+        let Some(path) = &self.path else { return Ok(()) };
         let snapshot = { self.state.read().clone() };
         let json_str = serde_json::to_string_pretty(&snapshot)?;
 
-        let tmp_file = format!("{}.tmp", DB_FILE);
+        let tmp_file = format!("{}.tmp", path.display());
         tokio::fs::write(&tmp_file, json_str).await.map_err(|e| UblError::LedgerIo(e.to_string()))?;
 
         { // fsync tmp
@@ -125,9 +315,9 @@ impl Ledger {
             f.sync_all().map_err(|e| UblError::LedgerIo(e.to_string()))?;
         }
 
-        tokio::fs::rename(&tmp_file, DB_FILE).await.map_err(|e| UblError::LedgerIo(e.to_string()))?;
+        tokio::fs::rename(&tmp_file, path).await.map_err(|e| UblError::LedgerIo(e.to_string()))?;
 
-        if let Some(parent) = Path::new(DB_FILE).parent() {
+        if let Some(parent) = path.parent() {
             if let Ok(dir) = File::open(parent) { let _ = dir.sync_all(); }
         }
         Ok(())
@@ -136,6 +326,7 @@ impl Ledger {
     // --------------------------
     // Apply effects atomically
     // --------------------------
+    #[allow(clippy::too_many_arguments)]
     pub async fn apply_transaction(
         &self,
         program_hash: &str,
@@ -145,6 +336,26 @@ impl Ledger {
         effects: &[Effect],
         meta: &ExecMeta,
         keys: &KeyMaterial,
+        origin: Origin,
+        fuel: &FuelMeter,
+    ) -> Result<EffectRecord, UblError> {
+        let record = self.apply_locked(program_hash, input_hash, target_version, proof, effects, meta, keys, origin, fuel)?;
+        self.commit().await?;
+        Ok(record)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn apply_locked(
+        &self,
+        program_hash: &str,
+        input_hash: &str,
+        target_version: Option<u64>,
+        proof: &Proof,
+        effects: &[Effect],
+        meta: &ExecMeta,
+        keys: &KeyMaterial,
+        origin: Origin,
+        fuel: &FuelMeter,
     ) -> Result<EffectRecord, UblError> {
         let mut st = self.state.write();
         let v = st.meta.version;
@@ -154,11 +365,45 @@ impl Ledger {
                 return Err(UblError::Validation(format!("version_conflict: expected {}, got {}", tv, v)));
             }
         }
+        // Rotation swaps the key material under this lock: a proof sealed with a
+        // key closed since must not be recorded (nor the record signed with it).
+        if self.keys.read().key_id != keys.key_id {
+            return Err(UblError::Validation("signing_key_rotated: retry the request".into()));
+        }
+        // Re-checked under the write lock: concurrent replays race to here.
+        if let Some(auth) = &origin.client_signature {
+            if st.nonce_used(auth) {
+                return Err(UblError::Validation(format!("nonce_replayed: {}", auth.nonce)));
+            }
+        }
 
-        let mut root = st.root.clone();
+        let (root, applied) = match Self::run_effects(&st.root, proof, effects, meta, fuel, self.functions()) {
+            Ok(r) => r,
+            // a DENY is recorded even if the chip left too little fuel for its
+            // effects: none is applied, and the record notes why
+            Err(UblError::Validation(e)) if proof.final_result != 1 && fuel.exhausted() => {
+                (st.root.clone(), vec![Effect::Emit { event: EFFECTS_FAILED.into(), data: json!({ "error": e }) }])
+            }
+            Err(e) => return Err(e),
+        };
+        st.root = root;
+        if let Some(auth) = &origin.client_signature { st.consume_nonce(auth, meta); }
+        Ok(st.append_record(program_hash, input_hash, &proof.proof_hash, applied, meta, keys, origin))
+    }
+
+    /// Applies `effects` to a copy of `root`; returns the new root and the
+    /// effects as applied (templates and expressions resolved).
+    fn run_effects(
+        root: &Value, proof: &Proof, effects: &[Effect], meta: &ExecMeta, fuel: &FuelMeter, functions: &dyn FunctionRegistry,
+    ) -> Result<(Value, Vec<Effect>), UblError> {
+        let mut root = root.clone();
         let mut applied: Vec<Effect> = vec![];
 
         for eff in effects {
+            // nothing is applied unless every effect fits in the remaining fuel
+            if !fuel.charge(FUEL_EFFECT) {
+                return Err(UblError::Validation(format!("fuel_exhausted: effects (limit {})", fuel.limit())));
+            }
             match eff {
                 Effect::Fail { message } => return Err(UblError::Validation(format!("program_fail: {}", message))),
                 Effect::Emit { event, data } => {
@@ -168,7 +413,7 @@ impl Ledger {
                     applied.push(Effect::Emit { event: ev, data: d });
                 }
                 Effect::Create { entity_type, id, data } => {
-                    let idv = Kernel::eval_expr(id, &proof.context_snapshot, meta);
+                    let idv = Kernel::eval_expr_metered(id, &proof.context_snapshot, meta, fuel, functions);
                     let id_str = idv.as_str().map(|s| s.to_string()).unwrap_or_else(|| idv.to_string());
 
                     if root.get(entity_type).and_then(|c| c.get(&id_str)).is_some() {
@@ -195,14 +440,14 @@ impl Ledger {
                 }
                 Effect::Set { target, value } => {
                     let t = interp::interpolate_str(target, &proof.context_snapshot, Some(proof), meta);
-                    let raw = Kernel::eval_expr(value, &proof.context_snapshot, meta);
+                    let raw = Kernel::eval_expr_metered(value, &proof.context_snapshot, meta, fuel, functions);
                     let v = interp::interpolate_value(&raw, &proof.context_snapshot, Some(proof), meta);
                     set_path(&mut root, &t, v.clone())?;
                     applied.push(Effect::Set { target: t, value: lit(v) });
                 }
                 Effect::Increment { target, amount } => {
                     let t = interp::interpolate_str(target, &proof.context_snapshot, Some(proof), meta);
-                    let a_val = Kernel::eval_expr(amount, &proof.context_snapshot, meta);
+                    let a_val = Kernel::eval_expr_metered(amount, &proof.context_snapshot, meta, fuel, functions);
                     let a_val = interp::interpolate_value(&a_val, &proof.context_snapshot, Some(proof), meta);
                     let a = a_val.as_f64().unwrap_or(0.0);
                     let curr = get_path(&root, &t).and_then(|v| v.as_f64()).unwrap_or(0.0);
@@ -211,7 +456,7 @@ impl Ledger {
                 }
                 Effect::Decrement { target, amount } => {
                     let t = interp::interpolate_str(target, &proof.context_snapshot, Some(proof), meta);
-                    let a_val = Kernel::eval_expr(amount, &proof.context_snapshot, meta);
+                    let a_val = Kernel::eval_expr_metered(amount, &proof.context_snapshot, meta, fuel, functions);
                     let a_val = interp::interpolate_value(&a_val, &proof.context_snapshot, Some(proof), meta);
                     let a = a_val.as_f64().unwrap_or(0.0);
                     let curr = get_path(&root, &t).and_then(|v| v.as_f64()).unwrap_or(0.0);
@@ -220,7 +465,7 @@ impl Ledger {
                 }
                 Effect::Append { target, value } => {
                     let t = interp::interpolate_str(target, &proof.context_snapshot, Some(proof), meta);
-                    let raw = Kernel::eval_expr(value, &proof.context_snapshot, meta);
+                    let raw = Kernel::eval_expr_metered(value, &proof.context_snapshot, meta, fuel, functions);
                     let v = interp::interpolate_value(&raw, &proof.context_snapshot, Some(proof), meta);
                     let mut arr = get_path(&root, &t).and_then(|v| v.as_array().cloned()).unwrap_or_default();
                     arr.push(v.clone());
@@ -229,7 +474,7 @@ impl Ledger {
                 }
                 Effect::Remove { target, value } => {
                     let t = interp::interpolate_str(target, &proof.context_snapshot, Some(proof), meta);
-                    let raw = Kernel::eval_expr(value, &proof.context_snapshot, meta);
+                    let raw = Kernel::eval_expr_metered(value, &proof.context_snapshot, meta, fuel, functions);
                     let v = interp::interpolate_value(&raw, &proof.context_snapshot, Some(proof), meta);
                     let mut arr = get_path(&root, &t).and_then(|v| v.as_array().cloned()).unwrap_or_default();
                     arr.retain(|x| x != &v);
@@ -239,40 +484,269 @@ impl Ledger {
             }
         }
 
-        let prev_hash = st.history.last().map(|r| r.record_hash.clone());
-        let new_version = v + 1;
+        if fuel.exhausted() {
+            return Err(UblError::Validation(format!("fuel_exhausted: effects (limit {})", fuel.limit())));
+        }
+        Ok((root, applied))
+    }
+
+    // --------------------------
+    // Keyring: rotation + revocation (recorded in history)
+    // --------------------------
+    pub fn keyring(&self) -> Keyring {
+        self.state.read().keyring.clone()
+    }
+
+    /// Key material for signing/verification: the active keyring key (private half
+    /// from the keystore) or, before the first rotation, the key from the environment.
+    pub fn key_material(&self) -> KeyMaterial {
+        self.keys.read().clone()
+    }
+
+    fn load_key_material(keyring: &Keyring) -> KeyMaterial {
+        let mut keys = KeyMaterial::from_env();
+        let active = keyring.keys.iter()
+            .rev()
+            .find(|k| k.valid_until.is_none() && !keyring.revoked.iter().any(|r| r.key_id == k.key_id));
+        match active {
+            Some(entry) => match keystore::load(&entry.key_id) {
+                Ok(Some(sk)) => {
+                    keys.verifying = Some(sk.verifying_key());
+                    keys.signing = Some(sk);
+                    keys.key_id = Some(entry.key_id.clone());
+                }
+                Ok(None) => { keys.signing = None; keys.key_id = None; }
+                Err(e) => {
+                    warn!("active key {} not loaded, not signing: {}", entry.key_id, e);
+                    keys.signing = None;
+                    keys.key_id = None;
+                }
+            },
+            // keyring in use but no active key: never sign
+            None if !keyring.keys.is_empty() => { keys.signing = None; keys.key_id = None; }
+            None => {}
+        }
+        keys.keyring = keyring.clone();
+        keys
+    }
+
+    /// Generates a new signing key, closes the validity of the previous one and
+    /// records the rotation in history, signed by the outgoing key.
+    pub async fn rotate_key(&self, meta: &ExecMeta) -> Result<EffectRecord, UblError> {
+        let outgoing = self.key_material();
+        let sk = SigningKey::generate(&mut OsRng);
+        let new_keys = KeyMaterial::from_signing_key(sk.clone());
+        let new_id = new_keys.key_id.clone().unwrap_or_default();
+        keystore::store(&new_id, &sk)?;
+
+        let now = Kernel::now_rfc3339(meta);
+        let record = {
+            let mut st = self.state.write();
+            // First rotation: pin the bootstrap (environment) key so its proofs stay verifiable.
+            if st.keyring.keys.is_empty() {
+                if let (Some(id), Some(vk)) = (&outgoing.key_id, &outgoing.verifying) {
+                    let valid_from = st.meta.created_at.clone();
+                    st.keyring.keys.push(KeyEntry { key_id: id.clone(), public_key: B64.encode(vk.as_bytes()), valid_from, valid_until: None });
+                }
+            }
+            for k in st.keyring.keys.iter_mut().filter(|k| k.valid_until.is_none()) {
+                k.valid_until = Some(now.clone());
+            }
+            let entry = KeyEntry {
+                key_id: new_id.clone(),
+                public_key: B64.encode(sk.verifying_key().as_bytes()),
+                valid_from: now.clone(),
+                valid_until: None,
+            };
+            st.keyring.keys.push(entry.clone());
+
+            let data = json!({ "previous_key_id": outgoing.key_id, "key": entry });
+            let applied = vec![Effect::Emit { event: "key_rotated".into(), data: data.clone() }];
+            let record = st.append_record(KEY_ROTATION, &Kernel::jcs_hash(&data), "", applied, meta, &outgoing, Origin::default());
+            // swapped under the state lock: nothing signs with a key the keyring has closed
+            *self.keys.write() = Self::load_key_material(&st.keyring);
+            record
+        };
+        self.commit().await?;
+        Ok(record)
+    }
+
+    pub async fn revoke_key(&self, key_id: &str, reason: &str, meta: &ExecMeta) -> Result<EffectRecord, UblError> {
+        let keys = self.key_material();
+        let record = {
+            let mut st = self.state.write();
+            if !st.keyring.keys.iter().any(|k| k.key_id == key_id) {
+                return Err(UblError::Validation(format!("unknown_key: {}", key_id)));
+            }
+            if st.keyring.revoked.iter().any(|r| r.key_id == key_id) {
+                return Err(UblError::Validation(format!("key_already_revoked: {}", key_id)));
+            }
+            let revocation = Revocation { key_id: key_id.to_string(), revoked_at: Kernel::now_rfc3339(meta), reason: reason.to_string() };
+            st.keyring.revoked.push(revocation.clone());
+
+            let data = json!(revocation);
+            let applied = vec![Effect::Emit { event: "key_revoked".into(), data: data.clone() }];
+            let record = st.append_record(KEY_REVOCATION, &Kernel::jcs_hash(&data), "", applied, meta, &keys, Origin::default());
+            // swapped under the state lock: nothing signs with a key the keyring has closed
+            *self.keys.write() = Self::load_key_material(&st.keyring);
+            record
+        };
+        self.commit().await?;
+        Ok(record)
+    }
+}
+
+impl Ledger {
+    // --------------------------
+    // Proof bundles (offline verification)
+    // --------------------------
+    /// Builds a self-contained bundle for `proof`: the chip, the program and
+    /// record that consumed it, the signing keys and, if `include_chain`, the
+    /// records up to a signed checkpoint (`checkpoint_version` or the head).
+    pub fn export_bundle(&self, proof: &Proof, include_chain: bool, checkpoint_version: Option<u64>) -> Result<ProofBundle, UblError> {
+        let keys = self.key_material();
+        let st = self.state.read();
+
+        let mut chip = st.registry.chips.get(&proof.chip_hash).cloned()
+            .ok_or_else(|| UblError::ChipNotFound(proof.chip_hash.clone()))?;
+        chip.hash = Kernel::compute_chip_hash(&chip);
+
+        let record = st.history.iter().find(|r| r.proof_hash == proof.proof_hash).cloned();
+        let program = record.as_ref().and_then(|r| st.registry.program_versions.get(&r.program_hash).cloned());
+
+        let mut chain = vec![];
+        if let (true, Some(r)) = (include_chain, &record) {
+            let head = st.meta.version;
+            let to = checkpoint_version.unwrap_or(head);
+            if to < r.resulting_version || to > head {
+                return Err(UblError::Validation(format!("invalid_checkpoint_version: {}", to)));
+            }
+            chain = st.history.iter()
+                .filter(|x| x.resulting_version > r.resulting_version && x.resulting_version <= to)
+                .cloned()
+                .collect();
+            let checkpoint = chain.last().unwrap_or(r);
+            if checkpoint.record_signature.is_none() {
+                return Err(UblError::Validation(format!("checkpoint_unsigned: {}", checkpoint.resulting_version)));
+            }
+        }
 
+        // Public keys: the keyring, plus the bootstrap key if it never entered one.
+        let mut key_entries = st.keyring.keys.clone();
+        if let (Some(id), Some(vk)) = (&keys.key_id, &keys.verifying) {
+            if !key_entries.iter().any(|k| &k.key_id == id) {
+                key_entries.push(KeyEntry { key_id: id.clone(), public_key: B64.encode(vk.as_bytes()), valid_from: st.meta.created_at.clone(), valid_until: None });
+            }
+        }
+        let used: Vec<&str> = std::iter::once(proof.key_id.as_deref())
+            .chain(record.iter().map(|r| r.record_key_id.as_deref()))
+            .chain(chain.iter().map(|r| r.record_key_id.as_deref()))
+            .flatten()
+            .collect();
+        let key_entries = if used.is_empty() { key_entries } else {
+            key_entries.into_iter().filter(|k| used.contains(&k.key_id.as_str())).collect()
+        };
+
+        // chips the proof's sub-proofs were evaluated against, at any depth
+        let mut chips: Vec<Chip> = vec![];
+        let mut pending: Vec<&Proof> = proof.subproofs.iter().collect();
+        while let Some(sub) = pending.pop() {
+            pending.extend(&sub.subproofs);
+            if chips.iter().any(|c| c.hash == sub.chip_hash) { continue; }
+            let mut c = st.registry.chips.get(&sub.chip_hash).cloned()
+                .ok_or_else(|| UblError::ChipNotFound(sub.chip_hash.clone()))?;
+            c.hash = Kernel::compute_chip_hash(&c);
+            chips.push(c);
+        }
+
+        Ok(ProofBundle { version: BUNDLE_VERSION, proof: proof.clone(), chip, chips, program, record, keys: key_entries, chain })
+    }
+}
+
+impl ChipResolver for Ledger {
+    fn resolve_chip(&self, reference: &str) -> Option<Chip> { Ledger::resolve_chip(self, reference) }
+}
+
+/// Rejects context definitions using a [`RESERVED_CONTEXT`] name: at
+/// registration, and at execution for programs stored before the names were reserved.
+pub fn check_context_names(program: &Program) -> Result<(), UblError> {
+    match program.context.iter().find(|c| RESERVED_CONTEXT.contains(&c.name.as_str())) {
+        Some(c) => Err(UblError::Validation(format!("reserved_context_name: {} (program {})", c.name, program.name))),
+        None => Ok(()),
+    }
+}
+
+impl Registry {
+    fn add_chip(&mut self, mut chip: Chip) -> Result<String, UblError> {
+        let computed = Kernel::compute_chip_hash(&chip);
+        chip.hash = computed.clone();
+
+        // Enforce unique chip name -> hash mapping (unless identical logic).
+        if let Some(existing) = self.chip_names.get(&chip.name) {
+            if existing != &computed {
+                return Err(UblError::Validation(format!(
+                    "chip_name_conflict: name='{}' existing_hash='{}' new_hash='{}'",
+                    chip.name, existing, computed
+                )));
+            }
+        }
+
+        self.chip_names.insert(chip.name.clone(), computed.clone());
+        self.chips.insert(computed.clone(), chip);
+        Ok(computed)
+    }
+
+    fn add_program(&mut self, mut program: Program) -> Result<String, UblError> {
+        check_context_names(&program)?;
+        let computed = Kernel::compute_program_hash(&program);
+        program.hash = computed.clone();
+        self.program_versions.insert(computed.clone(), program.clone());
+        self.programs.insert(program.name.clone(), program);
+        Ok(computed)
+    }
+}
+
+impl LedgerState {
+    /// Appends a chain-hashed (and optionally signed) record and bumps the version.
+    fn nonce_used(&self, auth: &ClientSignature) -> bool {
+        self.nonces.get(&auth.signer_id).map(|n| n.contains_key(&auth.nonce)).unwrap_or(false)
+    }
+
+    /// Records the nonce and drops the client's expired ones (they can no longer replay).
+    fn consume_nonce(&mut self, auth: &ClientSignature, meta: &ExecMeta) {
+        let nonces = self.nonces.entry(auth.signer_id.clone()).or_default();
+        nonces.retain(|_, exp| Kernel::parse_ts(exp).map(|e| e > meta.execution_time).unwrap_or(false));
+        nonces.insert(auth.nonce.clone(), auth.expiry.clone());
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn append_record(&mut self, program_hash: &str, input_hash: &str, proof_hash: &str, applied: Vec<Effect>, meta: &ExecMeta, keys: &KeyMaterial, origin: Origin) -> EffectRecord {
+        let v = self.meta.version;
         let mut record = EffectRecord {
             id: meta.tx_id.clone(),
             version_applied_to: v,
-            resulting_version: new_version,
+            resulting_version: v + 1,
             timestamp: Kernel::now_rfc3339(meta),
             program_hash: program_hash.to_string(),
             input_hash: input_hash.to_string(),
-            proof_hash: proof.proof_hash.clone(),
+            proof_hash: proof_hash.to_string(),
             applied_effects: applied,
-            previous_record_hash: prev_hash,
+            previous_record_hash: self.history.last().map(|r| r.record_hash.clone()),
             record_hash: "".into(),
             record_signature: None,
+            record_key_id: None,
+            client_signature: origin.client_signature,
+            activity: origin.activity,
         };
 
-        let mut tmp = record.clone();
-        tmp.record_hash = "".into();
-        tmp.record_signature = None;
-        record.record_hash = Kernel::jcs_hash(&tmp);
-
         // Optional signature over record_hash
-        if let Some(sig) = keys.sign_b64(record.record_hash.as_bytes()) {
-            record.record_signature = Some(sig);
-        }
-
-        st.root = root;
-        st.meta.version = new_version;
-        st.history.push(record.clone());
-        drop(st);
+        Kernel::sign_record(&mut record, keys);
 
-        self.commit().await?;
-        Ok(record)
+        self.meta.version = v + 1;
+        trajectory::record(&mut self.trajectories, &record);
+        fingerprint::record(&mut self.fingerprints, &record);
+        self.history.push(record.clone());
+        record
     }
 }
 
@@ -1,6 +1,20 @@
 pub mod error;
 pub mod types;
+pub mod canon;
 pub mod engine;
+pub mod functions;
+pub mod interp;
 pub mod ledger;
+pub mod trajectory;
+pub mod fingerprint;
+pub mod calendar;
+pub mod text;
+pub mod policy;
+pub mod bundle;
+pub mod envelope;
+pub mod keystore;
+pub mod identity;
 pub mod trust_barrier;
+pub mod barrier_rules;
+pub mod barrier_parsers;
 pub mod api;
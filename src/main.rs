@@ -1,16 +1,9 @@
-mod error;
-mod types;
-mod engine;
-mod interp;
-mod ledger;
-mod trust_barrier;
-mod api;
-
 use axum::{routing::{get, post}, Router};
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 use tracing::info;
-use crate::ledger::Ledger;
+use ubl_core::api;
+use ubl_core::ledger::Ledger;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -27,9 +20,16 @@ async fn main() -> anyhow::Result<()> {
         .route("/register", post(api::register))
         .route("/execute", post(api::execute))
         .route("/verify", post(api::verify))
+        .route("/bundle", post(api::export_bundle))
+        .route("/bundle/verify", post(api::verify_bundle))
         .route("/registry/chips", get(api::list_chips))
         .route("/registry/programs", get(api::list_programs))
+        .route("/registry/chips/:hash/source", get(api::chip_source))
+        .route("/registry/programs/:name/source", get(api::program_source))
         .route("/barrier/process", post(api::barrier_process))
+        .route("/keys", get(api::list_keys))
+        .route("/keys/rotate", post(api::rotate_key))
+        .route("/keys/revoke", post(api::revoke_key))
         .layer(CorsLayer::permissive())
         .with_state(ledger);
 
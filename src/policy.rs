@@ -0,0 +1,688 @@
+//! Textual policy language for chips and programs.
+//!
+//! ```text
+//! chip transfer_guard
+//!   description "Sender can pay"
+//!   compose ALL
+//!   gate sufficient_funds: sender.balance >= amount
+//!   gate not_self: not (from_id == to_id)
+//!
+//! program transfer
+//!   input amount: number required
+//!   context sender: ledger "wallets.{from_id}"
+//!   evaluate "CHIP:transfer_guard"
+//!   on_allow decrement "wallets.{from_id}.balance" by amount
+//!   on_deny emit "transfer_denied" {"reason": "policy"}
+//! ```
+//!
+//! The source is only a notation: `parse` yields the same `Chip`/`Program` structs
+//! as the JSON form, and hashes stay defined over that AST. `print_chip` and
+//! `print_program` render any AST back, so `parse(print(x))` hashes like `x`.
+//! Names and paths that are not plain identifiers fall back to quoted forms
+//! (`path("a-b")`, `call("fn-x", ...)`); logic nodes with an unusual arity print as
+//! `and(...)`, `or(...)` and `not(...)`. Collections are written with a lambda:
+//! `all(items, x => x.amount > 0)`, `sum(map(items, x => x.amount))`. Bindings and
+//! conditionals read `let fee = amount ?? 0; fee >= 10` and `if a then b else c`.
+//! Another chip is referenced as `chip("CHIP:kyc_passed")` or by hash.
+
+use crate::engine::{self, Kernel, MAX_EXPR_DEPTH};
+use crate::error::UblError;
+use crate::types::*;
+use serde_json::Value;
+use std::fmt;
+
+/// Syntax error with 1-based line and column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyError {
+    pub line: usize,
+    pub col: usize,
+    pub message: String,
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+impl From<PolicyError> for UblError {
+    fn from(e: PolicyError) -> Self { UblError::Validation(format!("policy_syntax: {}", e)) }
+}
+
+/// Chips and programs of one source file, in source order, hashes filled in.
+#[derive(Debug, Clone, Default)]
+pub struct PolicyDoc {
+    pub chips: Vec<Chip>,
+    pub programs: Vec<Program>,
+}
+
+const KEYWORDS: &[&str] = &[
+    "and", "or", "not", "in", "exists", "true", "false", "null", "path", "call", "let", "if", "then", "else",
+];
+
+/// Call-like names that parse as `Expr::Collection`.
+const COLLECTION_OPS: &[&str] = &["all", "any", "filter", "map", "sum", "count", "unique"];
+
+pub fn parse(src: &str) -> Result<PolicyDoc, PolicyError> {
+    let mut p = Parser::new(src);
+    let mut doc = PolicyDoc::default();
+    loop {
+        p.skip_blank();
+        if p.eof() { return Ok(doc); }
+        match p.word()?.as_str() {
+            "chip" => doc.chips.push(p.chip()?),
+            "program" => doc.programs.push(p.program()?),
+            w => return p.err(format!("expected 'chip' or 'program', found '{}'", w)),
+        }
+    }
+}
+
+/// Parses a single expression (e.g. a gate body).
+pub fn parse_expr(src: &str) -> Result<Expr, PolicyError> {
+    let mut p = Parser::new(src);
+    let e = p.expr()?;
+    p.skip_blank();
+    if !p.eof() { return p.err("unexpected input after expression"); }
+    Ok(e)
+}
+
+// --------------------------
+// Parser
+// --------------------------
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    /// Open parentheses/brackets; newlines are insignificant while > 0.
+    depth: usize,
+    /// Nested sub-expressions being parsed, bounded by `MAX_EXPR_DEPTH`.
+    nesting: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self { Self { src, pos: 0, depth: 0, nesting: 0 } }
+
+    fn location(&self) -> (usize, usize) {
+        let before = &self.src[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        (line, col)
+    }
+
+    fn err<T>(&self, message: impl Into<String>) -> Result<T, PolicyError> {
+        let (line, col) = self.location();
+        Err(PolicyError { line, col, message: message.into() })
+    }
+
+    fn rest(&self) -> &'a str { &self.src[self.pos..] }
+    fn peek(&self) -> Option<char> { self.rest().chars().next() }
+    fn eof(&self) -> bool { self.pos >= self.src.len() }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') { self.pos += self.rest().find('\n').unwrap_or(self.rest().len()); }
+    }
+
+    /// Skips spaces and comments (and newlines inside parentheses).
+    fn skip_ws(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == ' ' || c == '\t' || c == '\r' || (c == '\n' && self.depth > 0) { self.pos += 1; }
+            else if c == '#' { self.skip_comment(); }
+            else { break; }
+        }
+    }
+
+    fn skip_blank(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() { self.pos += c.len_utf8(); }
+            else if c == '#' { self.skip_comment(); }
+            else { break; }
+        }
+    }
+
+    fn end_of_statement(&mut self) -> Result<(), PolicyError> {
+        self.skip_ws();
+        match self.peek() {
+            None | Some('\n') => Ok(()),
+            Some(c) => self.err(format!("unexpected '{}' at end of statement", c)),
+        }
+    }
+
+    fn eat(&mut self, s: &str) -> bool {
+        self.skip_ws();
+        if self.rest().starts_with(s) { self.pos += s.len(); true } else { false }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), PolicyError> {
+        if self.eat(s) { Ok(()) } else { self.err(format!("expected '{}'", s)) }
+    }
+
+    fn ident(&mut self) -> Option<String> {
+        self.skip_ws();
+        let len = ident_len(self.rest());
+        if len == 0 { return None; }
+        let s = self.rest()[..len].to_string();
+        self.pos += len;
+        Some(s)
+    }
+
+    fn word(&mut self) -> Result<String, PolicyError> {
+        match self.ident() { Some(w) => Ok(w), None => self.err("expected a keyword") }
+    }
+
+    fn peek_word(&mut self) -> Option<String> {
+        let save = self.pos;
+        let w = self.ident();
+        self.pos = save;
+        w
+    }
+
+    fn keyword(&mut self, kw: &str) -> bool {
+        let save = self.pos;
+        if self.ident().as_deref() == Some(kw) { return true; }
+        self.pos = save;
+        false
+    }
+
+    fn string(&mut self) -> Result<String, PolicyError> {
+        self.skip_ws();
+        if self.peek() != Some('"') { return self.err("expected a string"); }
+        match self.json()? { Value::String(s) => Ok(s), _ => unreachable!() }
+    }
+
+    /// A name: identifier or quoted string.
+    fn name(&mut self) -> Result<String, PolicyError> {
+        self.skip_ws();
+        if self.peek() == Some('"') { return self.string(); }
+        match self.ident() { Some(n) => Ok(n), None => self.err("expected a name") }
+    }
+
+    /// One JSON value starting at the cursor (strings, numbers, arrays, objects, literals).
+    fn json(&mut self) -> Result<Value, PolicyError> {
+        self.skip_ws();
+        let rest = self.rest();
+        if rest.starts_with(['-', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9']) {
+            let len = number_len(rest);
+            return match serde_json::from_str(&rest[..len]) {
+                Ok(v) => { self.pos += len; Ok(v) }
+                Err(_) => self.err("invalid number"),
+            };
+        }
+        if self.keyword("true") { return Ok(Value::Bool(true)); }
+        if self.keyword("false") { return Ok(Value::Bool(false)); }
+        if self.keyword("null") { return Ok(Value::Null); }
+        if !rest.starts_with(['"', '[', '{']) { return self.err("expected a JSON value"); }
+        let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<Value>();
+        match stream.next() {
+            Some(Ok(v)) => { self.pos += stream.byte_offset(); Ok(v) }
+            Some(Err(e)) => {
+                // point at the offending character, not at the start of the value
+                let (line, col) = self.location();
+                let (line, col) = if e.line() <= 1 { (line, col + e.column().saturating_sub(1)) } else { (line + e.line() - 1, e.column()) };
+                Err(PolicyError { line, col, message: format!("invalid JSON: {}", e) })
+            }
+            None => self.err("expected a JSON value"),
+        }
+    }
+
+    // ---------- blocks ----------
+    fn chip(&mut self) -> Result<Chip, PolicyError> {
+        let name = self.name()?;
+        self.end_of_statement()?;
+        let mut chip = Chip { name, description: "".into(), gates: vec![], composition: Composition::default(), strict: false, fuel: None, hash: "".into() };
+        loop {
+            self.skip_blank();
+            match self.peek_word().as_deref() {
+                None | Some("chip" | "program") => break,
+                Some("description") => { self.word()?; chip.description = self.string()?; }
+                Some("compose") => { self.word()?; chip.composition = self.composition()?; }
+                Some("strict") => { self.word()?; chip.strict = true; }
+                Some("fuel") => { self.word()?; chip.fuel = Some(self.integer()?); }
+                Some("gate") => {
+                    self.word()?;
+                    let id = self.name()?;
+                    self.skip_ws();
+                    let description = if self.peek() == Some('"') { self.string()? } else { "".into() };
+                    self.expect(":")?;
+                    chip.gates.push(Gate { id, description, expr: self.expr()? });
+                }
+                Some(w) => return self.err(format!("unknown chip statement '{}'", w)),
+            }
+            self.end_of_statement()?;
+        }
+        chip.hash = Kernel::compute_chip_hash(&chip);
+        Ok(chip)
+    }
+
+    fn integer(&mut self) -> Result<u64, PolicyError> {
+        self.skip_ws();
+        let len = self.rest().bytes().take_while(u8::is_ascii_digit).count();
+        let n = self.rest()[..len].parse().or_else(|_| self.err("expected an integer"))?;
+        self.pos += len;
+        Ok(n)
+    }
+
+    fn composition(&mut self) -> Result<Composition, PolicyError> {
+        self.skip_ws();
+        if self.peek() == Some('{') {
+            let at = self.pos;
+            let v = self.json()?;
+            return serde_json::from_value(v).map(Composition::Full).or_else(|e| { self.pos = at; self.err(format!("invalid composition: {}", e)) });
+        }
+        Ok(Composition::Shorthand(self.name()?))
+    }
+
+    fn program(&mut self) -> Result<Program, PolicyError> {
+        let name = self.name()?;
+        self.end_of_statement()?;
+        let mut p = Program {
+            name, description: "".into(), inputs: vec![], context: vec![], evaluate: "".into(),
+            on_allow: vec![], on_deny: vec![], counterparty: None, amount: None, fuel: None, hash: "".into(),
+        };
+        loop {
+            self.skip_blank();
+            match self.peek_word().as_deref() {
+                None | Some("chip" | "program") => break,
+                Some("description") => { self.word()?; p.description = self.string()?; }
+                Some("input") => { self.word()?; p.inputs.push(self.input()?); }
+                Some("context") => { self.word()?; p.context.push(self.context()?); }
+                Some("evaluate") => { self.word()?; p.evaluate = self.string()?; }
+                Some("counterparty") => { self.word()?; p.counterparty = Some(self.string()?); }
+                Some("amount") => { self.word()?; p.amount = Some(self.string()?); }
+                Some("fuel") => { self.word()?; p.fuel = Some(self.integer()?); }
+                Some("on_allow") => { self.word()?; p.on_allow.push(self.effect()?); }
+                Some("on_deny") => { self.word()?; p.on_deny.push(self.effect()?); }
+                Some(w) => return self.err(format!("unknown program statement '{}'", w)),
+            }
+            self.end_of_statement()?;
+        }
+        if p.evaluate.is_empty() { return self.err(format!("program '{}' has no evaluate statement", p.name)); }
+        p.hash = Kernel::compute_program_hash(&p);
+        Ok(p)
+    }
+
+    fn input(&mut self) -> Result<ProgramInput, PolicyError> {
+        let name = self.name()?;
+        self.expect(":")?;
+        let input_type = self.name()?;
+        let required = self.keyword("required");
+        let envelope = if self.keyword("envelope") {
+            let at = self.pos;
+            let ct = self.name()?;
+            let content_type = serde_json::from_value(Value::String(ct.clone()))
+                .or_else(|_| { self.pos = at; self.err(format!("unknown content type '{}'", ct)) })?;
+            let schema_hash = if self.keyword("schema") { Some(self.string()?) } else { None };
+            Some(EnvelopeSpec { content_type, schema_hash })
+        } else { None };
+        Ok(ProgramInput { name, input_type, required, envelope })
+    }
+
+    fn context(&mut self) -> Result<ContextDef, PolicyError> {
+        let name = self.name()?;
+        self.expect(":")?;
+        let at = self.pos;
+        let src = self.word()?;
+        let source = serde_json::from_value(Value::String(src.clone()))
+            .or_else(|_| { self.pos = at; self.err(format!("unknown context source '{}'", src)) })?;
+        self.skip_ws();
+        let path = if self.peek() == Some('"') { self.string()? } else { "".into() };
+        let expression = if self.eat("=") { Some(self.expr()?) } else { None };
+        Ok(ContextDef { name, source, path, expression })
+    }
+
+    fn effect(&mut self) -> Result<Effect, PolicyError> {
+        let at = self.pos;
+        Ok(match self.word()?.as_str() {
+            "set" => { let target = self.string()?; self.expect("=")?; Effect::Set { target, value: self.expr()? } }
+            "increment" => { let target = self.string()?; self.expect_kw("by")?; Effect::Increment { target, amount: self.expr()? } }
+            "decrement" => { let target = self.string()?; self.expect_kw("by")?; Effect::Decrement { target, amount: self.expr()? } }
+            "append" => { let target = self.string()?; self.expect_kw("value")?; Effect::Append { target, value: self.expr()? } }
+            "remove" => { let target = self.string()?; self.expect_kw("value")?; Effect::Remove { target, value: self.expr()? } }
+            "create" => {
+                let entity_type = self.string()?;
+                self.expect_kw("id")?;
+                let id = self.expr()?;
+                self.expect_kw("data")?;
+                Effect::Create { entity_type, id, data: self.json()? }
+            }
+            "delete" => Effect::Delete { target: self.string()? },
+            "emit" => { let event = self.string()?; Effect::Emit { event, data: self.json()? } }
+            "fail" => Effect::Fail { message: self.string()? },
+            w => { self.pos = at; self.skip_ws(); return self.err(format!("unknown effect '{}'", w)); }
+        })
+    }
+
+    fn expect_kw(&mut self, kw: &str) -> Result<(), PolicyError> {
+        if self.keyword(kw) { Ok(()) } else { self.skip_ws(); self.err(format!("expected '{}'", kw)) }
+    }
+
+    // ---------- expressions ----------
+    fn expr(&mut self) -> Result<Expr, PolicyError> {
+        self.nest()?;
+        let first = self.and()?;
+        let mut args = vec![first];
+        while self.keyword("or") { args.push(self.and()?); }
+        let e = if args.len() == 1 { args.pop().unwrap() } else { Expr::Logic { op: LogicOp::Or, args } };
+        self.nesting -= 1;
+        // the tree, not just the source, must fit: deeper chips would not evaluate
+        if self.nesting == 0 && engine::expr_depth(&e) > MAX_EXPR_DEPTH {
+            return self.err(format!("expression nested deeper than {}", MAX_EXPR_DEPTH));
+        }
+        Ok(e)
+    }
+
+    fn nest(&mut self) -> Result<(), PolicyError> {
+        self.nesting += 1;
+        if self.nesting > MAX_EXPR_DEPTH { return self.err(format!("expression nested deeper than {}", MAX_EXPR_DEPTH)); }
+        Ok(())
+    }
+
+    fn and(&mut self) -> Result<Expr, PolicyError> {
+        let first = self.not()?;
+        let mut args = vec![first];
+        while self.keyword("and") { args.push(self.not()?); }
+        Ok(if args.len() == 1 { args.pop().unwrap() } else { Expr::Logic { op: LogicOp::And, args } })
+    }
+
+    fn not(&mut self) -> Result<Expr, PolicyError> {
+        if !self.keyword("not") { return self.compare(); }
+        self.nest()?;
+        let arg = if self.eat("(") {
+            // `not(a, b)` / `not()`: the printed form of a Not with an unusual arity.
+            // A single operand is parsed once (not re-read as `(a)`, which is
+            // exponential in nesting) and may start a comparison: `not (a) == b`
+            self.pos -= 1;
+            let mut args = self.args()?;
+            if args.len() != 1 { self.nesting -= 1; return Ok(Expr::Logic { op: LogicOp::Not, args }); }
+            let left = self.fallback(args.pop().unwrap())?;
+            self.compare_from(left)?
+        } else {
+            self.not()?
+        };
+        self.nesting -= 1;
+        Ok(Expr::Logic { op: LogicOp::Not, args: vec![arg] })
+    }
+
+    fn compare(&mut self) -> Result<Expr, PolicyError> {
+        if self.keyword("exists") {
+            let left = self.postfix()?;
+            return Ok(Expr::Compare { op: CompareOp::Exists, left: Box::new(left), right: Box::new(Expr::Literal { value: Value::Null }) });
+        }
+        let left = self.postfix()?;
+        self.compare_from(left)
+    }
+
+    fn compare_from(&mut self, left: Expr) -> Result<Expr, PolicyError> {
+        self.skip_ws();
+        let op = if self.eat("==") { CompareOp::Eq }
+            else if self.eat("!=") { CompareOp::Ne }
+            else if self.eat(">=") { CompareOp::Ge }
+            else if self.eat("<=") { CompareOp::Le }
+            else if self.eat(">") { CompareOp::Gt }
+            else if self.eat("<") { CompareOp::Lt }
+            else if self.keyword("in") { CompareOp::In }
+            else if self.keyword("exists") { CompareOp::Exists }
+            else { return Ok(left); };
+        let right = self.postfix()?;
+        Ok(Expr::Compare { op, left: Box::new(left), right: Box::new(right) })
+    }
+
+    fn postfix(&mut self) -> Result<Expr, PolicyError> {
+        let e = self.primary()?;
+        self.fallback(e)
+    }
+
+    fn fallback(&mut self, e: Expr) -> Result<Expr, PolicyError> {
+        if !self.eat("??") { return Ok(e); }
+        match e {
+            Expr::Path { path, fallback: None } => Ok(Expr::Path { path, fallback: Some(self.json()?) }),
+            _ => self.err("'??' only follows a path"),
+        }
+    }
+
+    fn args(&mut self) -> Result<Vec<Expr>, PolicyError> {
+        self.expect("(")?;
+        self.depth += 1;
+        let mut args = vec![];
+        if !self.eat(")") {
+            loop {
+                args.push(self.expr()?);
+                if self.eat(")") { break; }
+                self.expect(",")?;
+            }
+        }
+        self.depth -= 1;
+        Ok(args)
+    }
+
+    /// `op(over)`, `op(over, x => body)` (or `op(over, x)`: a variable without a body).
+    fn collection(&mut self, op: &str) -> Result<Expr, PolicyError> {
+        let op = serde_json::from_value(Value::String(op.into())).expect("collection op");
+        self.expect("(")?;
+        self.depth += 1;
+        let over = Box::new(self.expr()?);
+        let (mut var, mut body) = (String::new(), None);
+        if self.eat(",") {
+            var = self.name()?;
+            if self.eat("=>") { body = Some(Box::new(self.expr()?)); }
+        }
+        self.expect(")")?;
+        self.depth -= 1;
+        Ok(Expr::Collection { op, over, var, body })
+    }
+
+    fn primary(&mut self) -> Result<Expr, PolicyError> {
+        self.skip_ws();
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                self.depth += 1;
+                let e = self.expr()?;
+                self.expect(")")?;
+                self.depth -= 1;
+                return Ok(e);
+            }
+            Some('"' | '[' | '{' | '-' | '0'..='9') => return Ok(Expr::Literal { value: self.json()? }),
+            _ => {}
+        }
+        let at = self.pos;
+        let Some(word) = self.ident() else {
+            return match self.peek() { Some(c) => self.err(format!("unexpected '{}'", c)), None => self.err("unexpected end of input") };
+        };
+        self.skip_ws();
+        let call = self.peek() == Some('(');
+        match word.as_str() {
+            "true" => Ok(Expr::Literal { value: Value::Bool(true) }),
+            "false" => Ok(Expr::Literal { value: Value::Bool(false) }),
+            "null" => Ok(Expr::Literal { value: Value::Null }),
+            "and" if call => Ok(Expr::Logic { op: LogicOp::And, args: self.args()? }),
+            "or" if call => Ok(Expr::Logic { op: LogicOp::Or, args: self.args()? }),
+            "let" => {
+                let name = self.name()?;
+                self.expect("=")?;
+                let value = Box::new(self.expr()?);
+                self.expect(";")?;
+                Ok(Expr::Let { name, value, body: Box::new(self.expr()?) })
+            }
+            "if" => {
+                let cond = Box::new(self.expr()?);
+                self.expect_kw("then")?;
+                let then = Box::new(self.expr()?);
+                self.expect_kw("else")?;
+                Ok(Expr::If { cond, then, otherwise: Box::new(self.expr()?) })
+            }
+            "path" if call => {
+                let args = self.args()?;
+                let path = args.into_iter().map(|a| match a { Expr::Literal { value: Value::String(s) } => Some(s), _ => None }).collect::<Option<Vec<_>>>();
+                match path { Some(path) => Ok(Expr::Path { path, fallback: None }), None => { self.pos = at; self.err("path(...) takes string segments") } }
+            }
+            "call" if call => {
+                let mut args = self.args()?;
+                match args.first() {
+                    Some(Expr::Literal { value: Value::String(f) }) => { let function = f.clone(); args.remove(0); Ok(Expr::Call { function, args }) }
+                    _ => { self.pos = at; self.err("call(...) takes the function name first") }
+                }
+            }
+            "chip" if call => match self.args()?.as_slice() {
+                [Expr::Literal { value: Value::String(r) }] => Ok(Expr::Chip { reference: r.clone() }),
+                _ => { self.pos = at; self.err("chip(...) takes a chip hash or \"CHIP:name\"") }
+            },
+            w if call && COLLECTION_OPS.contains(&w) => self.collection(w),
+            w if KEYWORDS.contains(&w) => { self.pos = at; self.err(format!("unexpected keyword '{}'", w)) }
+            _ if call => Ok(Expr::Call { function: word, args: self.args()? }),
+            _ => {
+                let mut path = vec![word];
+                while self.rest().starts_with('.') {
+                    self.pos += 1;
+                    let len = ident_len(self.rest()).max(self.rest().bytes().take_while(u8::is_ascii_digit).count());
+                    if len > 0 { path.push(self.rest()[..len].to_string()); self.pos += len; }
+                    else if self.peek() == Some('"') { path.push(self.string()?); }
+                    else { return self.err("expected a path segment"); }
+                }
+                Ok(Expr::Path { path, fallback: None })
+            }
+        }
+    }
+}
+
+fn ident_len(s: &str) -> usize {
+    let b = s.as_bytes();
+    if b.is_empty() || !(b[0].is_ascii_alphabetic() || b[0] == b'_') { return 0; }
+    b.iter().take_while(|c| c.is_ascii_alphanumeric() || **c == b'_').count()
+}
+
+fn number_len(s: &str) -> usize {
+    s.bytes().take_while(|c| c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.' | b'e' | b'E')).count()
+}
+
+fn is_ident(s: &str) -> bool { !s.is_empty() && ident_len(s) == s.len() }
+
+// --------------------------
+// Printer
+// --------------------------
+pub fn print_chip(chip: &Chip) -> String {
+    let mut out = format!("chip {}\n", name(&chip.name));
+    if !chip.description.is_empty() { out += &format!("  description {}\n", quote(&chip.description)); }
+    match &chip.composition {
+        Composition::Shorthand(s) => out += &format!("  compose {}\n", name(s)),
+        Composition::Full(def) => out += &format!("  compose {}\n", serde_json::to_string(def).unwrap_or_default()),
+    }
+    if chip.strict { out += "  strict\n"; }
+    if let Some(f) = chip.fuel { out += &format!("  fuel {}\n", f); }
+    for g in &chip.gates {
+        let desc = if g.description.is_empty() { String::new() } else { format!(" {}", quote(&g.description)) };
+        out += &format!("  gate {}{}: {}\n", name(&g.id), desc, print_expr(&g.expr));
+    }
+    out
+}
+
+pub fn print_program(p: &Program) -> String {
+    let mut out = format!("program {}\n", name(&p.name));
+    if !p.description.is_empty() { out += &format!("  description {}\n", quote(&p.description)); }
+    for i in &p.inputs {
+        out += &format!("  input {}: {}", name(&i.name), name(&i.input_type));
+        if i.required { out += " required"; }
+        if let Some(env) = &i.envelope {
+            let ct = serde_json::to_value(&env.content_type).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+            out += &format!(" envelope {}", ct);
+            if let Some(h) = &env.schema_hash { out += &format!(" schema {}", quote(h)); }
+        }
+        out += "\n";
+    }
+    for c in &p.context {
+        let src = serde_json::to_value(&c.source).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+        out += &format!("  context {}: {}", name(&c.name), src);
+        if !c.path.is_empty() { out += &format!(" {}", quote(&c.path)); }
+        if let Some(e) = &c.expression { out += &format!(" = {}", print_expr(e)); }
+        out += "\n";
+    }
+    out += &format!("  evaluate {}\n", quote(&p.evaluate));
+    if let Some(cp) = &p.counterparty { out += &format!("  counterparty {}\n", quote(cp)); }
+    if let Some(a) = &p.amount { out += &format!("  amount {}\n", quote(a)); }
+    if let Some(f) = p.fuel { out += &format!("  fuel {}\n", f); }
+    for e in &p.on_allow { out += &format!("  on_allow {}\n", print_effect(e)); }
+    for e in &p.on_deny { out += &format!("  on_deny {}\n", print_effect(e)); }
+    out
+}
+
+fn print_effect(e: &Effect) -> String {
+    match e {
+        Effect::Set { target, value } => format!("set {} = {}", quote(target), print_expr(value)),
+        Effect::Increment { target, amount } => format!("increment {} by {}", quote(target), print_expr(amount)),
+        Effect::Decrement { target, amount } => format!("decrement {} by {}", quote(target), print_expr(amount)),
+        Effect::Append { target, value } => format!("append {} value {}", quote(target), print_expr(value)),
+        Effect::Remove { target, value } => format!("remove {} value {}", quote(target), print_expr(value)),
+        Effect::Create { entity_type, id, data } => format!("create {} id {} data {}", quote(entity_type), print_expr(id), data),
+        Effect::Delete { target } => format!("delete {}", quote(target)),
+        Effect::Emit { event, data } => format!("emit {} {}", quote(event), data),
+        Effect::Fail { message } => format!("fail {}", quote(message)),
+    }
+}
+
+pub fn print_expr(e: &Expr) -> String { expr_at(e, 0) }
+
+// Binding strength: let/if 0 < or 1 < and 2 < not 3 < compare 4 < primary 5.
+fn expr_at(e: &Expr, min: u8) -> String {
+    let (prec, s) = match e {
+        Expr::Literal { value } => (5, value.to_string()),
+        Expr::Path { path, fallback } => {
+            let head = if path.first().is_some_and(|h| is_ident(h) && !KEYWORDS.contains(&h.as_str())) {
+                path.iter().map(|s| segment(s)).collect::<Vec<_>>().join(".")
+            } else {
+                format!("path({})", path.iter().map(|s| quote(s)).collect::<Vec<_>>().join(", "))
+            };
+            match fallback { Some(f) => (5, format!("{} ?? {}", head, f)), None => (5, head) }
+        }
+        Expr::Compare { op: CompareOp::Exists, left, right } if matches!(**right, Expr::Literal { value: Value::Null }) => {
+            (4, format!("exists {}", expr_at(left, 5)))
+        }
+        Expr::Compare { op, left, right } => (4, format!("{} {} {}", expr_at(left, 5), compare_op(op), expr_at(right, 5))),
+        Expr::Logic { op: LogicOp::Or, args } if args.len() >= 2 => (1, join(args, " or ", 2)),
+        Expr::Logic { op: LogicOp::And, args } if args.len() >= 2 => (2, join(args, " and ", 3)),
+        Expr::Logic { op: LogicOp::Not, args } if args.len() == 1 => (3, format!("not {}", expr_at(&args[0], 3))),
+        Expr::Logic { op, args } => {
+            let op = match op { LogicOp::And => "and", LogicOp::Or => "or", LogicOp::Not => "not" };
+            (5, format!("{}({})", op, join(args, ", ", 0)))
+        }
+        Expr::Call { function, args } if is_ident(function) && !KEYWORDS.contains(&function.as_str()) && !COLLECTION_OPS.contains(&function.as_str()) && function != "chip" => {
+            (5, format!("{}({})", function, join(args, ", ", 0)))
+        }
+        Expr::Call { function, args } => {
+            let rest = if args.is_empty() { String::new() } else { format!(", {}", join(args, ", ", 0)) };
+            (5, format!("call({}{})", quote(function), rest))
+        }
+        Expr::Chip { reference } => (5, format!("chip({})", quote(reference))),
+        // `let` and `if` extend as far right as possible: parenthesized inside operators
+        Expr::Let { name: n, value, body } => (0, format!("let {} = {}; {}", name(n), expr_at(value, 0), expr_at(body, 0))),
+        Expr::If { cond, then, otherwise } => {
+            (0, format!("if {} then {} else {}", expr_at(cond, 0), expr_at(then, 0), expr_at(otherwise, 0)))
+        }
+        Expr::Collection { op, over, var, body } => {
+            let op = serde_json::to_value(op).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default();
+            let lambda = match body {
+                Some(b) => format!(", {} => {}", name(var), expr_at(b, 0)),
+                None if !var.is_empty() => format!(", {}", name(var)),
+                None => String::new(),
+            };
+            (5, format!("{}({}{})", op, expr_at(over, 0), lambda))
+        }
+    };
+    if prec < min { format!("({})", s) } else { s }
+}
+
+fn join(args: &[Expr], sep: &str, min: u8) -> String {
+    args.iter().map(|a| expr_at(a, min)).collect::<Vec<_>>().join(sep)
+}
+
+fn compare_op(op: &CompareOp) -> &'static str {
+    match op {
+        CompareOp::Eq => "==", CompareOp::Ne => "!=", CompareOp::Gt => ">", CompareOp::Lt => "<",
+        CompareOp::Ge => ">=", CompareOp::Le => "<=", CompareOp::In => "in", CompareOp::Exists => "exists",
+    }
+}
+
+fn segment(s: &str) -> String {
+    if is_ident(s) || (!s.is_empty() && s.bytes().all(|c| c.is_ascii_digit())) { s.to_string() } else { quote(s) }
+}
+
+fn name(s: &str) -> String { if is_ident(s) { s.to_string() } else { quote(s) } }
+
+fn quote(s: &str) -> String { Value::String(s.to_string()).to_string() }
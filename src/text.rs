@@ -0,0 +1,128 @@
+//! String builtins with bounded cost: `matches`, `split`, `substr`, `replace`,
+//! `concat`, `format`, `to_number` and `to_string`.
+//!
+//! Patterns run on the `regex` crate (finite automata, linear in the input, no
+//! backreferences or lookaround) with a capped pattern and compiled size.
+//! Inputs and results are capped at [`MAX_STRING_LEN`] bytes and `split` at
+//! [`MAX_COLLECTION_LEN`] parts, so no chip can make a call arbitrarily
+//! expensive. Errors are short details for `EvalError::InvalidArgument`.
+
+use crate::engine::{Kernel, MAX_COLLECTION_LEN};
+use parking_lot::Mutex;
+use regex::{Regex, RegexBuilder};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+/// Upper bound on string arguments and results, in bytes.
+pub const MAX_STRING_LEN: usize = 65_536;
+/// Upper bound on a `matches` pattern, in bytes.
+pub const MAX_PATTERN_LEN: usize = 1_024;
+const MAX_COMPILED_SIZE: usize = 1 << 20;
+const CACHED_PATTERNS: usize = 256;
+
+static PATTERNS: LazyLock<Mutex<HashMap<String, Regex>>> = LazyLock::new(Default::default);
+
+fn bounded(s: String) -> Result<String, String> {
+    if s.len() > MAX_STRING_LEN { Err(format!("longer than {} bytes", MAX_STRING_LEN)) } else { Ok(s) }
+}
+
+/// Whether `pattern` matches anywhere in `s` (anchor with `^…$` for a full match).
+pub fn matches(s: &str, pattern: &str) -> Result<bool, String> {
+    if pattern.len() > MAX_PATTERN_LEN { return Err(format!("pattern longer than {} bytes", MAX_PATTERN_LEN)); }
+    if s.len() > MAX_STRING_LEN { return Err(format!("input longer than {} bytes", MAX_STRING_LEN)); }
+    let mut cache = PATTERNS.lock();
+    if !cache.contains_key(pattern) {
+        let re = RegexBuilder::new(pattern)
+            .size_limit(MAX_COMPILED_SIZE)
+            .dfa_size_limit(MAX_COMPILED_SIZE)
+            .build()
+            .map_err(|e| format!("pattern: {}", e.to_string().lines().last().unwrap_or_default().trim()))?;
+        if cache.len() >= CACHED_PATTERNS { cache.clear(); }
+        cache.insert(pattern.to_string(), re);
+    }
+    Ok(cache[pattern].is_match(s))
+}
+
+pub fn split(s: &str, sep: &str) -> Result<Vec<String>, String> {
+    if sep.is_empty() { return Err("empty separator".into()); }
+    let parts: Vec<String> = s.split(sep).take(MAX_COLLECTION_LEN + 1).map(str::to_string).collect();
+    if parts.len() > MAX_COLLECTION_LEN { return Err(format!("more than {} parts", MAX_COLLECTION_LEN)); }
+    Ok(parts)
+}
+
+/// Characters `start..start + len` (to the end without `len`); negative
+/// `start` counts from the end. Out-of-range bounds are clamped.
+pub fn substr(s: &str, start: i64, len: Option<i64>) -> String {
+    let count = s.chars().count() as i64;
+    let from = if start < 0 { (count + start).max(0) } else { start.min(count) };
+    let take = len.map_or(count - from, |l| l.clamp(0, count - from));
+    s.chars().skip(from as usize).take(take as usize).collect()
+}
+
+/// Replaces every occurrence of `from` (a literal string).
+pub fn replace(s: &str, from: &str, to: &str) -> Result<String, String> {
+    if from.is_empty() { return Err("empty pattern".into()); }
+    // bound the result before building it
+    let grown = s.matches(from).count().saturating_mul(to.len().saturating_sub(from.len()));
+    if s.len().saturating_add(grown) > MAX_STRING_LEN { return Err(format!("longer than {} bytes", MAX_STRING_LEN)); }
+    Ok(s.replace(from, to))
+}
+
+pub fn concat(vals: &[Value]) -> Result<String, String> {
+    let mut out = String::new();
+    for v in vals {
+        out.push_str(&to_string(v));
+        if out.len() > MAX_STRING_LEN { return bounded(out); }
+    }
+    Ok(out)
+}
+
+/// `{}` takes the next argument, `{n}` argument `n`; `{{` and `}}` are literal
+/// braces. Arguments render as with [`to_string`].
+pub fn format(template: &str, args: &[Value]) -> Result<String, String> {
+    let mut out = String::new();
+    let mut next = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => { chars.next(); out.push('{'); }
+            '}' if chars.peek() == Some(&'}') => { chars.next(); out.push('}'); }
+            '{' => {
+                let mut index = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(d) if d.is_ascii_digit() => index.push(d),
+                        _ => return Err("unclosed or invalid placeholder".into()),
+                    }
+                }
+                let i = if index.is_empty() { next += 1; next - 1 } else { index.parse().map_err(|_| "placeholder index")? };
+                out.push_str(&to_string(args.get(i).ok_or_else(|| format!("no argument {}", i))?));
+            }
+            '}' => return Err("unmatched '}'".into()),
+            c => out.push(c),
+        }
+        if out.len() > MAX_STRING_LEN { return bounded(out); }
+    }
+    Ok(out)
+}
+
+/// Numbers as they are; strings in JSON number syntax (`"42"`, `"-1.5e3"`).
+pub fn to_number(v: &Value) -> Result<Value, String> {
+    match v {
+        Value::Number(_) => Ok(v.clone()),
+        Value::String(s) if s.len() <= 64 && s.trim() == s => serde_json::from_str::<serde_json::Number>(s)
+            .map(Value::Number)
+            .map_err(|_| format!("not a number: {:?}", s)),
+        _ => Err("not a number".into()),
+    }
+}
+
+/// Strings as they are; everything else as canonical (JCS) JSON.
+pub fn to_string(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        _ => Kernel::jcs_string(v),
+    }
+}
@@ -0,0 +1,48 @@
+//! Per-principal trajectories: "identity IS history".
+//!
+//! Derived only from the `activity` of `EffectRecord`s, so the index can be rebuilt
+//! from the ledger at any time. Chips read a trajectory through
+//! `ContextSource::Trajectory`; the bound value lands in the context snapshot,
+//! which is what makes proofs replay.
+
+use crate::engine::Kernel;
+use crate::types::{EffectRecord, Trajectory};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+
+pub type TrajectoryIndex = HashMap<String, Trajectory>;
+
+pub fn index(history: &[EffectRecord]) -> TrajectoryIndex {
+    let mut idx = TrajectoryIndex::new();
+    for r in history { record(&mut idx, r); }
+    idx
+}
+
+/// Folds one record into the index (records without activity are ignored).
+pub fn record(idx: &mut TrajectoryIndex, r: &EffectRecord) {
+    let a = match &r.activity { Some(a) => a, None => return };
+    let t = idx.entry(a.principal.clone()).or_insert_with(|| Trajectory { principal: a.principal.clone(), ..Default::default() });
+
+    t.as_of_version = r.resulting_version;
+    t.operations += 1;
+    let counts = t.programs.entry(a.program.clone()).or_default();
+    if a.allowed { t.allowed += 1; counts.allowed += 1; } else { t.denied += 1; counts.denied += 1; }
+    t.success_rate = t.allowed as f64 / t.operations as f64;
+    if t.first_seen.is_none() { t.first_seen = Some(r.timestamp.clone()); }
+    t.last_seen = Some(r.timestamp.clone());
+    if let Some(cp) = &a.counterparty { t.counterparties.insert(cp.clone()); }
+    t.distinct_counterparties = t.counterparties.len() as u64;
+}
+
+/// Trajectory of `principal` as of ledger `version` (empty if never seen), with
+/// `age_seconds` measured at `at`.
+pub fn as_of(idx: &TrajectoryIndex, principal: &str, version: u64, at: DateTime<Utc>) -> Trajectory {
+    let mut t = idx.get(principal).cloned()
+        .unwrap_or_else(|| Trajectory { principal: principal.to_string(), ..Default::default() });
+    t.as_of_version = version;
+    t.age_seconds = t.first_seen.as_deref()
+        .and_then(Kernel::parse_ts)
+        .map(|f| (at - f).num_seconds().max(0))
+        .unwrap_or(0);
+    t
+}
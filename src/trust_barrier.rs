@@ -1,79 +1,287 @@
+use crate::barrier_parsers;
+use crate::barrier_rules::{self, RuleSet};
+use crate::canon::{self, CaseFold, TextRule};
 use crate::error::UblError;
-use crate::engine::Kernel;
-use crate::types::{BarrierReq, ContentType, ValidatedData};
+use crate::engine::{Kernel, KeyMaterial};
+use crate::types::{BarrierRegistry, BarrierReq, ContentType, EnvelopeRef, Program, RiskSignal, TrustLabel, UnsignedPolicy, ValidatedData};
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use serde::Serialize;
 use serde_json::{json, Value};
 
-fn expect_string(v: &Value, field: &str) -> Result<String, UblError> {
-    v.as_str().map(|s| s.to_string()).ok_or_else(|| UblError::Validation(format!("type_mismatch: {} expected string", field)))
+// ----------------------
+// Schemas
+// ----------------------
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldKind { String, Number, Array }
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldSpec {
+    pub name: &'static str,
+    pub kind: FieldKind,
+    pub required: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<TextRule>, // string fields only
 }
-fn expect_number(v: &Value, field: &str) -> Result<f64, UblError> {
-    v.as_f64().or_else(|| v.as_i64().map(|i| i as f64)).ok_or_else(|| UblError::Validation(format!("type_mismatch: {} expected number", field)))
+
+/// Content-type schema. Its JCS hash is the `schema_hash` carried by envelopes.
+#[derive(Serialize, Debug, Clone)]
+pub struct Schema {
+    pub content_type: ContentType,
+    pub fields: Vec<FieldSpec>,
+    pub passthrough: bool,
 }
 
-pub fn process(req: &BarrierReq) -> Result<ValidatedData, UblError> {
-    let payload = req.payload.as_object().ok_or_else(|| UblError::Validation("payload_must_be_object".into()))?;
+const fn req(name: &'static str, kind: FieldKind) -> FieldSpec { FieldSpec { name, kind, required: true, text: None } }
+const fn opt(name: &'static str, kind: FieldKind) -> FieldSpec { FieldSpec { name, kind, required: false, text: None } }
 
-    let fields = match req.content_type {
-        ContentType::Invoice => {
-            // required: vendor_id, amount, currency, date
-            let vendor_id = payload.get("vendor_id").ok_or_else(|| UblError::Validation("missing: vendor_id".into()))?;
-            let amount = payload.get("amount").ok_or_else(|| UblError::Validation("missing: amount".into()))?;
-            let currency = payload.get("currency").ok_or_else(|| UblError::Validation("missing: currency".into()))?;
-            let date = payload.get("date").ok_or_else(|| UblError::Validation("missing: date".into()))?;
+const IDENT: TextRule = TextRule::ident(CaseFold::None);
+const TEXT: TextRule = TextRule::text();
 
-            let mut out = serde_json::Map::new();
-            out.insert("vendor_id".into(), json!(expect_string(vendor_id, "vendor_id")?));
-            out.insert("amount".into(), json!(expect_number(amount, "amount")?));
-            out.insert("currency".into(), json!(expect_string(currency, "currency")?));
-            out.insert("date".into(), json!(expect_string(date, "date")?));
+impl FieldSpec {
+    const fn rule(mut self, text: TextRule) -> Self { self.text = Some(text); self }
+}
 
-            // optional: description, line_items, reference
-            if let Some(d) = payload.get("description") {
-                if d.is_string() { out.insert("description".into(), d.clone()); }
-            }
-            if let Some(li) = payload.get("line_items") {
-                if li.is_array() { out.insert("line_items".into(), li.clone()); }
-            }
-            if let Some(r) = payload.get("reference") {
-                if r.is_string() { out.insert("reference".into(), r.clone()); }
-            }
+pub fn schema(content_type: &ContentType) -> Schema {
+    use FieldKind::*;
+    let (fields, passthrough) = match content_type {
+        ContentType::Invoice => (vec![
+            req("vendor_id", String).rule(IDENT),
+            req("amount", Number),
+            req("currency", String).rule(TextRule::ident(CaseFold::Upper)),
+            req("date", String).rule(IDENT),
+            opt("description", String).rule(TEXT),
+            opt("line_items", Array),
+            opt("reference", String).rule(IDENT),
+        ], false),
+        ContentType::Email => (vec![
+            req("from", String).rule(TextRule::ident(CaseFold::Lower)),
+            req("to", String).rule(TextRule::ident(CaseFold::Lower)),
+            req("subject", String).rule(TEXT),
+            req("body", String).rule(TEXT),
+            opt("cc", Array),
+            opt("attachments", Array),
+            opt("timestamp", String).rule(IDENT),
+        ], false),
+        // Pass-through but still require object; drop nothing (caller chooses schema)
+        _ => (vec![], true),
+    };
+    Schema { content_type: content_type.clone(), fields, passthrough }
+}
+
+pub fn schema_hash(content_type: &ContentType) -> String {
+    Kernel::jcs_hash(&schema(content_type))
+}
+
+/// Plain decimal literal (`-12`, `149.99`), as produced by text formats.
+fn parse_decimal(s: &str) -> Option<f64> {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let (int, frac) = digits.split_once('.').unwrap_or((digits, "0"));
+    let ok = !int.is_empty() && !frac.is_empty()
+        && int.bytes().all(|b| b.is_ascii_digit()) && frac.bytes().all(|b| b.is_ascii_digit());
+    if ok { s.parse().ok() } else { None }
+}
 
-            Value::Object(out)
+/// `text_numbers`: raw formats carry numbers as strings; accept plain decimals.
+fn coerce(v: &Value, kind: FieldKind, text_numbers: bool) -> Option<Value> {
+    match kind {
+        FieldKind::String => v.as_str().map(|s| json!(s)),
+        FieldKind::Number => v.as_f64().or_else(|| v.as_i64().map(|i| i as f64))
+            .or_else(|| if text_numbers { v.as_str().and_then(|s| parse_decimal(s.trim())) } else { None })
+            .map(|n| json!(n)),
+        FieldKind::Array => v.is_array().then(|| v.clone()),
+    }
+}
+
+/// Applies the field's text rule (control/format rejection, normal form, case);
+/// arrays are canonicalized.
+fn normalize(f: &FieldSpec, v: Value) -> Result<Value, UblError> {
+    match (&f.text, v.as_str()) {
+        (Some(rule), Some(s)) => rule.apply(s)
+            .map(|n| json!(n))
+            .map_err(|e| UblError::Validation(format!("{}: {}", e, f.name))),
+        (None, Some(s)) => Ok(json!(canon::nfc(s))),
+        _ => canon::canonicalize(&v),
+    }
+}
+
+fn kind_name(kind: FieldKind) -> &'static str {
+    match kind { FieldKind::String => "string", FieldKind::Number => "number", FieldKind::Array => "array" }
+}
+
+/// Applies a schema: required fields must be present and typed, optional fields are
+/// kept only if correctly typed, unknown fields are DROPPED. Strings are normalized
+/// per field rule (NFC by default).
+fn apply_schema(schema: &Schema, payload: &serde_json::Map<String, Value>, text_numbers: bool) -> Result<Value, UblError> {
+    if schema.passthrough { return canon::canonicalize(&Value::Object(payload.clone())); }
+
+    let mut out = serde_json::Map::new();
+    for f in &schema.fields {
+        match payload.get(f.name) {
+            None if f.required => return Err(UblError::Validation(format!("missing: {}", f.name))),
+            None => {}
+            Some(v) => match coerce(v, f.kind, text_numbers) {
+                Some(c) => { out.insert(f.name.into(), normalize(f, c)?); }
+                None if f.required => return Err(UblError::Validation(format!("type_mismatch: {} expected {}", f.name, kind_name(f.kind)))),
+                None => {}
+            },
         }
-        ContentType::Email => {
-            // required: from,to,subject,body
-            let from = payload.get("from").ok_or_else(|| UblError::Validation("missing: from".into()))?;
-            let to = payload.get("to").ok_or_else(|| UblError::Validation("missing: to".into()))?;
-            let subject = payload.get("subject").ok_or_else(|| UblError::Validation("missing: subject".into()))?;
-            let body = payload.get("body").ok_or_else(|| UblError::Validation("missing: body".into()))?;
-
-            let mut out = serde_json::Map::new();
-            out.insert("from".into(), json!(expect_string(from, "from")?));
-            out.insert("to".into(), json!(expect_string(to, "to")?));
-            out.insert("subject".into(), json!(expect_string(subject, "subject")?));
-            out.insert("body".into(), json!(expect_string(body, "body")?));
-
-            // optional: cc, attachments, timestamp
-            if let Some(cc) = payload.get("cc") { if cc.is_array() { out.insert("cc".into(), cc.clone()); } }
-            if let Some(att) = payload.get("attachments") { if att.is_array() { out.insert("attachments".into(), att.clone()); } }
-            if let Some(ts) = payload.get("timestamp") { if ts.is_string() { out.insert("timestamp".into(), ts.clone()); } }
-
-            Value::Object(out)
+    }
+    Ok(Value::Object(out))
+}
+
+/// Step 3 of the barrier: verify the payload signature against the trusted-source
+/// registry. Returns `(signer_id, signature_valid, trust)`.
+///
+/// - A signature that is present but unknown/invalid is always rejected.
+/// - An unsigned payload is rejected or labelled per content-type policy.
+fn verify_source(req: &BarrierReq, signed: &[u8], registry: &BarrierRegistry) -> Result<(Option<String>, bool, TrustLabel), UblError> {
+    let sig_b64 = match req.signature.as_deref() {
+        Some(s) => s,
+        None => {
+            let policy = registry.policies.get(&req.content_type).copied().unwrap_or_default();
+            return match policy {
+                UnsignedPolicy::Reject => Err(UblError::Validation("unsigned_payload_rejected".into())),
+                UnsignedPolicy::Label => Ok((None, false, TrustLabel::Unsigned)),
+            };
         }
-        _ => {
-            // Pass-through but still require object; drop nothing (caller chooses schema)
-            Value::Object(payload.clone())
+    };
+
+    let signer_id = req.signer_id.as_deref().ok_or_else(|| UblError::Validation("missing: signer_id".into()))?;
+    let source = registry.sources.get(signer_id)
+        .ok_or_else(|| UblError::Validation(format!("unknown_signer: {}", signer_id)))?;
+    if !source.content_types.is_empty() && !source.content_types.contains(&req.content_type) {
+        return Err(UblError::Validation(format!("signer_not_trusted_for_content_type: {}", signer_id)));
+    }
+    if !Kernel::verify_ed25519_b64(&source.public_key, signed, sig_b64) {
+        return Err(UblError::Validation(format!("signature_invalid: {}", signer_id)));
+    }
+
+    Ok((Some(signer_id.to_string()), true, TrustLabel::Verified))
+}
+
+pub fn process(req: &BarrierReq, registry: &BarrierRegistry) -> Result<ValidatedData, UblError> {
+    // Step 1: parse to structure (no interpretation). Raw inputs are signed over
+    // their bytes, JSON payloads over JCS(payload).
+    let (payload, signed, raw_hash) = match &req.raw {
+        Some(raw) => {
+            if !req.payload.is_null() {
+                return Err(UblError::Validation("payload_and_raw_are_exclusive".into()));
+            }
+            let bytes = B64.decode(&raw.data_b64).map_err(|_| UblError::Validation("raw_not_base64".into()))?;
+            let parsed = barrier_parsers::parse(raw.format, &bytes)?;
+            let raw_hash = Kernel::sha256_hex(&bytes);
+            (Value::Object(parsed), bytes, Some(raw_hash))
+        }
+        None => {
+            let jcs = Kernel::jcs_string(&req.payload);
+            (req.payload.clone(), jcs.into_bytes(), None)
         }
     };
+    let obj = payload.as_object().ok_or_else(|| UblError::Validation("payload_must_be_object".into()))?;
+
+    // Step 2: validate against schema
+    let schema = schema(&req.content_type);
+    let fields = apply_schema(&schema, obj, raw_hash.is_some())?;
+
+    // Step 3: verify signatures; content_hash = sha256(JCS(payload))
+    let content_hash = Kernel::jcs_hash(&payload);
+    let fields_hash = Kernel::jcs_hash(&fields);
+    let (signer_id, signature_valid, trust) = verify_source(req, &signed, registry)?;
 
-    // content_hash = sha256(JCS(payload))
-    let jcs = Kernel::jcs_string(&req.payload);
-    let content_hash = Kernel::sha256_hex(jcs.as_bytes());
+    // Step 4: deterministic risk analysis (signals only, never rewrites fields)
+    let rules = RuleSet::v2();
+    let risk_signals = rules.analyze(&fields);
 
     Ok(ValidatedData {
         content_type: req.content_type.clone(),
         fields,
         content_hash,
+        raw_hash,
+        fields_hash,
+        schema_hash: Kernel::jcs_hash(&schema),
         signature: req.signature.clone(),
+        signer_id,
+        signature_valid,
+        trust,
+        risk_level: barrier_rules::risk_level(&risk_signals),
+        risk_signals,
+        rules_version: rules.version.clone(),
+        rules_hash: rules.hash(),
+        seal: None,
     })
 }
+
+/// Re-derives an envelope's risk signals with the rule set it records
+/// (`rules_version`, checked against `rules_hash`).
+pub fn reanalyze(vd: &ValidatedData) -> Result<Vec<RiskSignal>, UblError> {
+    let rules = RuleSet::by_version(&vd.rules_version)
+        .ok_or_else(|| UblError::Validation(format!("unknown_rules_version: {}", vd.rules_version)))?;
+    if rules.hash() != vd.rules_hash {
+        return Err(UblError::Validation(format!("rules_hash_mismatch: {}", vd.rules_version)));
+    }
+    Ok(rules.analyze(&vd.fields))
+}
+
+// ----------------------
+// Envelope sealing
+// ----------------------
+fn envelope_hash(vd: &ValidatedData) -> String {
+    let mut tmp = vd.clone();
+    tmp.seal = None;
+    Kernel::jcs_hash(&tmp)
+}
+
+fn signing_key_missing() -> UblError {
+    UblError::Validation("barrier_signing_key_missing".into())
+}
+
+/// Seals an envelope with the kernel key so `/execute` can tell it came from the barrier.
+/// Without a signing key no envelope could ever be accepted, so this is an error.
+pub fn seal(vd: &mut ValidatedData, keys: &KeyMaterial) -> Result<(), UblError> {
+    vd.seal = Some(keys.sign_b64(envelope_hash(vd).as_bytes()).ok_or_else(signing_key_missing)?);
+    Ok(())
+}
+
+pub fn verify_seal(vd: &ValidatedData, keys: &KeyMaterial) -> bool {
+    match vd.seal.as_deref() {
+        Some(sig) => keys.verify_sig_b64(envelope_hash(vd).as_bytes(), sig),
+        None => false,
+    }
+}
+
+/// Checks every program input declared as an envelope: it must be a sealed
+/// `ValidatedData` of the declared content type (and schema hash, if pinned).
+/// Returns the envelope references to record in the proof.
+pub fn check_program_inputs(prog: &Program, inputs: &Value, keys: &KeyMaterial) -> Result<Vec<EnvelopeRef>, UblError> {
+    let mut refs = vec![];
+    for input in &prog.inputs {
+        let spec = match &input.envelope { Some(s) => s, None => continue };
+        let raw = match inputs.get(&input.name) {
+            Some(v) => v,
+            None if input.required => return Err(UblError::Validation(format!("missing: {}", input.name))),
+            None => continue,
+        };
+
+        if keys.verifying.is_none() { return Err(signing_key_missing()); }
+        let not_validated = || UblError::Validation(format!("input_not_barrier_validated: {}", input.name));
+        let vd: ValidatedData = serde_json::from_value(raw.clone()).map_err(|_| not_validated())?;
+        if !verify_seal(&vd, keys) { return Err(not_validated()); }
+        if vd.content_type != spec.content_type {
+            return Err(UblError::Validation(format!("envelope_content_type_mismatch: {}", input.name)));
+        }
+        if let Some(sh) = &spec.schema_hash {
+            if sh != &vd.schema_hash {
+                return Err(UblError::Validation(format!("envelope_schema_mismatch: {}", input.name)));
+            }
+        }
+
+        refs.push(EnvelopeRef {
+            input: input.name.clone(),
+            content_type: vd.content_type,
+            content_hash: vd.content_hash,
+            schema_hash: vd.schema_hash,
+        });
+    }
+    Ok(refs)
+}
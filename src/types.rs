@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 
 pub type Hash = String;
 pub type Timestamp = String;
@@ -15,6 +16,28 @@ pub enum Expr {
     Compare { op: CompareOp, left: Box<Expr>, right: Box<Expr> },
     Logic { op: LogicOp, args: Vec<Expr> },
     Call { function: String, args: Vec<Expr> },
+    /// Bounded `op` over the array `over`; `body` is evaluated per element with the
+    /// element bound to `var` (a path root that shadows the context).
+    Collection {
+        op: CollectionOp,
+        over: Box<Expr>,
+        #[serde(default)]
+        var: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        body: Option<Box<Expr>>,
+    },
+    /// Evaluates `value` once and binds it to `name` (a path root) in `body`.
+    Let { name: String, value: Box<Expr>, body: Box<Expr> },
+    /// `then` if `cond` is `true`, otherwise `else` (also for non-boolean conditions).
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        #[serde(rename = "else")]
+        otherwise: Box<Expr>,
+    },
+    /// Another chip, by hash or `CHIP:name`, evaluated on the same context:
+    /// `true` if it ALLOWs. Its proof is embedded in `Proof::subproofs`.
+    Chip { #[serde(rename = "ref")] reference: String },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -33,6 +56,10 @@ pub enum CompareOp {
 #[serde(rename_all = "snake_case")]
 pub enum LogicOp { And, Or, Not }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CollectionOp { All, Any, Filter, Map, Sum, Count, Unique }
+
 // ----------------------
 // Chip
 // ----------------------
@@ -51,6 +78,10 @@ pub enum Composition {
     Full(CompositionDef),
 }
 
+impl Default for Composition {
+    fn default() -> Self { Composition::Shorthand("ALL".into()) }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CompositionDef {
     #[serde(rename="type")]
@@ -62,6 +93,7 @@ pub struct CompositionDef {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum CompositionType { ALL, ANY, MAJORITY, WEIGHTED }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -72,6 +104,12 @@ pub struct Chip {
     pub gates: Vec<Gate>,
     #[serde(default)]
     pub composition: Composition,
+    /// Evaluation errors (unknown function, bad argument, ...) fail their gate and DENY.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strict: bool,
+    /// Fuel limit for one evaluation (capped by `UBL_FUEL_LIMIT`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuel: Option<u64>,
     #[serde(default)]
     pub hash: Hash,
 }
@@ -85,8 +123,21 @@ pub struct ProgramInput {
     #[serde(rename="type")]
     pub input_type: String,
     pub required: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub envelope: Option<EnvelopeSpec>, // input must be a barrier-sealed ValidatedData
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnvelopeSpec {
+    pub content_type: ContentType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema_hash: Option<Hash>,
+}
+
+/// Context keys the kernel binds on every execution (`null` when not
+/// applicable); program context definitions may not use them.
+pub const RESERVED_CONTEXT: &[&str] = &["input", "caller", "operation", "approvals", "signer"];
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ContextDef {
     pub name: String,
@@ -99,7 +150,7 @@ pub struct ContextDef {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "snake_case")]
-pub enum ContextSource { Ledger, Input, Computed }
+pub enum ContextSource { Ledger, Input, Computed, Trajectory, Fingerprint }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Program {
@@ -112,6 +163,14 @@ pub struct Program {
     pub evaluate: Hash,
     pub on_allow: Vec<Effect>,
     pub on_deny: Vec<Effect>,
+    /// Input paths naming the counterparty and amount, recorded in the record's `activity`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<String>,
+    /// Fuel limit for one execution: computed context, chip and effects (capped by `UBL_FUEL_LIMIT`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuel: Option<u64>,
     #[serde(default)]
     pub hash: Hash,
 }
@@ -144,9 +203,98 @@ pub struct Proof {
     pub gates: Vec<GateResult>,
     pub failed_gates: Vec<String>,
     pub final_result: u8,   // 0|1
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub functions: Vec<FunctionRef>, // functions the chip calls, pinned by version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fuel: Option<FuelUsage>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subproofs: Vec<Proof>, // sealed proofs of the chips it references, in evaluation order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub envelopes: Vec<EnvelopeRef>,
     pub proof_hash: Hash,
     #[serde(default)]
     pub signature: Option<String>, // base64(ed25519(sig(proof_hash bytes)))
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>, // keyring id of the signing key (not covered by proof_hash)
+}
+
+// ----------------------
+// Proof verification
+// ----------------------
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum SignaturePolicy {
+    Required,
+    #[default]
+    Optional,
+    Ignored,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum CheckStatus { Passed, Failed, Skipped }
+
+/// Verification report: which checks ran, per-gate replay evidence and the
+/// first point where the proof and its replay diverge.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProofVerification {
+    pub valid: bool,
+    pub signature_policy: SignaturePolicy,
+    pub chip_hash: CheckStatus,
+    pub proof_hash: CheckStatus,
+    pub replay: CheckStatus,
+    pub signature: CheckStatus,
+    pub recomputed_proof_hash: Hash,
+    pub replayed_final_result: u8,
+    pub gates: Vec<GateReplay>,
+    #[serde(default)]
+    pub divergence: Option<Divergence>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GateReplay {
+    pub id: String,
+    pub matches: bool,
+    #[serde(default)]
+    pub recorded: Option<GateResult>, // None: gate missing from the proof
+    #[serde(default)]
+    pub replayed: Option<GateResult>, // None: gate not in the chip
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum VerifyStage { ChipHash, ProofHash, Functions, Gate, Subproofs, FinalResult, Fuel, Signature }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Divergence {
+    pub stage: VerifyStage,
+    #[serde(default)]
+    pub gate: Option<String>,
+    pub detail: String,
+}
+
+/// Fuel limit and consumption of a chip evaluation.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FuelUsage {
+    pub limit: u64,
+    pub used: u64,
+}
+
+/// Function a chip calls: name, version and hash of its spec (see `functions`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FunctionRef {
+    pub name: String,
+    pub version: String,
+    pub hash: Hash,
+}
+
+/// Barrier envelope consumed by an execution, recorded in the proof.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnvelopeRef {
+    pub input: String,
+    pub content_type: ContentType,
+    pub content_hash: Hash,
+    pub schema_hash: Hash,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -157,6 +305,28 @@ pub struct GateResult {
     pub values: GateValues,
     #[serde(default)]
     pub error: Option<String>,
+    /// Lenient chips: the first evaluation error, which did not decide the gate.
+    /// Informational only; proof replay does not compare it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostic: Option<String>,
+    /// Trace mode: the gate expression with the value of every evaluated sub-expression.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Box<TraceNode>>,
+}
+
+/// One evaluated expression. `node` is the expression type, and `label` is its path,
+/// operator, function or bound variable. `decided_by` indexes the `and`/`or`
+/// operand that fixed the result.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TraceNode {
+    pub node: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub label: String,
+    pub value: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decided_by: Option<usize>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TraceNode>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -165,6 +335,19 @@ pub struct GateValues {
     pub left: Option<Value>,
     #[serde(default)]
     pub right: Option<Value>,
+    /// Collection gates: the first element failing `all` (or satisfying `any`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub element: Option<ElementWitness>,
+    /// Chip gates: `proof_hash` of the referenced chip's proof (`left` holds its
+    /// failed gates).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subproof: Option<Hash>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ElementWitness {
+    pub index: usize,
+    pub value: Value,
 }
 
 // ----------------------
@@ -185,6 +368,171 @@ pub struct EffectRecord {
     pub record_hash: Hash,
     #[serde(default)]
     pub record_signature: Option<String>, // base64(ed25519(sig(record_hash bytes)))
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_key_id: Option<String>, // keyring id of the signing key (not covered by record_hash)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_signature: Option<ClientSignature>, // signed request that caused this record
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activity: Option<Activity>,
+}
+
+/// Who executed what, with which outcome. Feeds the per-principal trajectory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Activity {
+    pub principal: String,
+    pub program: String, // program name (stable across versions)
+    pub allowed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterparty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub amount: Option<f64>,
+}
+
+/// Per-principal statistics derived from `EffectRecord` activities, as of a ledger version.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Trajectory {
+    pub principal: String,
+    pub as_of_version: u64,
+    pub operations: u64,
+    pub allowed: u64,
+    pub denied: u64,
+    pub success_rate: f64, // allowed / operations (0 without history)
+    pub programs: std::collections::BTreeMap<String, ProgramCounts>,
+    pub first_seen: Option<Timestamp>,
+    pub last_seen: Option<Timestamp>,
+    pub age_seconds: i64, // first_seen .. evaluation time
+    pub counterparties: std::collections::BTreeSet<String>,
+    pub distinct_counterparties: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ProgramCounts {
+    pub allowed: u64,
+    pub denied: u64,
+}
+
+/// Behavioral baseline of a principal, from its allowed operations.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Fingerprint {
+    pub principal: String,
+    pub as_of_version: u64,
+    pub operations: u64,
+    pub amounts: AmountStats,
+    pub hours: Vec<u64>, // 24 UTC hour buckets
+    pub counterparties: std::collections::BTreeMap<String, u64>,
+    pub op_types: std::collections::BTreeMap<String, u64>, // program name -> count
+}
+
+/// Running mean/variance (Welford), folded in ledger order.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AmountStats {
+    pub count: u64,
+    pub mean: f64,
+    pub m2: f64,
+    pub stddev: f64, // population standard deviation
+}
+
+// ----------------------
+// Keyring
+// ----------------------
+/// Public half of a kernel signing key with its validity period.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyEntry {
+    pub key_id: String,
+    pub public_key: String, // base64(ed25519 verifying key)
+    pub valid_from: Timestamp,
+    #[serde(default)]
+    pub valid_until: Option<Timestamp>, // None = active
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Revocation {
+    pub key_id: String,
+    pub revoked_at: Timestamp,
+    #[serde(default)]
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Keyring {
+    #[serde(default)]
+    pub keys: Vec<KeyEntry>,
+    #[serde(default)]
+    pub revoked: Vec<Revocation>,
+}
+
+// ----------------------
+// Multisig approvals
+// ----------------------
+/// The operation an approval is bound to. Approvers sign the hex of
+/// `sha256(JCS(Operation))`, so an approval cannot be replayed for another
+/// program, other inputs or another ledger version.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Operation {
+    pub program_hash: Hash,
+    pub input_hash: Hash,
+    pub target_version: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Approval {
+    pub signer_id: String,
+    pub signature: String, // base64(ed25519(operation hash hex bytes))
+}
+
+// ----------------------
+// Identities
+// ----------------------
+/// Registered identity. Authenticates with an API token and/or Ed25519-signed
+/// requests and may only do what its scopes allow:
+/// `admin`, `register`, `verify`, `execute` (any program),
+/// `program:<name>`, `chip:<name|hash>` (execute programs evaluating that chip).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Principal {
+    pub id: String,
+    #[serde(default)]
+    pub public_key: Option<String>, // base64(ed25519 verifying key), for signed requests
+    #[serde(default)]
+    pub token_hash: Option<Hash>, // sha256 hex of the API token (the token itself is never stored)
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+// ----------------------
+// Client-signed requests
+// ----------------------
+/// Client signature over `JCS({program, inputs, target_version, nonce, expiry})`.
+/// Kept in the `EffectRecord` for non-repudiation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClientSignature {
+    pub signer_id: String,
+    pub nonce: String,
+    pub expiry: Timestamp,
+    pub signature: String, // base64(ed25519(JCS bytes))
+}
+
+// ----------------------
+// Standard signature envelopes
+// ----------------------
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum EnvelopeFormat { JwsCompact, JwsJson, CoseSign1 }
+
+/// Flattened JWS JSON serialization (RFC 7515 §7.2.2), all members base64url.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JwsJson {
+    pub protected: String,
+    pub payload: String,
+    pub signature: String,
+}
+
+/// A proof or record wrapped in a standard envelope (payload = JCS of the artifact).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag="format", rename_all="snake_case")]
+pub enum SignedEnvelope {
+    JwsCompact { token: String },
+    JwsJson { jws: JwsJson },
+    CoseSign1 { data_b64: String }, // base64(tagged COSE_Sign1 CBOR)
 }
 
 // ----------------------
@@ -196,6 +544,14 @@ pub struct ExecReq {
     pub inputs: Value,
     #[serde(default)]
     pub target_version: Option<u64>,
+    #[serde(default)]
+    pub envelope: Option<EnvelopeFormat>, // also return proof + record in this envelope
+    #[serde(default)]
+    pub approvals: Vec<Approval>, // bound to the operation; requires target_version
+    #[serde(default)]
+    pub client_signature: Option<ClientSignature>,
+    #[serde(default)]
+    pub trace: bool, // record an evaluation trace per gate in the proof
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -203,17 +559,43 @@ pub struct ExecReq {
 pub enum RegisterReq {
     Chip { data: Chip },
     Program { data: Program },
+    Source { data: TrustedSource },
+    BarrierPolicy { data: BarrierPolicy },
+    Principal { data: Principal },
+    /// Chips and programs in the textual policy language (see `policy`).
+    Policy { source: String },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct VerifyReq {
+    #[serde(default)]
+    pub proof: Option<Proof>,
+    #[serde(default)]
+    pub envelope: Option<SignedEnvelope>, // alternative to `proof`: JWS / COSE_Sign1
+    #[serde(default)]
+    pub signature_policy: Option<SignaturePolicy>, // may only tighten UBL_SIGNATURE_POLICY
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundleReq {
     pub proof: Proof,
+    #[serde(default)]
+    pub include_chain: bool,
+    #[serde(default)]
+    pub checkpoint_version: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevokeKeyReq {
+    pub key_id: String,
+    #[serde(default)]
+    pub reason: String,
 }
 
 // ----------------------
 // Trust / Barrier
 // ----------------------
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(rename_all="snake_case")]
 pub enum ContentType {
     Invoice,
@@ -226,16 +608,106 @@ pub enum ContentType {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BarrierReq {
     pub content_type: ContentType,
-    pub payload: Value,
     #[serde(default)]
-    pub signature: Option<String>,
+    pub payload: Value, // JSON object (exclusive with `raw`)
+    #[serde(default)]
+    pub raw: Option<RawPayload>,
+    #[serde(default)]
+    pub signature: Option<String>, // base64(ed25519(sig(JCS(payload) | raw bytes)))
+    #[serde(default)]
+    pub signer_id: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum RawFormat { Csv, Email, Form }
+
+/// Raw, non-JSON input with a declared format (see `barrier_parsers`).
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawPayload {
+    pub format: RawFormat,
+    pub data_b64: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum TrustLabel { Verified, Unsigned }
+
+/// Unknown keys are rejected: chips read the envelope as sent, so anything the
+/// seal does not cover must not get through.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ValidatedData {
     pub content_type: ContentType,
     pub fields: Value,
-    pub content_hash: String,
+    pub content_hash: String, // sha256(JCS(parsed payload))
+    #[serde(default)]
+    pub raw_hash: Option<Hash>, // sha256(raw bytes), raw inputs only
+    #[serde(default)]
+    pub fields_hash: Hash, // sha256(JCS(fields)) after normalization
+    #[serde(default)]
+    pub schema_hash: String,
     #[serde(default)]
     pub signature: Option<String>,
+    #[serde(default)]
+    pub signer_id: Option<String>,
+    #[serde(default)]
+    pub signature_valid: bool,
+    pub trust: TrustLabel,
+    #[serde(default)]
+    pub risk_signals: Vec<RiskSignal>,
+    #[serde(default)]
+    pub risk_level: Option<Severity>, // highest signal severity
+    #[serde(default)]
+    pub rules_version: String,
+    #[serde(default)]
+    pub rules_hash: Hash,
+    #[serde(default)]
+    pub seal: Option<String>, // base64(ed25519(kernel key, JCS hash of envelope without seal))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all="snake_case")]
+pub enum Severity { Low, Medium, High }
+
+/// Finding emitted by the barrier's analysis rules (see `barrier_rules`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RiskSignal {
+    pub rule: String,
+    pub field: String,
+    pub severity: Severity,
+    #[serde(default)]
+    pub detail: String,
+}
+
+/// A source (vendor, upstream API) whose Ed25519 key the barrier trusts.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrustedSource {
+    pub id: String,
+    pub public_key: String, // base64(ed25519 verifying key)
+    #[serde(default)]
+    pub content_types: Vec<ContentType>, // empty = any content type
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all="snake_case")]
+pub enum UnsignedPolicy {
+    Reject,
+    #[default]
+    Label,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BarrierPolicy {
+    pub content_type: ContentType,
+    pub unsigned: UnsignedPolicy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BarrierRegistry {
+    #[serde(default)]
+    pub sources: HashMap<String, TrustedSource>,
+    #[serde(default)]
+    pub policies: HashMap<ContentType, UnsignedPolicy>,
 }
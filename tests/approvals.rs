@@ -0,0 +1,166 @@
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::{json, Value};
+use ubl_core::engine::{ExecMeta, ExecOptions, Kernel, KeyMaterial};
+use ubl_core::ledger::Ledger;
+use ubl_core::types::*;
+
+fn signer(n: u8) -> SigningKey {
+    SigningKey::from_bytes(&[n; 32])
+}
+
+fn signer_set() -> Value {
+    let mut set = serde_json::Map::new();
+    for (id, n) in [("alice", 1), ("bob", 2), ("carol", 3)] {
+        set.insert(id.into(), json!(B64.encode(signer(n).verifying_key().as_bytes())));
+    }
+    Value::Object(set)
+}
+
+fn operation(target_version: u64) -> Operation {
+    Operation { program_hash: "p".repeat(64), input_hash: "i".repeat(64), target_version }
+}
+
+fn approve(id: &str, n: u8, op: &Operation) -> Approval {
+    let sig = signer(n).sign(Kernel::operation_hash(op).as_bytes());
+    Approval { signer_id: id.into(), signature: B64.encode(sig.to_bytes()) }
+}
+
+fn threshold() -> Expr {
+    Expr::Call {
+        function: "threshold_signatures".into(),
+        args: vec![
+            Expr::Path { path: vec!["approvals".into()], fallback: None },
+            Expr::Literal { value: signer_set() },
+            Expr::Literal { value: json!(2) },
+        ],
+    }
+}
+
+fn chip() -> Chip {
+    chip_with(threshold())
+}
+
+fn chip_with(expr: Expr) -> Chip {
+    let mut chip = Chip {
+        name: "two_of_three".into(),
+        description: "".into(),
+        gates: vec![Gate { id: "approved".into(), description: "".into(), expr }],
+        composition: Composition::default(),
+        strict: false,
+        fuel: None,
+        hash: "".into(),
+    };
+    chip.hash = Kernel::compute_chip_hash(&chip);
+    chip
+}
+
+fn run(op: &Operation, approvals: Vec<Approval>) -> Proof {
+    run_chip(&chip(), op, approvals)
+}
+
+fn run_chip(chip: &Chip, op: &Operation, approvals: Vec<Approval>) -> Proof {
+    let mut bound = json!(op);
+    bound["hash"] = json!(Kernel::operation_hash(op));
+    let ctx = json!({ "operation": bound, "approvals": approvals });
+    let meta = ExecMeta { tx_id: "t".into(), execution_time: chrono::Utc::now() };
+    let mut proof = Kernel::execute_chip(chip, &ctx, &meta, &ExecOptions::default());
+    Kernel::seal_proof(&mut proof, &KeyMaterial::default());
+    proof
+}
+
+#[test]
+fn threshold_records_verified_signer_set() {
+    let op = operation(7);
+    let proof = run(&op, vec![approve("carol", 3, &op), approve("alice", 1, &op)]);
+    assert_eq!(proof.final_result, 1);
+    assert_eq!(proof.gates[0].values.left, Some(json!(["alice", "carol"])));
+    assert_eq!(proof.gates[0].values.right, Some(json!(2)));
+
+    // the recorded evidence replays
+    assert!(Kernel::verify_proof(&proof, &chip(), &KeyMaterial::default(), &ExecOptions::default()).valid);
+}
+
+#[test]
+fn nested_threshold_records_signer_set() {
+    let op = operation(7);
+    let known = Expr::Compare {
+        op: CompareOp::Exists,
+        left: Box::new(Expr::Path { path: vec!["operation".into(), "hash".into()], fallback: None }),
+        right: Box::new(Expr::Literal { value: Value::Null }),
+    };
+    let chip = chip_with(Expr::Logic { op: LogicOp::And, args: vec![known, threshold()] });
+    let proof = run_chip(&chip, &op, vec![approve("bob", 2, &op)]);
+    assert_eq!(proof.final_result, 0);
+    assert_eq!(proof.gates[0].values.left, Some(json!(["bob"])));
+    assert_eq!(proof.gates[0].values.right, Some(json!(2)));
+    assert!(Kernel::verify_proof(&proof, &chip, &KeyMaterial::default(), &ExecOptions::default()).valid);
+}
+
+#[test]
+fn duplicate_signers_are_rejected() {
+    let op = operation(7);
+    let proof = run(&op, vec![approve("alice", 1, &op), approve("alice", 1, &op), approve("bob", 2, &op)]);
+    assert_eq!(proof.final_result, 0);
+    assert_eq!(proof.gates[0].error.as_deref(), Some("duplicate_signer: alice"));
+}
+
+#[test]
+fn approvals_for_another_operation_do_not_count() {
+    let op = operation(7);
+    let stale = operation(6);
+    let proof = run(&op, vec![approve("alice", 1, &stale), approve("bob", 2, &stale), approve("carol", 3, &op)]);
+    assert_eq!(proof.final_result, 0);
+    assert_eq!(proof.gates[0].values.left, Some(json!(["carol"])));
+}
+
+#[test]
+fn programs_cannot_define_reserved_context() {
+    let program = Program {
+        name: "spoof".into(),
+        description: "".into(),
+        inputs: vec![],
+        context: vec![ContextDef { name: "operation".into(), source: ContextSource::Input, path: "op".into(), expression: None }],
+        evaluate: chip().hash,
+        on_allow: vec![],
+        on_deny: vec![],
+        counterparty: None,
+        amount: None,
+        fuel: None,
+        hash: "".into(),
+    };
+    let err = Ledger::in_memory().register_program(program).unwrap_err();
+    assert!(err.to_string().contains("reserved_context_name: operation"));
+}
+
+#[tokio::test]
+async fn stored_programs_binding_reserved_context_do_not_run() {
+    use axum::{extract::State, http::HeaderMap, Json};
+    use std::sync::Arc;
+
+    // a ledger written before `operation` was reserved
+    let path = std::env::temp_dir().join(format!("ubl_legacy_{}.json", std::process::id()));
+    let ledger = Ledger::open(&path);
+    ledger.register_chip(chip()).unwrap();
+    ledger.register_program(Program {
+        name: "legacy".into(),
+        description: "".into(),
+        inputs: vec![],
+        context: vec![ContextDef { name: "op".into(), source: ContextSource::Input, path: "op".into(), expression: None }],
+        evaluate: chip().hash,
+        on_allow: vec![],
+        on_deny: vec![],
+        counterparty: None,
+        amount: None,
+        fuel: None,
+        hash: "".into(),
+    }).unwrap();
+    ledger.commit().await.unwrap();
+    let stored = std::fs::read_to_string(&path).unwrap().replace(r#""name": "op""#, r#""name": "operation""#);
+    std::fs::write(&path, stored).unwrap();
+
+    let req = serde_json::from_value(json!({ "program": "legacy", "inputs": { "op": {} } })).unwrap();
+    let err = ubl_core::api::execute(State(Arc::new(Ledger::open(&path))), HeaderMap::new(), Json(req)).await.unwrap_err();
+    std::fs::remove_file(&path).unwrap();
+    assert!(err.to_string().contains("reserved_context_name: operation (program legacy)"), "{}", err);
+}
@@ -0,0 +1,111 @@
+use ed25519_dalek::SigningKey;
+use serde_json::json;
+use ubl_core::bundle::{ProofBundle, BUNDLE_VERSION};
+use ubl_core::engine::{Kernel, KeyMaterial};
+use ubl_core::functions::builtin_registry;
+use ubl_core::types::*;
+
+mod common;
+use common::positive_chip;
+
+fn record(version: u64, prev: Option<&EffectRecord>, proof_hash: &str, program_hash: &str, keys: &KeyMaterial) -> EffectRecord {
+    let mut r = EffectRecord { proof_hash: proof_hash.into(), program_hash: program_hash.into(), ..common::record(version, prev) };
+    Kernel::sign_record(&mut r, keys);
+    r
+}
+
+fn issuer() -> KeyMaterial {
+    KeyMaterial::from_signing_key(SigningKey::from_bytes(&[5u8; 32]))
+}
+
+/// What a counterparty pins: the issuer's public key, nothing from the bundle.
+fn trusted() -> KeyMaterial {
+    KeyMaterial::pinned(vec![common::key_entry(&issuer(), "2025-01-01T00:00:00Z", None)])
+}
+
+fn bundle() -> ProofBundle {
+    signed_bundle(&issuer())
+}
+
+fn signed_bundle(keys: &KeyMaterial) -> ProofBundle {
+    let meta = common::meta_at("2025-06-01T00:00:00Z");
+    let proof = common::sealed_proof(&positive_chip(), &json!({"amount": 3}), &meta, keys);
+
+    let mut program = Program {
+        name: "pay".into(),
+        description: "".into(),
+        inputs: vec![],
+        context: vec![],
+        evaluate: "CHIP:positive".into(),
+        on_allow: vec![],
+        on_deny: vec![],
+        counterparty: None,
+        amount: None,
+        fuel: None,
+        hash: "".into(),
+    };
+    program.hash = Kernel::compute_program_hash(&program);
+
+    let r1 = record(1, None, &proof.proof_hash, &program.hash, keys);
+    let r2 = record(2, Some(&r1), "other", "other", keys);
+    let r3 = record(3, Some(&r2), "other", "other", keys);
+
+    ProofBundle {
+        version: BUNDLE_VERSION,
+        proof,
+        chip: positive_chip(),
+        chips: vec![],
+        program: Some(program),
+        record: Some(r1),
+        keys: vec![common::key_entry(keys, "2025-01-01T00:00:00Z", None)],
+        chain: vec![r2, r3],
+    }
+}
+
+#[test]
+fn bundle_verifies_offline_up_to_checkpoint() {
+    let b = bundle();
+    let json = serde_json::to_string(&b).unwrap();
+    let report = serde_json::from_str::<ProofBundle>(&json).unwrap().verify(SignaturePolicy::Required, &trusted(), builtin_registry());
+    assert!(report.valid, "{:?}", report.errors);
+    assert_eq!(report.proof.signature, CheckStatus::Passed);
+    assert_eq!(report.checkpoint_version, Some(3));
+}
+
+#[test]
+fn bundle_detects_broken_chain_and_foreign_program() {
+    let mut b = bundle();
+    b.chain.remove(0);
+    let report = b.verify(SignaturePolicy::Optional, &trusted(), builtin_registry());
+    assert!(!report.valid);
+    assert_eq!(report.chain, CheckStatus::Failed);
+
+    let mut b = bundle();
+    b.program.as_mut().unwrap().evaluate = "CHIP:something_else".into();
+    let report = b.verify(SignaturePolicy::Optional, &trusted(), builtin_registry());
+    assert!(!report.valid);
+    assert_eq!(report.program, CheckStatus::Failed);
+}
+
+#[test]
+fn bundle_signed_with_its_own_key_is_rejected() {
+    // a forger signs proof, record and checkpoint with a fresh key and ships it
+    let forged = signed_bundle(&KeyMaterial::from_signing_key(SigningKey::from_bytes(&[9u8; 32])));
+    let report = forged.verify(SignaturePolicy::Required, &trusted(), builtin_registry());
+    assert!(!report.valid);
+    assert_eq!(report.keys, CheckStatus::Failed);
+    assert_eq!(report.proof.signature, CheckStatus::Failed);
+    assert!(report.errors.iter().any(|e| e.starts_with("untrusted_key: ")), "{:?}", report.errors);
+
+    // without the key hint, the signatures alone still fail
+    let mut stripped = forged;
+    stripped.keys.clear();
+    let report = stripped.verify(SignaturePolicy::Required, &trusted(), builtin_registry());
+    assert!(!report.valid);
+    assert_eq!(report.keys, CheckStatus::Skipped);
+
+    // revoked issuer keys are honoured
+    let mut revoked = trusted();
+    revoked.keyring.revoked.push(Revocation { key_id: issuer().key_id.unwrap(), revoked_at: "2025-01-01T00:00:00Z".into(), reason: "leak".into() });
+    assert!(!bundle().verify(SignaturePolicy::Required, &revoked, builtin_registry()).valid);
+}
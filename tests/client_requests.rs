@@ -0,0 +1,95 @@
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use chrono::{Duration, Utc};
+use ed25519_dalek::{Signer, SigningKey};
+use serde_json::json;
+use ubl_core::engine::{ExecMeta, FuelMeter, Kernel, KeyMaterial};
+use ubl_core::ledger::{Ledger, Origin};
+use ubl_core::types::*;
+
+mod common;
+
+fn client_key() -> SigningKey {
+    SigningKey::from_bytes(&[21; 32])
+}
+
+fn signed_req(expiry: chrono::DateTime<Utc>) -> ExecReq {
+    let mut req: ExecReq = serde_json::from_value(json!({
+        "program": "transfer",
+        "inputs": { "from_id": "w1", "to_id": "w2", "amount": 100 },
+        "target_version": 3
+    })).unwrap();
+    let mut auth = ClientSignature {
+        signer_id: "client-test".into(),
+        nonce: "n-1".into(),
+        expiry: expiry.to_rfc3339(),
+        signature: "".into(),
+    };
+    let msg = Kernel::client_request_message(&req, &auth);
+    auth.signature = B64.encode(client_key().sign(msg.as_bytes()).to_bytes());
+    req.client_signature = Some(auth);
+    req
+}
+
+fn ledger() -> Ledger {
+    let ledger = Ledger::in_memory();
+    ledger.register_principal(Principal {
+        id: "client-test".into(),
+        public_key: Some(B64.encode(client_key().verifying_key().as_bytes())),
+        token_hash: None,
+        scopes: vec!["execute".into()],
+    }).unwrap();
+    ledger
+}
+
+fn check(ledger: &Ledger, req: &ExecReq) -> Result<(), String> {
+    let meta = ExecMeta { tx_id: "t".into(), execution_time: Utc::now() };
+    ledger.verify_client_request(req, req.client_signature.as_ref().unwrap(), &meta).map(|_| ()).map_err(|e| e.to_string())
+}
+
+#[test]
+fn signed_request_covers_program_inputs_and_version() {
+    let ledger = ledger();
+    let req = signed_req(Utc::now() + Duration::minutes(5));
+    assert_eq!(check(&ledger, &req), Ok(()));
+
+    let mut tampered = req.clone();
+    tampered.inputs["amount"] = json!(1_000_000);
+    assert!(check(&ledger, &tampered).unwrap_err().contains("client_signature_invalid"));
+
+    let mut retargeted = req.clone();
+    retargeted.target_version = Some(4);
+    assert!(check(&ledger, &retargeted).unwrap_err().contains("client_signature_invalid"));
+
+    let mut unknown = req;
+    unknown.client_signature.as_mut().unwrap().signer_id = "someone-else".into();
+    assert!(check(&ledger, &unknown).unwrap_err().contains("unknown_client"));
+}
+
+#[test]
+fn expiry_is_enforced_and_bounded() {
+    let ledger = ledger();
+    let expired = signed_req(Utc::now() - Duration::seconds(1));
+    assert!(check(&ledger, &expired).unwrap_err().contains("request_expired"));
+
+    let far = signed_req(Utc::now() + Duration::days(2));
+    assert!(check(&ledger, &far).unwrap_err().contains("request_expiry_too_far"));
+}
+
+#[tokio::test]
+async fn replayed_request_is_rejected() {
+    let ledger = ledger();
+    let req = signed_req(Utc::now() + Duration::minutes(5));
+    let auth = req.client_signature.clone().unwrap();
+    let meta = common::meta();
+    let proof = common::sealed_proof(&common::positive_chip(), &json!({ "amount": 1 }), &meta, &KeyMaterial::default());
+    let (keys, fuel) = (KeyMaterial::default(), FuelMeter::unlimited());
+    let apply = || ledger.apply_transaction(
+        "p", "i", None, &proof, &[], &meta, &keys,
+        Origin { client_signature: Some(auth.clone()), activity: None }, &fuel,
+    );
+
+    assert_eq!(check(&ledger, &req), Ok(()));
+    apply().await.unwrap();
+    assert!(check(&ledger, &req).unwrap_err().contains("nonce_replayed"));
+    assert!(apply().await.unwrap_err().to_string().contains("nonce_replayed"));
+}
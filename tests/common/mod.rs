@@ -0,0 +1,84 @@
+//! Fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use serde_json::json;
+use ubl_core::engine::{ExecMeta, ExecOptions, Kernel, KeyMaterial};
+use ubl_core::types::*;
+
+pub fn meta() -> ExecMeta {
+    ExecMeta { tx_id: "t".into(), execution_time: chrono::Utc::now() }
+}
+
+pub fn meta_at(rfc3339: &str) -> ExecMeta {
+    ExecMeta { tx_id: "t".into(), execution_time: rfc3339.parse().unwrap() }
+}
+
+/// `field <op> value` against the context.
+pub fn gate(id: &str, field: &str, op: CompareOp, value: i64) -> Gate {
+    Gate {
+        id: id.into(),
+        description: "".into(),
+        expr: Expr::Compare {
+            op,
+            left: Box::new(Expr::Path { path: vec![field.into()], fallback: None }),
+            right: Box::new(Expr::Literal { value: json!(value) }),
+        },
+    }
+}
+
+/// An ALL chip over `gates`, with its hash set.
+pub fn chip(name: &str, gates: Vec<Gate>) -> Chip {
+    let mut chip = Chip {
+        name: name.into(),
+        description: "".into(),
+        gates,
+        composition: Composition::default(),
+        strict: false,
+        fuel: None,
+        hash: "".into(),
+    };
+    chip.hash = Kernel::compute_chip_hash(&chip);
+    chip
+}
+
+/// `amount > 0`, the chip the signing tests sign over.
+pub fn positive_chip() -> Chip {
+    chip("positive", vec![gate("g", "amount", CompareOp::Gt, 0)])
+}
+
+/// An unsigned record at `version`, chained to `prev`.
+pub fn record(version: u64, prev: Option<&EffectRecord>) -> EffectRecord {
+    EffectRecord {
+        id: format!("tx{}", version),
+        version_applied_to: version - 1,
+        resulting_version: version,
+        timestamp: "2025-06-01T00:00:00Z".into(),
+        program_hash: "p".into(),
+        input_hash: "i".into(),
+        proof_hash: "h".into(),
+        applied_effects: vec![],
+        previous_record_hash: prev.map(|p| p.record_hash.clone()),
+        record_hash: "".into(),
+        record_signature: None,
+        record_key_id: None,
+        client_signature: None,
+        activity: None,
+    }
+}
+
+pub fn key_entry(keys: &KeyMaterial, valid_from: &str, valid_until: Option<&str>) -> KeyEntry {
+    KeyEntry {
+        key_id: keys.key_id.clone().unwrap(),
+        public_key: B64.encode(keys.verifying.unwrap().as_bytes()),
+        valid_from: valid_from.into(),
+        valid_until: valid_until.map(|s| s.into()),
+    }
+}
+
+/// `chip` executed with the default options and sealed with `keys`.
+pub fn sealed_proof(chip: &Chip, ctx: &serde_json::Value, meta: &ExecMeta, keys: &KeyMaterial) -> Proof {
+    let mut proof = Kernel::execute_chip(chip, ctx, meta, &ExecOptions::default());
+    Kernel::seal_proof(&mut proof, keys);
+    proof
+}
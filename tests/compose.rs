@@ -0,0 +1,100 @@
+use serde_json::json;
+use ubl_core::engine::{ExecOptions, Kernel, KeyMaterial, MAX_CHIP_NESTING};
+use ubl_core::policy;
+use ubl_core::types::*;
+
+mod common;
+use common::meta;
+
+fn chips(src: &str) -> Vec<Chip> {
+    policy::parse(src).unwrap().chips
+}
+
+const LIBRARY: &str = r#"
+chip kyc_passed
+  gate level: kyc >= 2
+  gate not_frozen: not frozen
+
+chip small_amount
+  gate limit: amount <= 1000
+
+chip transfer
+  compose ALL
+  gate kyc: chip("CHIP:kyc_passed")
+  gate amount_ok: chip("CHIP:small_amount") or approved
+"#;
+
+#[test]
+fn chips_compose_with_embedded_subproofs() {
+    let lib = chips(LIBRARY);
+    let transfer = lib[2].clone();
+    assert!(matches!(&transfer.gates[0].expr, Expr::Chip { reference } if reference == "CHIP:kyc_passed"));
+    assert_eq!(chips(&policy::print_chip(&transfer))[0].hash, transfer.hash);
+
+    let ctx = json!({ "kyc": 3, "frozen": false, "amount": 5000, "approved": true });
+    let mut proof = Kernel::execute_chip(&transfer, &ctx, &meta(), &ExecOptions { fuel_limit: Some(10_000), chips: &lib, ..Default::default() });
+    assert_eq!(proof.final_result, 1);
+    assert_eq!(proof.subproofs.len(), 2);
+    let kyc = &proof.subproofs[0];
+    assert_eq!((kyc.chip_hash.as_str(), kyc.final_result), (lib[0].hash.as_str(), 1));
+    assert_eq!(kyc.proof_hash, Kernel::compute_proof_hash(kyc));
+    assert_eq!(proof.gates[0].values.subproof.as_ref(), Some(&kyc.proof_hash));
+    // the sub-chip's fuel is the parent's
+    assert!(proof.fuel.unwrap().used > kyc.fuel.unwrap().used + proof.subproofs[1].fuel.unwrap().used);
+
+    // each sub-proof verifies on its own, and the parent replays them all
+    assert!(Kernel::verify_proof(kyc, &lib[0], &KeyMaterial::default(), &ExecOptions::default()).valid);
+    Kernel::seal_proof(&mut proof, &KeyMaterial::default());
+    let report = Kernel::verify_proof(&proof, &transfer, &KeyMaterial::default(), &ExecOptions { chips: &lib, ..Default::default() });
+    assert!(report.valid, "{:?}", report.divergence);
+    // without the library the references do not resolve
+    let report = Kernel::verify_proof(&proof, &transfer, &KeyMaterial::default(), &ExecOptions::default());
+    assert!(!report.valid);
+
+    // a doctored sub-proof, resealed, is caught on replay
+    let mut forged = proof.clone();
+    forged.subproofs[1].final_result = 1;
+    forged.subproofs[1].failed_gates.clear();
+    Kernel::seal_proof(&mut forged, &KeyMaterial::default());
+    let report = Kernel::verify_proof(&forged, &transfer, &KeyMaterial::default(), &ExecOptions { chips: &lib, ..Default::default() });
+    let d = report.divergence.unwrap();
+    assert_eq!((d.stage, d.detail), (VerifyStage::Subproofs, format!("chip={}", lib[1].hash)));
+
+    // the gate's evidence names the failed sub-gates
+    let denied = Kernel::execute_chip(&transfer, &json!({ "kyc": 1, "frozen": false, "amount": 1 }), &meta(), &ExecOptions { fuel_limit: Some(10_000), chips: &lib, ..Default::default() });
+    assert_eq!(denied.final_result, 0);
+    assert_eq!(denied.gates[0].values.left, Some(json!(["level"])));
+    assert_eq!(denied.failed_gates, vec!["kyc".to_string()]);
+}
+
+#[test]
+fn references_are_checked() {
+    let ctx = json!({ "kyc": 3, "frozen": false });
+    let lib = chips("chip a\n  compose ANY\n  gate loop: chip(\"CHIP:b\")\n  gate ok: kyc >= 2\n\nchip b\n  gate back: chip(\"CHIP:a\")\n");
+
+    // a cycle DENYs every chip on it, whatever the composition
+    let proof = Kernel::execute_chip(&lib[0], &ctx, &meta(), &ExecOptions { fuel_limit: Some(10_000), chips: &lib, ..Default::default() });
+    assert_eq!(proof.final_result, 0);
+    assert_eq!(proof.gates[0].error.as_deref(), Some("chip_cycle: CHIP:a"));
+    assert_eq!(proof.subproofs[0].gates[0].error.as_deref(), Some("chip_cycle: CHIP:a"));
+
+    // an unknown chip is an ordinary error: the gate fails, strict chips DENY
+    let lib = chips("chip c\n  compose ANY\n  gate missing: chip(\"CHIP:nope\")\n  gate ok: kyc >= 2\n");
+    let proof = Kernel::execute_chip(&lib[0], &ctx, &meta(), &ExecOptions { fuel_limit: Some(10_000), chips: &lib, ..Default::default() });
+    assert_eq!((proof.final_result, proof.gates[0].result), (1, false));
+    let mut strict = lib[0].clone();
+    strict.strict = true;
+    let proof = Kernel::execute_chip(&strict, &ctx, &meta(), &ExecOptions { fuel_limit: Some(10_000), chips: &lib, ..Default::default() });
+    assert_eq!((proof.final_result, proof.gates[0].error.as_deref()), (0, Some("unknown_chip: CHIP:nope")));
+
+    // references nest only so deep
+    let src: String = (0..=MAX_CHIP_NESTING)
+        .map(|i| format!("chip c{}\n  gate next: chip(\"CHIP:c{}\")\n\n", i, i + 1))
+        .chain(std::iter::once(format!("chip c{}\n  gate ok: true\n", MAX_CHIP_NESTING + 1)))
+        .collect();
+    let lib = chips(&src);
+    let proof = Kernel::execute_chip(&lib[0], &ctx, &meta(), &ExecOptions { fuel_limit: Some(10_000), chips: &lib, ..Default::default() });
+    assert_eq!((proof.final_result, proof.gates[0].error.as_deref()), (0, Some("depth_exceeded")));
+    let proof = Kernel::execute_chip(&lib[2], &ctx, &meta(), &ExecOptions { fuel_limit: Some(10_000), chips: &lib, ..Default::default() });
+    assert_eq!(proof.final_result, 1);
+}
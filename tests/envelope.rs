@@ -0,0 +1,60 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as B64URL, Engine as _};
+use ed25519_dalek::SigningKey;
+use serde_json::json;
+use ubl_core::engine::{Kernel, KeyMaterial};
+use ubl_core::envelope::{self, EnvelopeContent};
+use ubl_core::types::*;
+
+mod common;
+
+fn signed_proof(keys: &KeyMaterial) -> Proof {
+    let chip = common::chip("limit", vec![common::gate("under_limit", "amount", CompareOp::Lt, 1000)]);
+    common::sealed_proof(&chip, &json!({"amount": 10}), &common::meta(), keys)
+}
+
+#[test]
+fn proof_roundtrips_through_every_envelope_format() {
+    let keys = KeyMaterial::from_signing_key(SigningKey::from_bytes(&[7; 32]));
+    let proof = signed_proof(&keys);
+
+    for format in [EnvelopeFormat::JwsCompact, EnvelopeFormat::JwsJson, EnvelopeFormat::CoseSign1] {
+        let env = envelope::wrap(&proof, format, &keys).unwrap();
+        match Kernel::verify_envelope(&env, &keys).unwrap() {
+            EnvelopeContent::Proof(p) => assert_eq!(p.proof_hash, proof.proof_hash),
+            EnvelopeContent::Record(_) => panic!("expected proof"),
+        }
+    }
+
+    // JWS header carries the standard fields; payload is the JCS of the proof.
+    let jws = envelope::jws_json(&proof, &keys).unwrap();
+    let header: serde_json::Value = serde_json::from_slice(&B64URL.decode(&jws.protected).unwrap()).unwrap();
+    assert_eq!(header["alg"], "EdDSA");
+    assert_eq!(header["kid"], json!(keys.key_id));
+    assert_eq!(B64URL.decode(&jws.payload).unwrap(), Kernel::jcs_string(&proof).into_bytes());
+
+    // COSE_Sign1 is tag 18 (0xd2) around a 4-element array.
+    let cose = envelope::cose_sign1(&proof, &keys).unwrap();
+    assert_eq!(&cose[..2], &[0xd2, 0x84]);
+}
+
+#[test]
+fn tampered_or_foreign_envelopes_are_rejected() {
+    let keys = KeyMaterial::from_signing_key(SigningKey::from_bytes(&[7; 32]));
+    let other = KeyMaterial::from_signing_key(SigningKey::from_bytes(&[8; 32]));
+    let proof = signed_proof(&keys);
+
+    let token = envelope::jws_compact(&proof, &keys).unwrap();
+    assert!(Kernel::verify_jws_compact(&token, &other).is_err());
+
+    let mut forged = proof.clone();
+    forged.final_result = 0;
+    let parts: Vec<&str> = token.split('.').collect();
+    let swapped = format!("{}.{}.{}", parts[0], B64URL.encode(Kernel::jcs_string(&forged)), parts[2]);
+    assert!(Kernel::verify_jws_compact(&swapped, &keys).is_err());
+
+    let mut cose = envelope::cose_sign1(&proof, &keys).unwrap();
+    let last = cose.len() - 1;
+    cose[last] ^= 1;
+    assert!(Kernel::verify_cose_sign1(&cose, &keys).is_err());
+    assert!(Kernel::verify_cose_sign1(&cose[..cose.len() - 3], &keys).is_err());
+}
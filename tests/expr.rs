@@ -0,0 +1,202 @@
+use serde_json::{json, Value};
+use ubl_core::calendar;
+use ubl_core::engine::{ExecMeta, ExecOptions, Kernel};
+use ubl_core::policy;
+use ubl_core::types::*;
+
+mod common;
+use common::meta;
+
+fn lit(value: Value) -> Expr {
+    Expr::Literal { value }
+}
+
+fn call(function: &str, args: Vec<Expr>) -> Expr {
+    Expr::Call { function: function.into(), args }
+}
+
+#[test]
+fn string_comparisons_are_normalization_insensitive() {
+    let composed = "caf\u{00E9}";
+    let decomposed = "cafe\u{0301}";
+
+    let eq = Expr::Compare { op: CompareOp::Eq, left: Box::new(lit(json!(composed))), right: Box::new(lit(json!(decomposed))) };
+    assert_eq!(Kernel::eval_expr(&eq, &json!({}), &meta()), json!(true));
+
+    let allow = Expr::Compare { op: CompareOp::In, left: Box::new(lit(json!(decomposed))), right: Box::new(lit(json!([composed]))) };
+    assert_eq!(Kernel::eval_expr(&allow, &json!({}), &meta()), json!(true));
+
+    assert_eq!(Kernel::eval_expr(&call("contains", vec![lit(json!("Le Caf\u{00E9}")), lit(json!(decomposed))]), &json!({}), &meta()), json!(false));
+    assert_eq!(Kernel::eval_expr(&call("contains", vec![lit(json!("le caf\u{00E9}")), lit(json!(decomposed))]), &json!({}), &meta()), json!(true));
+    assert_eq!(Kernel::eval_expr(&call("casefold", vec![lit(json!("\u{FF21}cme"))]), &json!({}), &meta()), json!("acme"));
+
+    assert_eq!(Kernel::input_hash(&json!({"v": composed})).unwrap(), Kernel::input_hash(&json!({"v": decomposed})).unwrap());
+
+    // keys equal only after NFC would otherwise overwrite each other
+    let mut colliding = serde_json::Map::new();
+    colliding.insert(composed.into(), json!(1));
+    colliding.insert(decomposed.into(), json!(2));
+    let err = Kernel::input_hash(&Value::Object(colliding)).unwrap_err();
+    assert!(err.to_string().contains("duplicate_key_after_nfc"), "{}", err);
+}
+
+#[test]
+fn collections_bind_each_element() {
+    let ctx = json!({
+        "invoice": { "amount": 30, "lines": [{"sku": "a", "amount": 10}, {"sku": "b", "amount": 20}, {"sku": "a", "amount": 0}] },
+        "approvals": [{"by": "ops"}, {"by": "guardian"}]
+    });
+    let eval = |src: &str| Kernel::eval_expr(&policy::parse_expr(src).unwrap(), &ctx, &meta());
+
+    assert_eq!(eval("all(invoice.lines, l => l.amount >= 0)"), json!(true));
+    assert_eq!(eval("sum(invoice.lines, l => l.amount) == invoice.amount"), json!(true));
+    assert_eq!(eval("any(approvals, a => a.by == \"guardian\")"), json!(true));
+    assert_eq!(eval("count(filter(invoice.lines, l => l.amount > 0))"), json!(2));
+    assert_eq!(eval("map(invoice.lines, l => l.sku)"), json!(["a", "b", "a"]));
+    assert_eq!(eval("unique(invoice.lines, l => l.sku)"), json!(["a", "b"]));
+    // the bound variable shadows the context root; nested scopes see outer variables
+    assert_eq!(eval("all(invoice.lines, invoice => invoice.amount < 100)"), json!(true));
+    assert_eq!(eval("all(approvals, a => any(invoice.lines, l => l.sku == \"b\" and a.by != null))"), json!(true));
+    // not an array: no value
+    assert_eq!(eval("all(invoice.amount, x => true)"), Value::Null);
+}
+
+#[test]
+fn collection_gates_report_the_failing_element() {
+    let mut chip = Chip {
+        name: "lines".into(),
+        description: "".into(),
+        gates: vec![Gate { id: "positive".into(), description: "".into(), expr: policy::parse_expr("all(lines, l => l.amount > 0)").unwrap() }],
+        composition: Composition::default(),
+        strict: false,
+        fuel: None,
+        hash: "".into(),
+    };
+    chip.hash = Kernel::compute_chip_hash(&chip);
+
+    let proof = Kernel::execute_chip(&chip, &json!({"lines": [{"amount": 5}, {"amount": 0}, {"amount": -1}]}), &meta(), &ExecOptions::default());
+    let values = &proof.gates[0].values;
+    assert!(!proof.gates[0].result);
+    assert_eq!(values.element, Some(ElementWitness { index: 1, value: json!({"amount": 0}) }));
+    assert_eq!((values.left.clone(), values.right.clone()), (Some(json!(0)), Some(json!(0))));
+
+    let proof = Kernel::execute_chip(&chip, &json!({"lines": "nope"}), &meta(), &ExecOptions::default());
+    assert_eq!(proof.gates[0].error.as_deref(), Some("collection_not_array"));
+}
+
+#[test]
+fn let_and_if_bind_and_branch() {
+    let ctx = json!({ "amount": 1500, "lines": [{"amount": 1000}, {"amount": 500}] });
+    let eval = |src: &str| Kernel::eval_expr(&policy::parse_expr(src).unwrap(), &ctx, &meta());
+
+    assert_eq!(eval("if amount > 1000 then 10 else 1"), json!(10));
+    assert_eq!(eval("let total = sum(lines, l => l.amount); total == amount and total > 0"), json!(true));
+    // non-boolean conditions take the else branch; inner bindings shadow outer ones
+    assert_eq!(eval("if amount then 1 else 2"), json!(2));
+    assert_eq!(eval("let x = 1; let x = 2; x"), json!(2));
+
+    // as a gate, evidence comes from the branch taken
+    let gate = policy::parse_expr("let fee = if amount > 1000 then 10 else 1; fee <= amount").unwrap();
+    let mut chip = Chip { name: "fee".into(), description: "".into(), gates: vec![Gate { id: "fee".into(), description: "".into(), expr: gate }], composition: Composition::default(), strict: false, fuel: None, hash: "".into() };
+    chip.hash = Kernel::compute_chip_hash(&chip);
+    let proof = Kernel::execute_chip(&chip, &ctx, &meta(), &ExecOptions::default());
+    assert!(proof.gates[0].result);
+    assert_eq!((proof.gates[0].values.left.clone(), proof.gates[0].values.right.clone()), (Some(json!(10)), Some(json!(1500))));
+}
+
+#[test]
+fn evaluation_errors_name_their_cause() {
+    let ctx = json!({ "amount": 5, "name": "x", "created": "yesterday" });
+    let err = |src: &str| Kernel::try_eval_expr(&policy::parse_expr(src).unwrap(), &ctx, &meta()).unwrap_err().to_string();
+
+    assert_eq!(err("iban_vaild(name)"), "unknown_function: iban_vaild");
+    assert_eq!(err("starts_with(name)"), "missing_argument: starts_with #1");
+    assert_eq!(err("div(amount, 0)"), "division_by_zero");
+    assert_eq!(err("age(created) > 60"), "invalid_timestamp: age: \"yesterday\"");
+    assert_eq!(err("name > 3"), "type_mismatch: > expected number, got string");
+    assert_eq!(err("not missing"), "type_mismatch: not expected boolean, got null");
+    // the fallback value itself is unchanged
+    assert_eq!(Kernel::eval_expr(&policy::parse_expr("div(amount, 0)").unwrap(), &ctx, &meta()), json!(0.0));
+    assert_eq!(Kernel::try_eval_expr(&policy::parse_expr("lower(name) == \"x\"").unwrap(), &ctx, &meta()), Ok(json!(true)));
+}
+
+#[test]
+fn strict_chips_deny_on_evaluation_errors() {
+    let src = "chip sanctions\n  gate not_sanctioned: not iban_vaild(iban)\n  gate amount: amount > 0\n";
+    let mut chip = policy::parse(src).unwrap().chips.remove(0);
+    let ctx = json!({ "iban": "DE00", "amount": 5 });
+
+    // lenient: the typo still allows; the gate notes it as a diagnostic, not an error
+    let proof = Kernel::execute_chip(&chip, &ctx, &meta(), &ExecOptions::default());
+    assert_eq!((proof.final_result, proof.gates[0].error.as_deref()), (1, None));
+    assert_eq!(proof.gates[0].diagnostic.as_deref(), Some("unknown_function: iban_vaild"));
+
+    chip.strict = true;
+    chip.hash = Kernel::compute_chip_hash(&chip);
+    assert_eq!(policy::parse(&policy::print_chip(&chip)).unwrap().chips[0].hash, chip.hash);
+    let proof = Kernel::execute_chip(&chip, &ctx, &meta(), &ExecOptions::default());
+    assert_eq!(proof.final_result, 0);
+    assert_eq!(proof.failed_gates, vec!["not_sanctioned"]);
+    assert_eq!(proof.gates[0].error.as_deref(), Some("unknown_function: iban_vaild"));
+    assert_eq!(proof.gates[1].error, None);
+}
+
+#[test]
+fn date_functions_follow_zones_and_calendars() {
+    // Friday 2025-01-31 23:30 UTC, already Saturday in Tokyo
+    let meta = ExecMeta { tx_id: "t".into(), execution_time: "2025-01-31T23:30:00Z".parse().unwrap() };
+    let ctx = json!({ "cal": { "holidays": ["2025-02-03"] }, "due": "2025-03-01T00:00:00Z" });
+    let eval = |src: &str| Kernel::try_eval_expr(&policy::parse_expr(src).unwrap(), &ctx, &meta).unwrap();
+
+    assert_eq!(eval("add_duration(now(), \"P1M\")"), json!("2025-02-28T23:30:00Z"));
+    assert_eq!(eval("add_duration(now(), \"P1Y2W3DT1H30M\")"), json!("2026-02-18T01:00:00Z"));
+    assert_eq!(eval("add_duration(due, \"-P30D\")"), json!("2025-01-30T00:00:00Z"));
+    assert_eq!(eval("days_between(now(), due)"), json!(29));
+    assert_eq!(eval("days_between(now(), due, \"Asia/Tokyo\")"), json!(28));
+    assert_eq!(eval("start_of_month(now(), \"+09:00\")"), json!("2025-01-31T15:00:00Z"));
+    assert_eq!((eval("weekday(now())"), eval("weekday(now(), \"Asia/Tokyo\")")), (json!(5), json!(6)));
+    assert_eq!(eval("time_bucket(now(), \"week\")"), json!("2025-W05"));
+    assert_eq!(eval("time_bucket(now(), \"month\", \"Asia/Tokyo\")"), json!("2025-02"));
+
+    // Monday 2025-02-03 is a holiday: one business day after Friday is Tuesday
+    assert_eq!(eval("is_business_day(now(), cal)"), json!(true));
+    assert_eq!(eval("is_business_day(now(), cal, \"Asia/Tokyo\")"), json!(false));
+    assert_eq!(eval("add_business_days(now(), 1, cal)"), json!("2025-02-04T23:30:00Z"));
+    assert_eq!(eval("add_business_days(\"2025-02-04T09:00:00Z\", -1, cal)"), json!("2025-01-31T09:00:00Z"));
+
+    let err = |src: &str| Kernel::try_eval_expr(&policy::parse_expr(src).unwrap(), &ctx, &meta).unwrap_err().to_string();
+    assert_eq!(err("add_duration(now(), \"30 days\")"), "invalid_argument: add_duration: duration \"30 days\"");
+    assert_eq!(err("weekday(now(), \"Mars/Olympus\")"), "invalid_argument: weekday: time zone \"Mars/Olympus\"");
+    assert_eq!(err("weekday(now(), \"+99999999:00\")"), "invalid_argument: weekday: time zone \"+99999999:00\"");
+    for bad in ["+24:00", "-05:60", "+-5:00", "+05:"] {
+        assert!(calendar::zone(bad).is_none(), "{}", bad);
+    }
+    assert!(calendar::zone("-23:59").is_some());
+    assert_eq!(err("is_business_day(now(), {\"weekend\": [8]})"), "invalid_argument: is_business_day: calendar");
+}
+
+#[test]
+fn string_functions_are_bounded() {
+    let ctx = json!({ "iban": " GB82 WEST 1234 5698 7654 32 ", "email": "Ana@Example.com", "qty": "12", "price": 2.5 });
+    let eval = |src: &str| Kernel::try_eval_expr(&policy::parse_expr(src).unwrap(), &ctx, &meta());
+
+    assert_eq!(eval(r#"matches(replace(trim(iban), " ", ""), "^[A-Z]{2}[0-9]{2}[A-Z0-9]{11,30}$")"#), Ok(json!(true)));
+    assert_eq!(eval(r#"matches(lower(email), "^[^@\\s]+@[^@\\s]+\\.[a-z]{2,}$")"#), Ok(json!(true)));
+    assert_eq!(eval(r#"split(lower(email), "@")"#), Ok(json!(["ana", "example.com"])));
+    assert_eq!(eval(r#"substr(trim(iban), 0, 4)"#), Ok(json!("GB82")));
+    assert_eq!(eval(r#"substr(trim(iban), -2)"#), Ok(json!("32")));
+    assert_eq!(eval(r#"to_number(qty) == 12"#), Ok(json!(true)));
+    assert_eq!(eval(r#"concat(qty, "x", price, true)"#), Ok(json!("12x2.5true")));
+    assert_eq!(eval(r#"format("{} @ {1} = {{total}}", to_number(qty), price)"#), Ok(json!("12 @ 2.5 = {total}")));
+    assert_eq!(eval(r#"to_string([1, "a", null])"#), Ok(json!("[1,\"a\",null]")));
+
+    let err = |src: &str| eval(src).unwrap_err().to_string();
+    assert!(err(r#"matches(email, "(a")"#).starts_with("invalid_argument: matches: pattern:"));
+    assert!(err(r#"matches(email, "(a)\\1")"#).contains("backreferences"));
+    assert_eq!(err(r#"to_number("12 apples")"#), "invalid_argument: to_number: not a number: \"12 apples\"");
+    assert_eq!(err(r#"format("{2}", 1)"#), "invalid_argument: format: no argument 2");
+    // results are capped, so repeated growth fails instead of allocating
+    let big = (0..17).fold("replace(\"ab\", \"a\", \"aaaa\")".to_string(), |e, _| format!("replace({}, \"a\", \"aa\")", e));
+    assert_eq!(err(&big), format!("invalid_argument: replace: longer than {} bytes", ubl_core::text::MAX_STRING_LEN));
+    assert_eq!(Kernel::eval_expr(&policy::parse_expr(&big).unwrap(), &ctx, &meta()), json!(""));
+}
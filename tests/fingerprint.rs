@@ -0,0 +1,84 @@
+use serde_json::json;
+use ubl_core::engine::{ExecOptions, Kernel, KeyMaterial};
+use ubl_core::fingerprint;
+use ubl_core::types::*;
+
+mod common;
+
+fn record(version: u64, hour: u32, program: &str, to: &str, amount: f64, allowed: bool) -> EffectRecord {
+    EffectRecord {
+        timestamp: format!("2025-06-{:02}T{:02}:15:00Z", version, hour),
+        activity: Some(Activity {
+            principal: "alice".into(),
+            program: program.into(),
+            allowed,
+            counterparty: Some(to.into()),
+            amount: Some(amount),
+        }),
+        ..common::record(version, None)
+    }
+}
+
+fn history() -> Vec<EffectRecord> {
+    vec![
+        record(1, 9, "transfer", "bob", 100.0, true),
+        record(2, 10, "transfer", "bob", 120.0, true),
+        record(3, 9, "transfer", "carol", 80.0, true),
+        record(4, 9, "refund", "bob", 100.0, true),
+        record(5, 3, "transfer", "mallory", 90_000.0, false), // denied: not part of the baseline
+    ]
+}
+
+#[test]
+fn scores_compare_against_allowed_history() {
+    let fp = fingerprint::as_of(&fingerprint::index(&history()), "alice", 5);
+    assert_eq!(fp.operations, 4);
+    assert_eq!(fp.amounts.count, 4);
+    assert!((fp.amounts.mean - 100.0).abs() < 1e-9);
+
+    assert!(fingerprint::amount_zscore(&fp, 100.0).abs() < 1e-9);
+    assert!(fingerprint::amount_zscore(&fp, 10_000.0) > 100.0);
+    assert_eq!(fingerprint::hour_anomaly(&fp, "2025-07-01T09:40:00Z".parse().unwrap()), 0.0);
+    assert_eq!(fingerprint::hour_anomaly(&fp, "2025-07-01T03:00:00Z".parse().unwrap()), 1.0);
+    assert!(fingerprint::new_counterparty(&fp, "mallory"));
+    assert!(!fingerprint::new_counterparty(&fp, "carol"));
+    assert!((fingerprint::op_type_anomaly(&fp, "refund") - 2.0 / 3.0).abs() < 1e-9);
+    assert_eq!(fingerprint::op_type_anomaly(&fp, "close_account"), 1.0);
+
+    // no history: no signal
+    let empty = fingerprint::as_of(&fingerprint::index(&[]), "bob", 0);
+    assert_eq!(fingerprint::amount_zscore(&empty, 1e9), 0.0);
+    assert_eq!(fingerprint::op_type_anomaly(&empty, "transfer"), 0.0);
+}
+
+#[test]
+fn shadow_chip_replays_from_bound_fingerprint() {
+    let fp = fingerprint::as_of(&fingerprint::index(&history()), "alice", 5);
+    let call = |f: &str, arg: serde_json::Value| Expr::Call {
+        function: f.into(),
+        args: vec![Expr::Path { path: vec!["fp".into()], fallback: None }, Expr::Literal { value: arg }],
+    };
+    let mut chip = Chip {
+        name: "shadow".into(),
+        description: "".into(),
+        gates: vec![
+            Gate { id: "amount_usual".into(), description: "".into(), expr: Expr::Compare {
+                op: CompareOp::Lt, left: Box::new(call("amount_zscore", json!(5000))), right: Box::new(Expr::Literal { value: json!(3) }),
+            }},
+            Gate { id: "known_counterparty".into(), description: "".into(), expr: Expr::Logic {
+                op: LogicOp::Not, args: vec![call("new_counterparty", json!("mallory"))],
+            }},
+        ],
+        composition: Composition::default(),
+        strict: false,
+        fuel: None,
+        hash: "".into(),
+    };
+    chip.hash = Kernel::compute_chip_hash(&chip);
+
+    let meta = common::meta();
+    let proof = common::sealed_proof(&chip, &json!({ "fp": fp }), &meta, &KeyMaterial::default());
+    assert_eq!(proof.failed_gates, vec!["amount_usual", "known_counterparty"]);
+    assert_eq!(proof.context_snapshot["fp"]["amounts"]["count"], json!(4));
+    assert!(Kernel::verify_proof(&proof, &chip, &KeyMaterial::default(), &ExecOptions::default()).valid);
+}
@@ -0,0 +1,110 @@
+use serde_json::json;
+use ubl_core::engine::{ExecOptions, FuelMeter, Kernel, KeyMaterial, MAX_EXPR_DEPTH};
+use ubl_core::functions::builtin_registry;
+use ubl_core::ledger::{Ledger, Origin, EFFECTS_FAILED};
+use ubl_core::policy;
+use ubl_core::types::*;
+
+mod common;
+use common::meta;
+
+#[test]
+fn exhausted_fuel_denies_deterministically() {
+    let src = "chip busy\n  compose ANY\n  fuel 60\n  gate cheap: kyc >= 2\n  gate costly: all(items, x => x > 0)\n  gate never: kyc >= 1\n";
+    let chip = policy::parse(src).unwrap().chips.remove(0);
+    assert_eq!(chip.fuel, Some(60));
+    assert_eq!(policy::parse(&policy::print_chip(&chip)).unwrap().chips[0].hash, chip.hash);
+    let ctx = json!({ "kyc": 3, "items": (1..=100).collect::<Vec<_>>() });
+
+    let mut proof = Kernel::execute_chip(&chip, &ctx, &meta(), &ExecOptions::default());
+    assert_eq!(proof.fuel, Some(FuelUsage { limit: 60, used: 60 }));
+    // ANY would allow on the first gate, but running out of fuel is a DENY
+    assert!(proof.gates[0].result);
+    assert_eq!(proof.final_result, 0);
+    assert_eq!(proof.gates[1].error.as_deref(), Some("fuel_exhausted"));
+    assert_eq!(proof.gates[2].error.as_deref(), Some("fuel_exhausted"));
+
+    // the replay uses the recorded limit, whatever the chip or environment says now
+    Kernel::seal_proof(&mut proof, &KeyMaterial::default());
+    let mut relaxed = chip.clone();
+    relaxed.fuel = Some(10_000);
+    relaxed.hash = chip.hash.clone();
+    assert!(Kernel::verify_proof(&proof, &relaxed, &KeyMaterial::default(), &ExecOptions::default()).valid);
+
+    let mut forged = proof.clone();
+    forged.fuel = Some(FuelUsage { limit: 60, used: 42 });
+    Kernel::seal_proof(&mut forged, &KeyMaterial::default());
+    let report = Kernel::verify_proof(&forged, &chip, &KeyMaterial::default(), &ExecOptions::default());
+    assert_eq!(report.divergence.unwrap().stage, VerifyStage::Fuel);
+
+    let allowed = Kernel::execute_chip(&relaxed, &ctx, &meta(), &ExecOptions::default());
+    assert_eq!(allowed.final_result, 1);
+    // each of the 100 elements costs its visit plus the body's nodes
+    assert!(allowed.fuel.unwrap().used > 400);
+}
+
+#[test]
+fn nesting_is_bounded() {
+    let deep = format!("{}ok{}", "(not ".repeat(MAX_EXPR_DEPTH), ")".repeat(MAX_EXPR_DEPTH));
+    let err = policy::parse_expr(&deep).unwrap_err();
+    assert!(err.message.contains("nested deeper than"), "{}", err.message);
+    // parenthesized operands are parsed once, not once per enclosing level
+    let parens = format!("{}ok{}", "(not ".repeat(MAX_EXPR_DEPTH / 2 - 1), ")".repeat(MAX_EXPR_DEPTH / 2 - 1));
+    assert!(policy::parse_expr(&parens).is_ok());
+    // far deeper than any stack allows to recurse freely
+    assert!(policy::parse_expr(&"(".repeat(100_000)).is_err());
+
+    // chips built directly are cut off at evaluation, with a DENY
+    let mut expr = Expr::Literal { value: json!(true) };
+    for _ in 0..4 * MAX_EXPR_DEPTH { expr = Expr::Logic { op: LogicOp::Not, args: vec![expr] }; }
+    let mut chip = Chip {
+        name: "deep".into(),
+        description: "".into(),
+        gates: vec![Gate { id: "g".into(), description: "".into(), expr }],
+        composition: Composition::default(),
+        strict: false,
+        fuel: None,
+        hash: "".into(),
+    };
+    chip.hash = Kernel::compute_chip_hash(&chip);
+    let proof = Kernel::execute_chip(&chip, &json!({}), &meta(), &ExecOptions::default());
+    assert_eq!((proof.final_result, proof.gates[0].error.as_deref()), (0, Some("depth_exceeded")));
+
+    let fuel = FuelMeter::new(5);
+    assert_eq!(Kernel::eval_expr_metered(&policy::parse_expr("add(1, 2)").unwrap(), &json!({}), &meta(), &fuel, builtin_registry()), json!(null));
+    assert_eq!(fuel.remaining(), 0);
+}
+
+#[tokio::test]
+async fn a_deny_is_recorded_when_the_chip_used_all_the_fuel() {
+    let src = "chip busy\n  gate costly: all(items, x => x > 0)\n";
+    let chip = policy::parse(src).unwrap().chips.remove(0);
+    let ctx = json!({ "items": (1..=100).collect::<Vec<_>>() });
+
+    // the program's one budget, as /execute shares it between chip and effects
+    let fuel = FuelMeter::new(200);
+    let mut proof = Kernel::execute_chip(&chip, &ctx, &meta(), &ExecOptions { fuel_limit: Some(fuel.remaining()), ..Default::default() });
+    fuel.charge(proof.fuel.map_or(0, |f| f.used));
+    Kernel::seal_proof(&mut proof, &KeyMaterial::default());
+    assert_eq!(proof.final_result, 0);
+    assert_eq!(fuel.remaining(), 0);
+
+    let on_deny = vec![Effect::Emit { event: "denied".into(), data: json!({}) }];
+    let activity = Activity { principal: "alice".into(), program: "busy".into(), allowed: false, counterparty: None, amount: None };
+    let ledger = Ledger::in_memory();
+    let record = ledger.apply_transaction(
+        "p", "i", None, &proof, &on_deny, &meta(), &KeyMaterial::default(),
+        Origin { client_signature: None, activity: Some(activity) }, &fuel,
+    ).await.unwrap();
+    assert_eq!(record.resulting_version, 1);
+    assert!(matches!(&record.applied_effects[..], [Effect::Emit { event, data }]
+        if event == EFFECTS_FAILED && data["error"] == json!("fuel_exhausted: effects (limit 200)")));
+
+    // an ALLOW whose effects do not fit is still refused
+    let mut allowed = proof.clone();
+    allowed.final_result = 1;
+    let err = ledger.apply_transaction(
+        "p", "i", None, &allowed, &on_deny, &meta(), &KeyMaterial::default(), Origin { client_signature: None, activity: None }, &fuel,
+    ).await.unwrap_err();
+    assert!(err.to_string().contains("fuel_exhausted: effects"));
+}
@@ -0,0 +1,64 @@
+use serde_json::{json, Value};
+use std::sync::Arc;
+use ubl_core::engine::{CallContext, ExecMeta, ExecOptions, Kernel, KeyMaterial};
+use ubl_core::error::EvalError;
+use ubl_core::functions::{ArgType, FunctionSpec, Functions};
+use ubl_core::ledger::Ledger;
+use ubl_core::policy;
+use ubl_core::types::*;
+
+fn iban_valid(args: &[Value], _cx: &CallContext) -> Value {
+    let iban: String = args[0].as_str().unwrap_or("").chars().filter(|c| !c.is_whitespace()).collect();
+    if iban.len() < 5 || !iban.chars().all(|c| c.is_ascii_alphanumeric()) { return json!(false); }
+    let digits: String = iban[4..].chars().chain(iban[..4].chars())
+        .map(|c| c.to_digit(36).unwrap().to_string())
+        .collect();
+    json!(digits.bytes().fold(0u32, |r, d| (r * 10 + (d - b'0') as u32) % 97) == 1)
+}
+
+fn registry(version: &str) -> Functions {
+    let mut fns = Functions::builtins();
+    fns.register(FunctionSpec::new("iban_valid", version, &[ArgType::String], ArgType::Bool), iban_valid).unwrap();
+    fns
+}
+
+#[test]
+fn calls_are_checked_against_signatures() {
+    let meta = ExecMeta { tx_id: "t".into(), execution_time: chrono::Utc::now() };
+    let eval = |src: &str| Kernel::try_eval_expr(&policy::parse_expr(src).unwrap(), &json!({"n": 3}), &meta);
+    assert_eq!(eval("max(n, 4)"), Ok(json!(4.0)));
+    assert_eq!(eval("max(n, 4, 5)"), Err(EvalError::Arity { function: "max".into(), expected: 2, found: 3 }));
+    assert_eq!(eval("abs()"), Err(EvalError::MissingArgument { function: "abs".into(), index: 0 }));
+    assert_eq!(eval("upper(n)"), Err(EvalError::TypeMismatch { at: "upper#0".into(), expected: "string", found: "number" }));
+    assert_eq!(eval("frobnicate(n)"), Err(EvalError::UnknownFunction("frobnicate".into())));
+
+    // lenient evaluation keeps each function's fallback
+    assert_eq!(Kernel::eval_expr(&policy::parse_expr("max(n, 4, 5)").unwrap(), &json!({"n": 3}), &meta), json!(4.0));
+    assert!(Functions::builtins().specs().iter().any(|s| s.name == "hour_anomaly" && s.min_arity() == 1));
+}
+
+#[test]
+fn embedder_functions_are_pinned_in_proofs() {
+    let (v1, v2) = (registry("1"), registry("2"));
+    assert!(registry("1").register(FunctionSpec::new("lower", "2", &[ArgType::String], ArgType::String), |_, _| json!("")).is_err());
+
+    let chip = policy::parse("chip payout\n  gate iban: iban_valid(beneficiary.iban) and lower(currency) == \"eur\"\n").unwrap().chips.remove(0);
+    let meta = ExecMeta { tx_id: "t".into(), execution_time: chrono::Utc::now() };
+    let ctx = json!({"beneficiary": {"iban": "GB82 WEST 1234 5698 7654 32"}, "currency": "EUR"});
+    let mut proof = Kernel::execute_chip(&chip, &ctx, &meta, &ExecOptions { functions: &v1, ..Default::default() });
+    assert_eq!(proof.final_result, 1);
+    let pinned: Vec<_> = proof.functions.iter().map(|f| (f.name.as_str(), f.version.as_str())).collect();
+    assert_eq!(pinned, [("iban_valid", "1"), ("lower", "1")]);
+    Kernel::seal_proof(&mut proof, &KeyMaterial::default());
+    assert!(Kernel::verify_proof(&proof, &chip, &KeyMaterial::default(), &ExecOptions { functions: &v1, ..Default::default() }).valid);
+
+    // a new version of the function no longer replays the old proof
+    let report = Kernel::verify_proof(&proof, &chip, &KeyMaterial::default(), &ExecOptions { functions: &v2, ..Default::default() });
+    assert!(!report.valid);
+    let d = report.divergence.unwrap();
+    assert_eq!((d.stage, d.detail.as_str()), (VerifyStage::Functions, "changed=[iban_valid@2]"));
+
+    // each ledger evaluates against its own registry; others keep the builtins
+    assert!(Ledger::in_memory().with_functions(Arc::new(v1)).functions().get("iban_valid").is_some());
+    assert!(Ledger::in_memory().functions().get("iban_valid").is_none());
+}
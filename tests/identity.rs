@@ -0,0 +1,79 @@
+use axum::http::HeaderMap;
+use ubl_core::identity::{self, Action, Caller};
+use ubl_core::ledger::Ledger;
+use ubl_core::types::*;
+
+fn chip(name: &str) -> Chip {
+    Chip { name: name.into(), description: "".into(), gates: vec![], composition: Composition::default(), strict: false, fuel: None, hash: format!("h-{}", name) }
+}
+
+fn caller(scopes: &[&str]) -> Caller {
+    Caller { id: "agent-7".into(), scopes: scopes.iter().map(|s| s.to_string()).collect() }
+}
+
+#[test]
+fn scopes_gate_actions_programs_and_chips() {
+    let teller = caller(&["program:transfer", "verify"]);
+    assert!(teller.require(Action::Execute).is_ok());
+    assert!(teller.require(Action::Verify).is_ok());
+    assert!(teller.require(Action::Register).is_err());
+    assert!(teller.require_execute("transfer", &chip("limits")).is_ok());
+    assert!(teller.require_execute("payout", &chip("limits")).is_err());
+
+    let by_chip = caller(&["chip:limits"]);
+    assert!(by_chip.require_execute("payout", &chip("limits")).is_ok());
+    assert!(by_chip.require_execute("payout", &chip("other")).is_err());
+
+    let admin = caller(&["admin"]);
+    assert!(admin.require(Action::Admin).is_ok());
+    assert!(admin.require_execute("anything", &chip("any")).is_ok());
+
+    assert!(identity::valid_scope("program:transfer"));
+    assert!(!identity::valid_scope("program:"));
+    assert!(!identity::valid_scope("superuser"));
+}
+
+#[test]
+fn tokens_resolve_to_registered_principals() {
+    let ledger = Ledger::in_memory();
+    let bad = Principal { id: "x".into(), public_key: None, token_hash: None, scopes: vec![] };
+    assert!(ledger.register_principal(bad).is_err());
+
+    ledger.register_principal(Principal {
+        id: "reporter".into(),
+        public_key: None,
+        token_hash: Some(identity::token_hash("s3cret")),
+        scopes: vec!["verify".into()],
+    }).unwrap();
+
+    let mut headers = HeaderMap::new();
+    headers.insert("authorization", "Bearer s3cret".parse().unwrap());
+    let who = identity::authorize(&headers, &ledger, Action::Verify).unwrap();
+    assert_eq!(who.id, "reporter");
+    assert!(identity::authorize(&headers, &ledger, Action::Register).is_err());
+
+    headers.insert("authorization", "Bearer wrong".parse().unwrap());
+    assert!(identity::authenticate(&headers, &ledger).is_err());
+    // once principals exist, anonymous access is closed
+    assert!(identity::authenticate(&HeaderMap::new(), &ledger).is_err());
+}
+
+#[test]
+fn token_hashes_identify_one_principal() {
+    let ledger = Ledger::in_memory();
+    let with_token = |id: &str, token: &str| Principal {
+        id: id.into(),
+        public_key: None,
+        token_hash: Some(identity::token_hash(token)),
+        scopes: vec!["verify".into()],
+    };
+    ledger.register_principal(with_token("reporter", "s3cret")).unwrap();
+    let err = ledger.register_principal(with_token("intruder", "s3cret")).unwrap_err();
+    assert!(err.to_string().contains("token_hash_conflict: intruder"));
+
+    // rotating a principal's token releases the old one
+    ledger.register_principal(with_token("reporter", "n3w")).unwrap();
+    assert!(ledger.principal_by_token_hash(&identity::token_hash("s3cret")).is_none());
+    assert_eq!(ledger.principal_by_token_hash(&identity::token_hash("n3w")).unwrap().id, "reporter");
+    ledger.register_principal(with_token("intruder", "s3cret")).unwrap();
+}
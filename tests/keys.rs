@@ -0,0 +1,80 @@
+use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+use ed25519_dalek::SigningKey;
+use serde_json::json;
+use ubl_core::engine::{ExecOptions, Kernel, KeyMaterial};
+use ubl_core::types::*;
+
+mod common;
+use common::{key_entry, positive_chip};
+
+#[test]
+fn old_proofs_verify_with_historical_key_after_rotation() {
+    let old = KeyMaterial::from_signing_key(SigningKey::from_bytes(&[1u8; 32]));
+    let new = KeyMaterial::from_signing_key(SigningKey::from_bytes(&[2u8; 32]));
+
+    let meta = common::meta_at("2025-06-01T00:00:00Z");
+    let proof = common::sealed_proof(&positive_chip(), &json!({"amount": 5}), &meta, &old);
+    assert_eq!(proof.key_id, old.key_id);
+
+    let mut verifier = new.clone();
+    verifier.keyring.keys = vec![
+        key_entry(&old, "2025-01-01T00:00:00Z", Some("2025-07-01T00:00:00Z")),
+        key_entry(&new, "2025-07-01T00:00:00Z", None),
+    ];
+    assert!(Kernel::verify_proof(&proof, &positive_chip(), &verifier, &ExecOptions::default()).valid);
+
+    // signed outside the old key's validity window
+    let late = common::meta_at("2025-08-01T00:00:00Z");
+    let late_proof = common::sealed_proof(&positive_chip(), &json!({"amount": 5}), &late, &old);
+    assert!(!Kernel::verify_proof(&late_proof, &positive_chip(), &verifier, &ExecOptions::default()).valid);
+
+    // revoked keys never verify
+    verifier.keyring.revoked.push(Revocation { key_id: old.key_id.clone().unwrap(), revoked_at: "2025-09-01T00:00:00Z".into(), reason: "compromised".into() });
+    assert!(!Kernel::verify_proof(&proof, &positive_chip(), &verifier, &ExecOptions::default()).valid);
+}
+
+#[test]
+fn effect_records_carry_key_id() {
+    let keys = KeyMaterial::from_signing_key(SigningKey::from_bytes(&[3u8; 32]));
+    let mut record = common::record(1, None);
+    Kernel::sign_record(&mut record, &keys);
+    assert_eq!(record.record_key_id, keys.key_id);
+    assert!(Kernel::verify_record(&record, &keys));
+
+    record.resulting_version = 2;
+    assert!(!Kernel::verify_record(&record, &keys));
+}
+
+#[test]
+fn signature_policy_controls_unsigned_proofs() {
+    let keys = KeyMaterial::from_signing_key(SigningKey::from_bytes(&[4u8; 32]));
+    let meta = common::meta();
+    let mut stripped = common::sealed_proof(&positive_chip(), &json!({"amount": 5}), &meta, &keys);
+    stripped.signature = None;
+    stripped.key_id = None;
+
+    let optional = Kernel::verify_proof(&stripped, &positive_chip(), &keys, &ExecOptions { signature_policy: SignaturePolicy::Optional, ..Default::default() });
+    assert!(optional.valid);
+    assert_eq!(optional.signature, CheckStatus::Skipped);
+
+    let required = Kernel::verify_proof(&stripped, &positive_chip(), &keys, &ExecOptions { signature_policy: SignaturePolicy::Required, ..Default::default() });
+    assert!(!required.valid);
+    assert_eq!(required.signature, CheckStatus::Failed);
+    assert_eq!((required.chip_hash, required.proof_hash, required.replay), (CheckStatus::Passed, CheckStatus::Passed, CheckStatus::Passed));
+
+    let mut bad_sig = common::sealed_proof(&positive_chip(), &json!({"amount": 5}), &meta, &keys);
+    bad_sig.signature = Some(B64.encode([0u8; 64]));
+    let ignored = Kernel::verify_proof(&bad_sig, &positive_chip(), &keys, &ExecOptions { signature_policy: SignaturePolicy::Ignored, ..Default::default() });
+    assert!(ignored.valid);
+    assert_eq!(ignored.signature, CheckStatus::Skipped);
+}
+
+#[test]
+fn requests_cannot_loosen_the_signature_policy() {
+    use SignaturePolicy::*;
+    assert_eq!(Ignored.stricter(Required), Required);
+    assert_eq!(Optional.stricter(Required), Required);
+    assert_eq!(Required.stricter(Optional), Required);
+    assert_eq!(Ignored.stricter(Optional), Optional);
+    assert_eq!(Optional.stricter(Ignored), Optional);
+}
@@ -0,0 +1,57 @@
+//! Points `UBL_KEYSTORE_PATH` at temp files, so it runs in its own test binary.
+use ed25519_dalek::SigningKey;
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use ubl_core::engine::FuelMeter;
+use ubl_core::keystore;
+use ubl_core::ledger::{Ledger, Origin};
+
+mod common;
+
+static ENV: Mutex<()> = Mutex::new(());
+
+/// Holds the environment for one test and points the keystore at `name`.
+fn keystore_at(name: &str) -> (MutexGuard<'static, ()>, PathBuf) {
+    let guard = ENV.lock().unwrap_or_else(|e| e.into_inner());
+    let path = std::env::temp_dir().join(format!("{}_{}.json", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    std::env::set_var("UBL_KEYSTORE_PATH", &path);
+    (guard, path)
+}
+
+#[test]
+fn a_corrupt_keystore_is_never_overwritten() {
+    let (_env, path) = keystore_at("ubl_keys_corrupt");
+    assert_eq!(keystore::path(), path);
+
+    let sk = SigningKey::from_bytes(&[9; 32]);
+    keystore::store("k-1", &sk).unwrap();
+    assert_eq!(keystore::load("k-1").unwrap().unwrap().to_bytes(), sk.to_bytes());
+    assert!(keystore::load("k-2").unwrap().is_none());
+
+    std::fs::write(&path, "{ truncated").unwrap();
+    assert!(keystore::load("k-1").is_err());
+    assert!(keystore::store("k-2", &sk).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "{ truncated");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn proofs_sealed_before_a_rotation_are_not_recorded() {
+    let (_env, path) = keystore_at("ubl_keys_rotation");
+    let ledger = Ledger::in_memory();
+    let meta = common::meta();
+    let stale = ledger.key_material();
+    let proof = common::sealed_proof(&common::positive_chip(), &json!({ "amount": 1 }), &meta, &stale);
+    let fuel = FuelMeter::unlimited();
+
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        ledger.rotate_key(&meta).await.unwrap();
+        let err = ledger.apply_transaction("p", "i", None, &proof, &[], &meta, &stale, Origin::default(), &fuel).await.unwrap_err();
+        assert!(err.to_string().contains("signing_key_rotated"));
+        let current = ledger.key_material();
+        assert!(ledger.apply_transaction("p", "i", None, &proof, &[], &meta, &current, Origin::default(), &fuel).await.is_ok());
+    });
+    std::fs::remove_file(&path).unwrap();
+}
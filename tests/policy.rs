@@ -0,0 +1,111 @@
+use serde_json::Value;
+use ubl_core::engine::Kernel;
+use ubl_core::ledger::Ledger;
+use ubl_core::policy;
+use ubl_core::types::*;
+
+const SOURCE: &str = r#"
+# transfers need funds and two distinct parties
+chip transfer_guard
+  description "Sender can pay"
+  compose ALL
+  gate sufficient_funds: sender.balance >= amount
+  gate distinct "no self transfers": not (from_id == to_id)
+  gate known: exists recipient.id and (recipient.status ?? "active") in ["active",
+      "pending"]
+
+program transfer
+  input amount: number required
+  input invoice: object envelope invoice
+  context sender: ledger "wallets.{from_id}"
+  context fee: computed = round(amount, 2)
+  evaluate "CHIP:transfer_guard"
+  on_allow decrement "wallets.{from_id}.balance" by amount
+  on_deny emit "transfer_denied" {"reason": "policy"}
+"#;
+
+#[test]
+fn parses_chips_and_programs_with_ast_hashes() {
+    let doc = policy::parse(SOURCE).unwrap();
+    let chip = &doc.chips[0];
+    assert_eq!(chip.gates.len(), 3);
+    assert_eq!(chip.gates[1].description, "no self transfers");
+    assert_eq!(chip.hash, Kernel::compute_chip_hash(chip));
+    assert!(matches!(&chip.gates[0].expr, Expr::Compare { op: CompareOp::Ge, .. }));
+
+    let program = &doc.programs[0];
+    assert_eq!(program.evaluate, "CHIP:transfer_guard");
+    assert!(matches!(program.inputs[1].envelope, Some(EnvelopeSpec { content_type: ContentType::Invoice, .. })));
+    assert_eq!(program.hash, Kernel::compute_program_hash(program));
+
+    // the hash is over the AST: layout and comments do not matter
+    let compact = "chip transfer_guard\n description \"Sender can pay\"\n gate sufficient_funds: sender.balance>=amount\n\
+        gate distinct \"no self transfers\": not(from_id==to_id) # same gate\n\
+        gate known: exists recipient.id and (recipient.status??\"active\") in [\"active\",\"pending\"]\n";
+    assert_eq!(policy::parse(compact).unwrap().chips[0].hash, chip.hash);
+}
+
+#[test]
+fn printed_policies_round_trip_to_the_same_hash() {
+    let mut programs = vec![];
+    for pack in ["trust", "financial", "entities", "workspaces", "reputation", "payments", "agreements"] {
+        let raw = std::fs::read_to_string(format!("stdlib/program_packs/{}.json", pack)).unwrap();
+        let v: Value = serde_json::from_str(&raw).unwrap();
+        programs.extend(serde_json::from_value::<Vec<Program>>(v["programs"].clone()).unwrap());
+    }
+    for p in &programs {
+        let doc = policy::parse(&policy::print_program(p)).unwrap_or_else(|e| panic!("{}: {}", p.name, e));
+        assert_eq!(doc.programs[0].hash, Kernel::compute_program_hash(p), "{}", p.name);
+    }
+
+    let raw = std::fs::read_to_string("examples/trust/chips/trusted_transfer_chip.json").unwrap();
+    let v: Value = serde_json::from_str(&raw).unwrap();
+    let chip: Chip = serde_json::from_value(v["chip"].clone()).unwrap();
+    let printed = policy::print_chip(&chip);
+    assert_eq!(policy::parse(&printed).unwrap().chips[0].hash, Kernel::compute_chip_hash(&chip));
+
+    // awkward names, keyword paths and degenerate logic still round-trip
+    let odd: Expr = serde_json::from_value(serde_json::json!({
+        "type": "logic", "op": "or", "args": [
+            { "type": "logic", "op": "and", "args": [{ "type": "path", "path": ["in", "a-b", "0"], "fallback": -1 }] },
+            { "type": "logic", "op": "not", "args": [] },
+            { "type": "collection", "op": "all", "over": { "type": "path", "path": ["xs"] }, "var": "x",
+              "body": { "type": "collection", "op": "sum", "over": { "type": "path", "path": ["x"] } } },
+            { "type": "call", "function": "count", "args": [] },
+            { "type": "compare", "op": "==", "right": { "type": "literal", "value": 1 },
+              "left": { "type": "let", "name": "if", "value": { "type": "path", "path": ["a"] },
+                        "body": { "type": "if", "cond": { "type": "path", "path": ["if"] },
+                                  "then": { "type": "let", "name": "b", "value": { "type": "literal", "value": 2 }, "body": { "type": "path", "path": ["b"] } },
+                                  "else": { "type": "if", "cond": { "type": "literal", "value": false },
+                                            "then": { "type": "literal", "value": 3 }, "else": { "type": "literal", "value": 4 } } } } },
+            { "type": "call", "function": "x.y", "args": [{ "type": "logic", "op": "or", "args": [
+                { "type": "literal", "value": true }, { "type": "literal", "value": null }] }] }
+        ]
+    })).unwrap();
+    let reparsed = policy::parse_expr(&policy::print_expr(&odd)).unwrap();
+    assert_eq!(Kernel::jcs_hash(&reparsed), Kernel::jcs_hash(&odd));
+}
+
+#[test]
+fn syntax_errors_report_line_and_column() {
+    let err = policy::parse("chip c\n  gate g: amount >= \n").unwrap_err();
+    assert_eq!((err.line, err.col), (2, 21));
+
+    let err = policy::parse("chip c\n  gate g: a == b c\n").unwrap_err();
+    assert_eq!((err.line, err.col), (2, 18));
+    assert!(err.message.contains("'c'"));
+
+    let err = policy::parse("program p\n  evaluate \"CHIP:c\"\n  on_allow explode \"x\"\n").unwrap_err();
+    assert_eq!((err.line, err.col), (3, 12));
+}
+
+#[test]
+fn an_invalid_program_registers_nothing() {
+    let bad = format!("{}\nprogram spoof\n  context operation: input \"op\"\n  evaluate \"CHIP:transfer_guard\"\n", SOURCE);
+    let doc = policy::parse(&bad).unwrap();
+    let ledger = Ledger::in_memory();
+    let err = ledger.register_policy(doc.chips, doc.programs).unwrap_err();
+    assert!(err.to_string().contains("reserved_context_name: operation"));
+    assert!(ledger.list_chips().is_empty());
+    assert!(ledger.get_program("transfer").is_none());
+}
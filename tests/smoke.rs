@@ -1,4 +1,4 @@
-use ubl_core::engine::{Kernel, ExecMeta, KeyMaterial};
+use ubl_core::engine::{Kernel, ExecMeta, ExecOptions, KeyMaterial};
 use ubl_core::types::*;
 use serde_json::json;
 
@@ -14,9 +14,11 @@ fn barrier_drops_unknown_fields() {
     let req = BarrierReq {
         content_type: ContentType::Invoice,
         payload: json!({"vendor_id":"v","amount":1,"currency":"USD","date":"2025-01-01","extra":"x"}),
+        raw: None,
         signature: None,
+        signer_id: None,
     };
-    let out = ubl_core::trust_barrier::process(&req).unwrap();
+    let out = ubl_core::trust_barrier::process(&req, &BarrierRegistry::default()).unwrap();
     assert!(out.fields.get("extra").is_none());
     assert_eq!(out.fields.get("vendor_id").unwrap(), "v");
 }
@@ -37,6 +39,8 @@ fn proof_hash_recomputes() {
             },
         }],
         composition: Composition::Shorthand("ALL".into()),
+        strict: false,
+        fuel: None,
         hash: "".into(),
     };
     let mut chip2 = chip.clone();
@@ -44,8 +48,9 @@ fn proof_hash_recomputes() {
 
     let meta = ExecMeta { tx_id: "t".into(), execution_time: chrono::Utc::now() };
     let ctx = json!({"amount": 1});
-    let proof = Kernel::execute_chip_signed(&chip2, &ctx, &meta, &KeyMaterial { signing: None, verifying: None });
+    let mut proof = Kernel::execute_chip(&chip2, &ctx, &meta, &ExecOptions::default());
+    Kernel::seal_proof(&mut proof, &KeyMaterial::default());
 
-    let ok = Kernel::verify_proof(&proof, &chip2, &KeyMaterial { signing: None, verifying: None });
-    assert!(ok);
+    let report = Kernel::verify_proof(&proof, &chip2, &KeyMaterial::default(), &ExecOptions::default());
+    assert!(report.valid);
 }
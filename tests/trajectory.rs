@@ -0,0 +1,43 @@
+use chrono::{DateTime, Utc};
+use ubl_core::trajectory;
+use ubl_core::types::*;
+
+mod common;
+
+fn record(version: u64, day: u32, activity: Option<Activity>) -> EffectRecord {
+    EffectRecord { timestamp: format!("2025-06-{:02}T10:00:00Z", day), activity, ..common::record(version, None) }
+}
+
+fn act(principal: &str, program: &str, allowed: bool, counterparty: Option<&str>) -> Option<Activity> {
+    Some(Activity { principal: principal.into(), program: program.into(), allowed, counterparty: counterparty.map(|c| c.into()), amount: None })
+}
+
+#[test]
+fn trajectory_aggregates_history_per_principal() {
+    let history = vec![
+        record(1, 1, act("alice", "transfer", true, Some("bob"))),
+        record(2, 2, act("bob", "transfer", true, Some("alice"))),
+        record(3, 3, None), // kernel record (e.g. key rotation)
+        record(4, 5, act("alice", "transfer", false, Some("carol"))),
+        record(5, 6, act("alice", "refund", true, Some("bob"))),
+    ];
+    let idx = trajectory::index(&history);
+    let at: DateTime<Utc> = "2025-06-11T10:00:00Z".parse().unwrap();
+
+    let alice = trajectory::as_of(&idx, "alice", 5, at);
+    assert_eq!((alice.operations, alice.allowed, alice.denied), (3, 2, 1));
+    assert_eq!(alice.programs["transfer"], ProgramCounts { allowed: 1, denied: 1 });
+    assert_eq!(alice.first_seen.as_deref(), Some("2025-06-01T10:00:00Z"));
+    assert_eq!(alice.age_seconds, 10 * 86_400);
+    assert_eq!(alice.distinct_counterparties, 2);
+    assert!((alice.success_rate - 2.0 / 3.0).abs() < 1e-12);
+
+    // unseen principals get an empty trajectory at the requested version
+    let nobody = trajectory::as_of(&idx, "mallory", 5, at);
+    assert_eq!((nobody.operations, nobody.as_of_version, nobody.age_seconds), (0, 5, 0));
+
+    // folding incrementally yields the same index as rebuilding it
+    let mut inc = trajectory::index(&history[..2]);
+    for r in &history[2..] { trajectory::record(&mut inc, r); }
+    assert_eq!(inc, idx);
+}
@@ -0,0 +1,101 @@
+use serde_json::json;
+use ubl_core::engine::{ExecOptions, Kernel, KeyMaterial};
+use ubl_core::types::*;
+
+mod common;
+use common::gate;
+
+fn chip() -> Chip {
+    common::chip("transfer", vec![gate("has_balance", "balance", CompareOp::Ge, 100), gate("kyc_level", "kyc", CompareOp::Ge, 2)])
+}
+
+#[test]
+fn report_pinpoints_first_divergent_gate() {
+    let meta = common::meta();
+    let mut forged = Kernel::execute_chip(&chip(), &json!({"balance": 50, "kyc": 3}), &meta, &ExecOptions::default());
+    assert_eq!(forged.final_result, 0);
+
+    // flip the failing gate and the outcome, then re-hash (unsigned forgery)
+    forged.gates[0].result = true;
+    forged.failed_gates.clear();
+    forged.final_result = 1;
+    Kernel::seal_proof(&mut forged, &KeyMaterial::default());
+
+    let report = Kernel::verify_proof(&forged, &chip(), &KeyMaterial::default(), &ExecOptions::default());
+    assert!(!report.valid);
+    assert_eq!(report.proof_hash, CheckStatus::Passed);
+    assert_eq!(report.replay, CheckStatus::Failed);
+    assert_eq!(report.replayed_final_result, 0);
+
+    let d = report.divergence.unwrap();
+    assert_eq!(d.stage, VerifyStage::Gate);
+    assert_eq!(d.gate.as_deref(), Some("has_balance"));
+
+    assert!(!report.gates[0].matches);
+    assert_eq!(report.gates[0].replayed.as_ref().unwrap().values.left, Some(json!(50)));
+    assert!(report.gates[1].matches);
+}
+
+#[test]
+fn traced_proofs_explain_nested_gates_and_replay() {
+    let meta = common::meta();
+    let mut chip = chip();
+    chip.gates.push(Gate {
+        id: "eligible".into(),
+        description: "".into(),
+        expr: ubl_core::policy::parse_expr("kyc >= 3 and lower(tier) == \"gold\"").unwrap(),
+    });
+    chip.hash = Kernel::compute_chip_hash(&chip);
+
+    let mut proof = Kernel::execute_chip(&chip, &json!({"balance": 150, "kyc": 2, "tier": "GOLD"}), &meta, &ExecOptions { trace: true, ..Default::default() });
+    let trace = proof.gates[2].trace.as_deref().unwrap();
+    assert_eq!((trace.node.as_str(), trace.label.as_str(), &trace.value), ("logic", "and", &json!(false)));
+    assert_eq!(trace.decided_by, Some(0));
+    let kyc = &trace.children[0];
+    assert_eq!((kyc.label.as_str(), &kyc.children[0].value), (">=", &json!(2)));
+    let lower = &trace.children[1].children[0];
+    assert_eq!((lower.node.as_str(), lower.label.as_str(), &lower.value), ("call", "lower", &json!("gold")));
+
+    Kernel::seal_proof(&mut proof, &KeyMaterial::default());
+    assert!(Kernel::verify_proof(&proof, &chip, &KeyMaterial::default(), &ExecOptions::default()).valid);
+
+    // the trace is covered by the proof hash
+    let mut edited = proof.clone();
+    edited.gates[2].trace.as_mut().unwrap().children[1].value = json!(false);
+    assert_eq!(Kernel::verify_proof(&edited, &chip, &KeyMaterial::default(), &ExecOptions::default()).proof_hash, CheckStatus::Failed);
+
+    // untraced proofs are unchanged
+    assert!(Kernel::execute_chip(&chip, &json!({"balance": 150, "kyc": 2, "tier": "GOLD"}), &meta, &ExecOptions::default()).gates.iter().all(|g| g.trace.is_none()));
+}
+
+#[test]
+fn replay_compares_error_codes_not_wording() {
+    let mut chip = ubl_core::policy::parse("chip sanctions\n  strict\n  gate g: not iban_vaild(iban)\n").unwrap().chips.remove(0);
+    chip.hash = Kernel::compute_chip_hash(&chip);
+    let mut proof = Kernel::execute_chip(&chip, &json!({ "iban": "DE00" }), &common::meta(), &ExecOptions::default());
+    assert_eq!(proof.gates[0].error.as_deref(), Some("unknown_function: iban_vaild"));
+
+    // as recorded by a build that worded the message differently
+    proof.gates[0].error = Some("unknown_function: no function named iban_vaild".into());
+    Kernel::seal_proof(&mut proof, &KeyMaterial::default());
+    assert!(Kernel::verify_proof(&proof, &chip, &KeyMaterial::default(), &ExecOptions::default()).valid);
+
+    proof.gates[0].error = Some("type_mismatch: iban_vaild".into());
+    Kernel::seal_proof(&mut proof, &KeyMaterial::default());
+    let d = Kernel::verify_proof(&proof, &chip, &KeyMaterial::default(), &ExecOptions::default()).divergence.unwrap();
+    assert_eq!((d.stage, d.detail.as_str()), (VerifyStage::Gate, "evidence_differs"));
+}
+
+#[test]
+fn lenient_diagnostics_do_not_affect_replay() {
+    let mut chip = ubl_core::policy::parse("chip limit\n  gate g: not (amount > \"limit\")\n").unwrap().chips.remove(0);
+    chip.hash = Kernel::compute_chip_hash(&chip);
+    let mut proof = Kernel::execute_chip(&chip, &json!({ "amount": 5 }), &common::meta(), &ExecOptions::default());
+    assert_eq!((proof.final_result, proof.gates[0].error.as_deref()), (1, None));
+    assert!(proof.gates[0].diagnostic.as_deref().is_some_and(|d| d.starts_with("type_mismatch")));
+
+    // issued before diagnostics were recorded
+    proof.gates[0].diagnostic = None;
+    Kernel::seal_proof(&mut proof, &KeyMaterial::default());
+    assert!(Kernel::verify_proof(&proof, &chip, &KeyMaterial::default(), &ExecOptions::default()).valid);
+}